@@ -13,7 +13,13 @@ import ApplicationServices // for AX APIs
 
 final class KeyboardBridge {
   static let shared = KeyboardBridge()
-  private init() {}
+  private init() {
+    // Route Rust logs into the app's log instead of stderr
+    superspeed_set_log_callback { level, target, message in
+      guard let target = target, let message = message else { return }
+      NSLog("[Rust %d %@] %@", level, String(cString: target), String(cString: message))
+    }
+  }
 
   // Optional: prompt for Accessibility if not trusted
   private func ensureAccessibility() {
@@ -21,25 +27,48 @@ final class KeyboardBridge {
     _ = AXIsProcessTrustedWithOptions(opts) // prompts if needed
   }
 
+  /// `method` defaults to whatever is configured for the frontmost app (clipboard paste unless set)
   @discardableResult
-  func insertGhostText(_ text: String) -> Bool {
+  func insertGhostText(_ text: String, method: SuperspeedInsertionMethod = SUPERSPEED_INSERTION_METHOD_AUTO) -> Bool {
     precondition(Thread.isMainThread, "Call on main thread for NSPasteboard safety")
 
     ensureAccessibility()
 
+    var options = SuperspeedInsertOptions(
+      method: Int32(method.rawValue),
+      markers: SuperspeedClipboardMarkers(transient: true, concealed: true)
+    )
     let ok = text.withCString { cstr in
-      superspeed_insert_ghost_text_v2(cstr)
+      superspeed_insert_ghost_text_with_options(cstr, &options)
     }
 
     if !ok {
-      // If events were blocked, user likely denied Accessibility
-      if !AXIsProcessTrusted() {
-        NSLog("Enable Accessibility: System Settings > Privacy & Security > Accessibility")
-      }
+      logLastError("insert ghost text")
     }
     return ok
   }
 
+  /// Code and message for the most recent failed Rust call on this thread
+  func lastError() -> (code: Int32, message: String)? {
+    let code = superspeed_last_error_code()
+    guard code != Int32(SUPERSPEED_ERROR_CODE_OK.rawValue) else { return nil }
+
+    var message = "Unknown error"
+    if let ptr = superspeed_last_error_message() {
+      message = String(cString: ptr)
+      superspeed_free_string(ptr)
+    }
+    return (code, message)
+  }
+
+  private func logLastError(_ action: String) {
+    guard let error = lastError() else { return }
+    NSLog("❌ Failed to \(action): \(error.message) (code \(error.code))")
+    if error.code == Int32(SUPERSPEED_ERROR_CODE_ACCESSIBILITY_DENIED.rawValue) {
+      ensureAccessibility()
+    }
+  }
+
   /// Read N characters before cursor using clipboard trick
   /// Returns the text before cursor, or nil on error
   func readCursorContext(charCount: Int) -> String? {
@@ -53,7 +82,7 @@ final class KeyboardBridge {
 
     // Check for null pointer (error)
     guard cStringPtr != nil else {
-      NSLog("❌ Failed to read cursor context (null pointer returned)")
+      logLastError("read cursor context")
       return nil
     }
 
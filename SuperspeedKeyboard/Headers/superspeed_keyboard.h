@@ -1,33 +1,295 @@
 // superspeed_keyboard.h
 // Public C API exported by the Rust cdylib/staticlib.
+// GENERATED by build.rs from the Rust sources. Do not edit by hand.
 
 #ifndef SUPERSPEED_KEYBOARD_H
 #define SUPERSPEED_KEYBOARD_H
 
 #include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#if defined(__GNUC__) || defined(__clang__)
+#define SUPERSPEED_DEPRECATED(note) __attribute__((deprecated(note)))
+#else
+#define SUPERSPEED_DEPRECATED(note)
+#endif
+
+// What happened to the saved clipboard when a ghost session ended
+typedef enum {
+  // The session never touched the clipboard (or has not ended yet)
+  SUPERSPEED_CLIPBOARD_RESTORE_NOT_NEEDED = 0,
+  // The user's clipboard was put back
+  SUPERSPEED_CLIPBOARD_RESTORE_RESTORED = 1,
+  // The user copied something while the ghost text was pending; their copy was kept
+  SUPERSPEED_CLIPBOARD_RESTORE_SKIPPED_USER_CHANGE = 2,
+} SuperspeedClipboardRestore;
+
+// Stable integer codes exposed over the C ABI
+// Never renumber these; only append.
+typedef enum {
+  SUPERSPEED_ERROR_CODE_OK = 0,
+  SUPERSPEED_ERROR_CODE_NULL_POINTER = 1,
+  SUPERSPEED_ERROR_CODE_INVALID_UTF8 = 2,
+  SUPERSPEED_ERROR_CODE_INTERIOR_NUL = 3,
+  SUPERSPEED_ERROR_CODE_EVENT_SOURCE = 4,
+  SUPERSPEED_ERROR_CODE_EVENT_POST = 5,
+  SUPERSPEED_ERROR_CODE_CLIPBOARD_VERIFY_TIMEOUT = 6,
+  SUPERSPEED_ERROR_CODE_CLIPBOARD = 7,
+  SUPERSPEED_ERROR_CODE_ACCESSIBILITY_DENIED = 8,
+  SUPERSPEED_ERROR_CODE_INVALID_STATE = 9,
+  SUPERSPEED_ERROR_CODE_UNSUPPORTED = 10,
+  SUPERSPEED_ERROR_CODE_PERMISSION_DENIED = 11,
+  SUPERSPEED_ERROR_CODE_NOT_INSERTED = 12,
+  SUPERSPEED_ERROR_CODE_INVALID_PROFILE = 13,
+} SuperspeedErrorCode;
+
+// What a hotkey does
+typedef enum {
+  // Keep the ghost text (Tab)
+  SUPERSPEED_HOTKEY_ACTION_ACCEPT = 1,
+  // Delete the ghost text (Esc)
+  SUPERSPEED_HOTKEY_ACTION_REJECT = 2,
+  // Delete the ghost text and ask the host for another suggestion
+  SUPERSPEED_HOTKEY_ACTION_REGENERATE = 3,
+} SuperspeedHotkeyAction;
+
+// Insertion method selectable over FFI
+typedef enum {
+  // Whatever is configured for the frontmost app, else the default
+  SUPERSPEED_INSERTION_METHOD_AUTO = 0,
+  SUPERSPEED_INSERTION_METHOD_CLIPBOARD = 1,
+  SUPERSPEED_INSERTION_METHOD_TYPING = 2,
+} SuperspeedInsertionMethod;
+
+// Log severity, also used as the integer level passed to the C callback
+typedef enum {
+  SUPERSPEED_LOG_LEVEL_ERROR = 1,
+  SUPERSPEED_LOG_LEVEL_WARN = 2,
+  SUPERSPEED_LOG_LEVEL_INFO = 3,
+  SUPERSPEED_LOG_LEVEL_DEBUG = 4,
+  SUPERSPEED_LOG_LEVEL_TRACE = 5,
+} SuperspeedLogLevel;
+
+// Markers asking clipboard managers to keep a write out of their history
+typedef struct {
+  // The contents are only on the clipboard briefly and will be replaced
+  bool transient;
+  // The contents are private and must not be stored or shown
+  bool concealed;
+} SuperspeedClipboardMarkers;
+
+// Options for one ghost text insertion
+typedef struct {
+  // A SuperspeedInsertionMethod; AUTO uses the method set for the frontmost app
+  int32_t method;
+  // Markers asking clipboard managers to keep the suggestion out of their history
+  SuperspeedClipboardMarkers markers;
+} SuperspeedInsertOptions;
+
+// Options for reading the cursor context
+typedef struct {
+  // Markers asking clipboard managers to keep the copied text out of their history
+  SuperspeedClipboardMarkers markers;
+} SuperspeedReadOptions;
+
+// Delays used while synthesizing input, in milliseconds
+// Layout is shared with C.
+// In profile files, fields left out of a timing table keep their DEFAULT values.
+typedef struct {
+  // After each key down/up (Shift+Enter, Backspace, Tab, Cmd+V)
+  uint32_t key_event_ms;
+  // After the Shift+Enter layout, before pasting
+  uint32_t layout_settle_ms;
+  // After each Shift+Left / Right arrow key down
+  uint32_t arrow_down_ms;
+  // After each Shift+Left / Right arrow key up
+  uint32_t arrow_up_ms;
+  // After the selection is complete, before copying
+  uint32_t selection_settle_ms;
+  // Between Cmd+C key down and key up
+  uint32_t copy_hold_ms;
+  // After Cmd+C, before reading the clipboard
+  uint32_t clipboard_read_ms;
+  // Between clipboard verification polls
+  uint32_t clipboard_poll_ms;
+  // Clipboard verification polls before giving up
+  uint32_t clipboard_poll_attempts;
+} SuperspeedTimingProfile;
+
+// Host notification after a hotkey ran: (HotkeyAction, whether the action succeeded)
+// Called on the main thread on macOS, on the listener's worker thread on Linux.
+typedef void (*SuperspeedHotkeyCallback)(int32_t action, bool succeeded);
+
+// Host log sink: (level, target, message), both strings NUL-terminated and only valid during the call
+typedef void (*SuperspeedLogCallback)(int32_t level, const char *target, const char *message);
 
 #ifdef __cplusplus
 extern "C" {
-#endif
+#endif // __cplusplus
+
+// FFI: Insert ghost text (separator keys from the app's profile + paste)
+// Shift+Enter x2 by default; two tabs instead in a terminal
+// Saves old clipboard for later restore
+// `options` picks the insertion method and clipboard markers; NULL uses the method
+// set for the frontmost app and marks the clipboard write transient and concealed.
+// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
+bool superspeed_insert_ghost_text_with_options(const char *text_ptr,
+                                               const SuperspeedInsertOptions *options);
+
+// FFI: Deprecated, use superspeed_insert_ghost_text_with_options() with NULL options
+SUPERSPEED_DEPRECATED("use superspeed_insert_ghost_text_with_options")
+bool superspeed_insert_ghost_text_v2(const char *text_ptr);
+
+// FFI: Accept ghost text (Tab key)
+// Restores old clipboard, keeps ghost text
+// In a terminal the user's request is replaced by the suggested command; Enter is never pressed.
+// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
+bool superspeed_accept_ghost_text(void);
 
-// Insert ghost text two lines below using synthetic keystrokes + clipboard.
-// Returns true on success, false on failure.
+// FFI: Reject ghost text (Esc key)
+// Deletes ghost text and restores old clipboard
 // IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
-// V2: Renamed to break stale linkage
-bool superspeed_insert_ghost_text_v2(const char *text);
+bool superspeed_reject_ghost_text(void);
+
+// FFI: Accept, reject and regenerate with hotkeys handled inside the crate
+// While ghost text is pending the bound keys (Tab and Esc by default) are swallowed
+// and run the action, so the host no longer has to watch the keyboard. Needs
+// Accessibility permission on macOS and read access to /dev/input on Linux.
+// IMPORTANT: Call from the main thread; on macOS hotkey actions are queued onto it.
+bool superspeed_start_hotkey_listener(void);
+
+// FFI: Stop handling hotkeys; keys reach applications untouched again
+// IMPORTANT: Call from the main thread.
+void superspeed_stop_hotkey_listener(void);
+
+// FFI: Bind a SuperspeedHotkeyAction to a key, e.g. "tab", "escape", "primary+shift+r"
+// Modifiers must match exactly; `hotkey` NULL unbinds the action.
+bool superspeed_set_hotkey(int32_t action, const char *hotkey);
+
+// FFI: Be told when a hotkey has run (pass NULL to stop)
+// REGENERATE has already deleted the ghost text; the host fetches and inserts a new suggestion.
+// When `succeeded` is false, superspeed_last_error_code() and _message() called from the
+// callback say why.
+void superspeed_set_hotkey_callback(void (*callback)(int32_t action, bool succeeded));
+
+// FFI: Set how long the user must stop typing before it counts as a pause
+// `threshold_ms` defaults to 3000. After an accept, keystrokes are ignored for
+// `accept_cooldown_ms` (default 3000) so continuing to type doesn't trigger again.
+void superspeed_configure_typing_pause(uint32_t threshold_ms, uint32_t accept_cooldown_ms);
+
+// FFI: Record a keystroke by the user, for hosts that watch the keyboard themselves
+// Not needed while the hotkey listener runs: it feeds every key to the pause detector.
+// Don't report modifier-only presses or keys the host posted itself.
+void superspeed_record_keystroke(void);
 
-// Read N characters before cursor using clipboard trick (Shift+Left + Cmd+C).
-// Returns a null-terminated C string, or NULL on error.
-// IMPORTANT: Caller must free the returned string with superspeed_free_string().
+// FFI: Whether the user has paused after typing; true once per pause
+// Poll from a timer; superspeed_ms_until_typing_pause() says when to look next.
+bool superspeed_poll_typing_pause(void);
+
+// FFI: Milliseconds until the current burst of typing becomes a pause, or -1 if there is none
+int64_t superspeed_ms_until_typing_pause(void);
+
+// FFI: Tell the pause detector whether the focused field is a password field
+// While it is, typing never reports a pause.
+void superspeed_set_secure_field(bool secure);
+
+// FFI: Tell the intent buffer that focus entered a text field
+// `field_empty` says the field has no text yet; then everything the user types is
+// known from the first key and superspeed_read_cursor_context() can answer without
+// touching the clipboard. Call on every focus change: clicks are not seen on Linux.
+void superspeed_reset_intent_buffer(bool field_empty);
+
+// FFI: Read cursor context (text before cursor)
+// Returns null-terminated C string, or null pointer on error
+// Caller must free the returned string with superspeed_free_string()
 // IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
 char *superspeed_read_cursor_context(size_t char_count);
 
-// Free a string allocated by Rust.
-// Safe to call with NULL pointer (no-op).
+// FFI: Read cursor context with explicit options
+// `options` sets the markers the copied text is tagged with for clipboard managers;
+// NULL marks it transient and concealed.
+// Caller must free the returned string with superspeed_free_string()
+// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
+char *superspeed_read_cursor_context_with_options(size_t char_count,
+                                                  const SuperspeedReadOptions *options);
+
+// FFI: Describe the focused application and window as JSON
+// {"app_id", "name", "pid", "title", "window_id", "wm_class"}; unknown fields are null.
+// Returns null if the platform cannot tell (Wayland, no graphical session).
+// Caller must free the returned string with superspeed_free_string()
+char *superspeed_focused_target_json(void);
+
+// FFI: The application and window the current ghost session was inserted into, as JSON
+// Same shape as superspeed_focused_target_json(); null before the first insert.
+// Caller must free the returned string with superspeed_free_string()
+char *superspeed_ghost_session_target_json(void);
+
+// FFI: Free string allocated by Rust
+// Safe to call with NULL pointer (no-op)
 void superspeed_free_string(char *ptr);
 
+// FFI: Error code of the most recent failed call on this thread (0 if it succeeded)
+int32_t superspeed_last_error_code(void);
+
+// FFI: Human-readable message for the most recent failed call on this thread
+// Returns null if the last call succeeded
+// Caller must free the returned string with superspeed_free_string()
+char *superspeed_last_error_message(void);
+
+// FFI: Set the timing profile for an application identifier (NULL = fallback for all apps)
+bool superspeed_set_timing_profile(const char *app_id, const SuperspeedTimingProfile *profile);
+
+// FFI: Copy the timing profile used for an application identifier into `out` (NULL = fallback)
+bool superspeed_get_timing_profile(const char *app_id, SuperspeedTimingProfile *out);
+
+// FFI: Measure the focused field's input latency and store a tuned profile
+// `app_id` NULL calibrates the frontmost application. The caret must be in an
+// editable field; a probe character is typed and removed again.
+// On success the tuned profile is copied into `out` (may be NULL).
+bool superspeed_calibrate_timing(const char *app_id, SuperspeedTimingProfile *out);
+
+// FFI: Set the insertion method for an application identifier (NULL = default for all apps)
+// `method` is a SuperspeedInsertionMethod; AUTO removes the app's override.
+bool superspeed_set_insertion_method(const char *app_id, int32_t method);
+
+// FFI: Resolve shortcut keys (Cmd/Ctrl+V, C, U) against a bundled keyboard layout
+// `name` is "us", "us-dvorak", "us-colemak" or "fr"; NULL follows the system layout again.
+bool superspeed_set_keyboard_layout(const char *name);
+
+// FFI: Load application profiles from a TOML file, searched before the built-in ones
+// `path` NULL drops the host file and keeps only the built-in profiles. On error
+// (unreadable file, bad TOML, invalid profile) the previous profiles stay in effect.
+bool superspeed_load_app_profiles(const char *path);
+
+// FFI: Read the application profile file again after it was edited
+bool superspeed_reload_app_profiles(void);
+
+// FFI: Insertion method that got the current ghost text into the field (AUTO if none did)
+int32_t superspeed_last_insertion_method(void);
+
+// FFI: What happened to the saved clipboard when the last ghost session ended
+// Returns a SuperspeedClipboardRestore; SKIPPED_USER_CHANGE means the user copied
+// something while the ghost text was pending and their copy was left in place.
+int32_t superspeed_last_clipboard_restore(void);
+
+// FFI: Read the field back after inserting and fall back to another method if nothing landed
+// Enabled by default; each check selects and copies the end of the inserted text.
+void superspeed_set_insert_verification(bool enabled);
+
+// FFI: Route crate logs to a host callback (pass NULL to log to stderr again)
+// The callback may be invoked from any thread that calls into the crate
+void superspeed_set_log_callback(void (*callback)(int32_t level,
+                                                  const char *target,
+                                                  const char *message));
+
+// FFI: Set the most verbose level that is emitted (1 = error ... 5 = trace)
+void superspeed_set_log_level(int32_t level);
+
+// FFI: Debug opt-in to log user text verbatim instead of as length + hash
+void superspeed_set_log_user_text(bool enabled);
+
 #ifdef __cplusplus
-}
-#endif
+}  // extern "C"
+#endif  // __cplusplus
 
-#endif // SUPERSPEED_KEYBOARD_H
+#endif  /* SUPERSPEED_KEYBOARD_H */
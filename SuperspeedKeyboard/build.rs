@@ -0,0 +1,83 @@
+// Generates Headers/superspeed_keyboard.h and Headers/module.modulemap from the
+// Rust sources, then fails the build if any exported symbol is missing from the header.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MODULEMAP: &str = r#"// module.modulemap
+// Expose the C header as a Swift module and link the Rust library.
+// GENERATED by build.rs. Do not edit by hand.
+//
+// If you built a dynamic library (recommended):
+//   lib name -> libsuperspeed_keyboard.dylib  => link "superspeed_keyboard"
+// If you built a static library:
+//   lib name -> libsuperspeed_keyboard.a      => link "superspeed_keyboard"
+
+module SuperspeedKeyboard [system] {
+  header "superspeed_keyboard.h"
+  link "superspeed_keyboard"
+  export *
+}
+"#;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let headers_dir = crate_dir.join("Headers");
+    let header_path = headers_dir.join("superspeed_keyboard.h");
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=Headers");
+
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).expect("Invalid cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate C header")
+        .write_to_file(&header_path);
+
+    write_if_changed(&headers_dir.join("module.modulemap"), MODULEMAP);
+
+    // Every #[no_mangle] export must be declared, or Swift silently links a stale symbol
+    let header = fs::read_to_string(&header_path).unwrap();
+    let declarations: Vec<&str> = header.lines().filter(|line| !line.trim_start().starts_with("//")).collect();
+    let missing: Vec<String> = exported_symbols(&crate_dir.join("src"))
+        .into_iter()
+        .filter(|symbol| !declarations.iter().any(|line| line.contains(&format!("{}(", symbol))))
+        .collect();
+    if !missing.is_empty() {
+        panic!("Exported symbols missing from {}: {}", header_path.display(), missing.join(", "));
+    }
+}
+
+/// Names of all `#[no_mangle]` functions under `dir`
+fn exported_symbols(dir: &Path) -> Vec<String> {
+    let mut symbols = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            symbols.extend(exported_symbols(&path));
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            let source = fs::read_to_string(&path).unwrap();
+            let mut lines = source.lines();
+            while let Some(line) = lines.next() {
+                if line.trim() != "#[no_mangle]" {
+                    continue;
+                }
+                let signature = lines.next().unwrap_or_default();
+                if let Some(name) = signature.split("fn ").nth(1).and_then(|rest| rest.split('(').next()) {
+                    symbols.push(name.trim().to_string());
+                }
+            }
+        }
+    }
+    symbols
+}
+
+fn write_if_changed(path: &Path, contents: &str) {
+    if fs::read_to_string(path).ok().as_deref() != Some(contents) {
+        fs::write(path, contents).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+// Time source for every delay and timestamp in the crate
+// The system clock really sleeps; the virtual clock only advances a counter,
+// so headless runs go through the same delays instantly.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/// Wall clock backed by std::time and thread::sleep
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Clock that advances only when slept on or told to
+/// Clones share the same time, so a test can keep one handle and install another
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock {
+            start: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Move time forward without anyone sleeping
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// Total virtual time since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+// Active clock; None means SystemClock
+static CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+/// Replace the active clock (None restores the system clock)
+pub fn set_clock(clock: Option<Arc<dyn Clock>>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+pub fn now() -> Instant {
+    match CLOCK.read().unwrap().as_ref() {
+        Some(clock) => clock.now(),
+        None => Instant::now(),
+    }
+}
+
+pub fn sleep(duration: Duration) {
+    // Clone out of the lock so a sleeping thread never blocks set_clock()
+    let clock = CLOCK.read().unwrap().clone();
+    match clock {
+        Some(clock) => clock.sleep(duration),
+        None => thread::sleep(duration),
+    }
+}
@@ -0,0 +1,117 @@
+// Typed errors and their stable C error codes
+use std::cell::RefCell;
+use std::fmt;
+
+/// Everything that can go wrong inside the crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuperspeedError {
+    /// A required pointer argument was NULL
+    NullPointer,
+    /// Text handed across the FFI was not valid UTF-8
+    InvalidUtf8,
+    /// Text handed back to C contained a NUL byte at this byte offset
+    InteriorNul(usize),
+    /// The platform refused to create a keyboard event source
+    EventSource,
+    /// A keyboard event could not be created or posted
+    EventPost(String),
+    /// The clipboard never reported the text we wrote
+    ClipboardVerifyTimeout,
+    /// The clipboard backend failed
+    Clipboard(String),
+    /// The process is not trusted to post events (macOS Accessibility)
+    AccessibilityDenied,
+    /// An FFI call arrived in the wrong ghost session state
+    InvalidState(String),
+    /// No backend is available for this platform or session
+    Unsupported(String),
+    /// The OS denied access to an input device or service (e.g. /dev/uinput)
+    PermissionDenied(String),
+    /// No insertion method got the ghost text into the focused field
+    NotInserted,
+    /// An application profile file could not be read or is invalid
+    InvalidProfile(String),
+}
+
+/// Stable integer codes exposed over the C ABI
+/// Never renumber these; only append.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidUtf8 = 2,
+    InteriorNul = 3,
+    EventSource = 4,
+    EventPost = 5,
+    ClipboardVerifyTimeout = 6,
+    Clipboard = 7,
+    AccessibilityDenied = 8,
+    InvalidState = 9,
+    Unsupported = 10,
+    PermissionDenied = 11,
+    NotInserted = 12,
+    InvalidProfile = 13,
+}
+
+impl SuperspeedError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SuperspeedError::NullPointer => ErrorCode::NullPointer,
+            SuperspeedError::InvalidUtf8 => ErrorCode::InvalidUtf8,
+            SuperspeedError::InteriorNul(_) => ErrorCode::InteriorNul,
+            SuperspeedError::EventSource => ErrorCode::EventSource,
+            SuperspeedError::EventPost(_) => ErrorCode::EventPost,
+            SuperspeedError::ClipboardVerifyTimeout => ErrorCode::ClipboardVerifyTimeout,
+            SuperspeedError::Clipboard(_) => ErrorCode::Clipboard,
+            SuperspeedError::AccessibilityDenied => ErrorCode::AccessibilityDenied,
+            SuperspeedError::InvalidState(_) => ErrorCode::InvalidState,
+            SuperspeedError::Unsupported(_) => ErrorCode::Unsupported,
+            SuperspeedError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            SuperspeedError::NotInserted => ErrorCode::NotInserted,
+            SuperspeedError::InvalidProfile(_) => ErrorCode::InvalidProfile,
+        }
+    }
+}
+
+impl fmt::Display for SuperspeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuperspeedError::NullPointer => write!(f, "Null pointer argument"),
+            SuperspeedError::InvalidUtf8 => write!(f, "Text is not valid UTF-8"),
+            SuperspeedError::InteriorNul(pos) => {
+                write!(f, "Text contains a NUL byte at position {} and cannot be returned as a C string", pos)
+            }
+            SuperspeedError::EventSource => write!(f, "Failed to create event source"),
+            SuperspeedError::EventPost(detail) => write!(f, "Failed to post keyboard event: {}", detail),
+            SuperspeedError::ClipboardVerifyTimeout => write!(f, "Failed to verify clipboard content was set"),
+            SuperspeedError::Clipboard(detail) => write!(f, "Clipboard error: {}", detail),
+            SuperspeedError::AccessibilityDenied => write!(
+                f,
+                "Accessibility permission denied: enable Superspeed in System Settings > Privacy & Security > Accessibility"
+            ),
+            SuperspeedError::InvalidState(detail) => write!(f, "{}", detail),
+            SuperspeedError::Unsupported(detail) => write!(f, "{}", detail),
+            SuperspeedError::PermissionDenied(detail) => write!(f, "Permission denied: {}", detail),
+            SuperspeedError::NotInserted => write!(f, "Ghost text did not appear in the focused field"),
+            SuperspeedError::InvalidProfile(detail) => write!(f, "Invalid application profile: {}", detail),
+        }
+    }
+}
+
+impl std::error::Error for SuperspeedError {}
+
+thread_local! {
+    // Error from the most recent failed FFI call on this thread
+    static LAST_ERROR: RefCell<Option<SuperspeedError>> = const { RefCell::new(None) };
+}
+
+/// Record the outcome of an FFI call for superspeed_last_error_*()
+pub(crate) fn set_last_error(error: Option<SuperspeedError>) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+/// Error from the most recent failed FFI call on this thread
+pub fn last_error() -> Option<SuperspeedError> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
@@ -0,0 +1,173 @@
+// Focused application and window introspection
+// Tells the crate (and the host, over FFI) which app and window ghost text is
+// going into: NSWorkspace + the CoreGraphics window list on macOS, EWMH
+// properties on X11. Wayland has no protocol for this, so it reports nothing.
+
+use crate::error::SuperspeedError;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// The application and window that currently has keyboard focus
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FocusedTarget {
+    /// Bundle identifier on macOS, WM_CLASS class on X11
+    pub app_id: Option<String>,
+    /// User-visible application name
+    pub name: Option<String>,
+    /// Process owning the window
+    pub pid: Option<u32>,
+    /// Title of the focused window (macOS needs Screen Recording permission for other apps' titles)
+    pub title: Option<String>,
+    /// CGWindowID on macOS, X11 window ID
+    pub window_id: Option<u64>,
+    /// WM_CLASS instance and class (X11 only)
+    pub wm_class: Vec<String>,
+}
+
+impl FocusedTarget {
+    /// Whether both targets name the same window (false if either window is unknown)
+    pub fn same_window(&self, other: &FocusedTarget) -> bool {
+        self.window_id.is_some() && self.window_id == other.window_id
+    }
+}
+
+// Target reported instead of asking the platform (tests, hosts that already know)
+static OVERRIDE: Mutex<Option<FocusedTarget>> = Mutex::new(None);
+
+/// Report `target` as focused until cleared with None
+pub fn set_override(target: Option<FocusedTarget>) {
+    *OVERRIDE.lock().unwrap() = target;
+}
+
+/// Ask the platform which application and window has focus
+pub fn query() -> Result<FocusedTarget, SuperspeedError> {
+    if let Some(target) = OVERRIDE.lock().unwrap().clone() {
+        return Ok(target);
+    }
+    query_platform()
+}
+
+/// Focused target, or an empty one when the platform can't tell
+pub fn current() -> FocusedTarget {
+    query().unwrap_or_else(|e| {
+        log_debug!("Focused target unknown: {}", e);
+        FocusedTarget::default()
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn query_platform() -> Result<FocusedTarget, SuperspeedError> {
+    macos::focused_target()
+}
+
+#[cfg(target_os = "linux")]
+fn query_platform() -> Result<FocusedTarget, SuperspeedError> {
+    use crate::keyboard::display::{self, DisplayServer};
+
+    match display::detect() {
+        DisplayServer::X11 => crate::keyboard::x11_focus::focused_target(None),
+        DisplayServer::Wayland => Err(SuperspeedError::Unsupported(
+            "Wayland does not expose the focused window to other clients".to_string(),
+        )),
+        DisplayServer::Unknown => Err(SuperspeedError::Unsupported("No graphical session".to_string())),
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn query_platform() -> Result<FocusedTarget, SuperspeedError> {
+    Err(SuperspeedError::Unsupported("Focus introspection is not available on this platform".to_string()))
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::FocusedTarget;
+    use crate::error::SuperspeedError;
+    use cocoa::base::{id, nil};
+    use cocoa::foundation::{NSArray, NSAutoreleasePool, NSString};
+    use objc::{class, msg_send, sel, sel_impl};
+    use std::ffi::CStr;
+
+    // CGWindowListOption bits (CGWindow.h)
+    const ON_SCREEN_ONLY: u32 = 1 << 0;
+    const EXCLUDE_DESKTOP_ELEMENTS: u32 = 1 << 4;
+    // kCGNullWindowID
+    const NULL_WINDOW_ID: u32 = 0;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        // Returns a CFArrayRef of CFDictionaryRef, toll-free bridged to NSArray of NSDictionary
+        fn CGWindowListCopyWindowInfo(option: u32, relative_to_window: u32) -> id;
+    }
+
+    /// Frontmost application from NSWorkspace, plus its frontmost normal window
+    pub fn focused_target() -> Result<FocusedTarget, SuperspeedError> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+            let app: id = msg_send![workspace, frontmostApplication];
+            if app == nil {
+                return Ok(FocusedTarget::default());
+            }
+
+            let pid: i32 = msg_send![app, processIdentifier];
+            let mut target = FocusedTarget {
+                app_id: optional_string(msg_send![app, bundleIdentifier]),
+                name: optional_string(msg_send![app, localizedName]),
+                pid: u32::try_from(pid).ok(),
+                ..FocusedTarget::default()
+            };
+            if let Some((window_id, title)) = front_window(pid) {
+                target.window_id = Some(window_id);
+                target.title = title;
+            }
+            Ok(target)
+        }
+    }
+
+    // Windows are listed front to back, so the first layer-0 window owned by `pid` is its key window
+    unsafe fn front_window(pid: i32) -> Option<(u64, Option<String>)> {
+        let windows = CGWindowListCopyWindowInfo(ON_SCREEN_ONLY | EXCLUDE_DESKTOP_ELEMENTS, NULL_WINDOW_ID);
+        if windows == nil {
+            return None;
+        }
+
+        let mut found = None;
+        for i in 0..windows.count() {
+            let info = windows.objectAtIndex(i);
+            let owner: id = msg_send![info, objectForKey: key("kCGWindowOwnerPID")];
+            let layer: id = msg_send![info, objectForKey: key("kCGWindowLayer")];
+            if owner == nil || layer == nil {
+                continue;
+            }
+            let owner: i32 = msg_send![owner, intValue];
+            let layer: i32 = msg_send![layer, intValue];
+            if owner != pid || layer != 0 {
+                continue;
+            }
+
+            let number: id = msg_send![info, objectForKey: key("kCGWindowNumber")];
+            let name: id = msg_send![info, objectForKey: key("kCGWindowName")];
+            if number != nil {
+                let number: u32 = msg_send![number, unsignedIntValue];
+                found = Some((number as u64, optional_string(name).filter(|title| !title.is_empty())));
+                break;
+            }
+        }
+
+        // The list is a Copy-rule CFArray: ours to release
+        let () = msg_send![windows, release];
+        found
+    }
+
+    // Window info dictionary key (the kCGWindow* constants are these same strings)
+    unsafe fn key(name: &str) -> id {
+        NSString::alloc(nil).init_str(name).autorelease()
+    }
+
+    unsafe fn optional_string(string: id) -> Option<String> {
+        if string == nil {
+            return None;
+        }
+        Some(CStr::from_ptr(NSString::UTF8String(string)).to_string_lossy().into_owned())
+    }
+}
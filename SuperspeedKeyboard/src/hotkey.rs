@@ -0,0 +1,441 @@
+// Accept / reject / regenerate hotkeys
+// While ghost text is pending, the key listener watches for the bound keys and
+// runs the matching action inside the crate. The triggering key is swallowed,
+// along with its auto-repeats and release, so Tab never also types a tab into
+// the field. Everything else, and every event the crate posts itself, passes.
+
+use crate::error::SuperspeedError;
+use crate::keyboard::listen::{self, Disposition, InputKey, KeyEvent};
+use crate::keyboard::synth::Modifiers;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// What a hotkey does
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    /// Keep the ghost text (Tab)
+    Accept = 1,
+    /// Delete the ghost text (Esc)
+    Reject = 2,
+    /// Delete the ghost text and ask the host for another suggestion
+    Regenerate = 3,
+}
+
+impl HotkeyAction {
+    pub fn from_i32(value: i32) -> Result<Self, SuperspeedError> {
+        match value {
+            1 => Ok(HotkeyAction::Accept),
+            2 => Ok(HotkeyAction::Reject),
+            3 => Ok(HotkeyAction::Regenerate),
+            _ => Err(SuperspeedError::Unsupported(format!("Unknown hotkey action {}", value))),
+        }
+    }
+}
+
+/// Host notification after a hotkey ran: (HotkeyAction, whether the action succeeded)
+/// Called on the main thread on macOS, on the listener's worker thread on Linux.
+pub type HotkeyCallback = extern "C" fn(action: i32, succeeded: bool);
+
+/// A key plus the exact modifiers that must be held with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    pub key: InputKey,
+    pub modifiers: Modifiers,
+}
+
+impl Hotkey {
+    pub const fn new(key: InputKey) -> Self {
+        Hotkey { key, modifiers: Modifiers::NONE }
+    }
+
+    /// Whether `event` is this key with exactly these modifiers (and no Option/Alt)
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        event.key == self.key && event.modifiers == self.modifiers && !event.alt
+    }
+}
+
+/// Parses "tab", "escape", "shift+tab", "primary+shift+r" (same modifier names as paste chords)
+impl FromStr for Hotkey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_ascii_lowercase();
+        let mut parts: Vec<&str> = lower.split('+').map(str::trim).collect();
+        let key_name = parts.pop().unwrap_or_default();
+        let key = InputKey::from_name(key_name).ok_or_else(|| format!("unknown key \"{}\" in hotkey \"{}\"", key_name, s))?;
+        let modifiers = parts.iter().try_fold(Modifiers::NONE, |modifiers, part| {
+            modifiers.with_named(part).ok_or_else(|| format!("unknown modifier \"{}\" in hotkey \"{}\"", part, s))
+        })?;
+        Ok(Hotkey { key, modifiers })
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [(self.modifiers.control, "ctrl"), (self.modifiers.command, "cmd"), (self.modifiers.shift, "shift")] {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        write!(f, "{:?}", self.key)
+    }
+}
+
+/// Which hotkey triggers each action (None = action unbound)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotkeyBindings {
+    pub accept: Option<Hotkey>,
+    pub reject: Option<Hotkey>,
+    pub regenerate: Option<Hotkey>,
+}
+
+impl HotkeyBindings {
+    /// Tab accepts, Esc rejects, nothing regenerates
+    pub const DEFAULT: HotkeyBindings = HotkeyBindings {
+        accept: Some(Hotkey::new(InputKey::Tab)),
+        reject: Some(Hotkey::new(InputKey::Escape)),
+        regenerate: None,
+    };
+
+    pub fn get(&self, action: HotkeyAction) -> Option<Hotkey> {
+        match action {
+            HotkeyAction::Accept => self.accept,
+            HotkeyAction::Reject => self.reject,
+            HotkeyAction::Regenerate => self.regenerate,
+        }
+    }
+
+    pub fn set(&mut self, action: HotkeyAction, hotkey: Option<Hotkey>) {
+        match action {
+            HotkeyAction::Accept => self.accept = hotkey,
+            HotkeyAction::Reject => self.reject = hotkey,
+            HotkeyAction::Regenerate => self.regenerate = hotkey,
+        }
+    }
+
+    /// Action bound to `event`, if any
+    pub fn action_for(&self, event: &KeyEvent) -> Option<HotkeyAction> {
+        [HotkeyAction::Accept, HotkeyAction::Reject, HotkeyAction::Regenerate]
+            .into_iter()
+            .find(|&action| self.get(action).is_some_and(|hotkey| hotkey.matches(event)))
+    }
+}
+
+impl Default for HotkeyBindings {
+    fn default() -> Self {
+        HotkeyBindings::DEFAULT
+    }
+}
+
+/// What to do with one key event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub disposition: Disposition,
+    /// Action to run once the event has been dealt with
+    pub action: Option<HotkeyAction>,
+}
+
+impl Decision {
+    const PASS: Decision = Decision { disposition: Disposition::Pass, action: None };
+    const SWALLOW: Decision = Decision { disposition: Disposition::Swallow, action: None };
+}
+
+/// Decides, event by event, which keys trigger an action and which are swallowed
+#[derive(Debug, Default)]
+pub struct HotkeyFilter {
+    // Keys whose press triggered an action and are still held
+    swallowed: Vec<InputKey>,
+}
+
+impl HotkeyFilter {
+    /// Decide what happens to `event`; `pending` says whether ghost text is waiting for accept/reject
+    pub fn handle(&mut self, event: &KeyEvent, bindings: &HotkeyBindings, pending: bool) -> Decision {
+        // Our own Tabs and Backspaces must reach the field
+        if event.synthetic {
+            return Decision::PASS;
+        }
+
+        let held = self.swallowed.iter().position(|&key| key == event.key);
+        if !event.down {
+            // The release of a swallowed press goes too, or the app sees a stray key up
+            return match held {
+                Some(index) => {
+                    self.swallowed.remove(index);
+                    Decision::SWALLOW
+                }
+                None => Decision::PASS,
+            };
+        }
+        if event.repeat || held.is_some() {
+            // Holding the key after it fired must not type it once the session is over
+            return if held.is_some() { Decision::SWALLOW } else { Decision::PASS };
+        }
+
+        match bindings.action_for(event).filter(|_| pending) {
+            Some(action) => {
+                self.swallowed.push(event.key);
+                Decision { disposition: Disposition::Swallow, action: Some(action) }
+            }
+            None => Decision::PASS,
+        }
+    }
+}
+
+static BINDINGS: Mutex<HotkeyBindings> = Mutex::new(HotkeyBindings::DEFAULT);
+static CALLBACK: Mutex<Option<HotkeyCallback>> = Mutex::new(None);
+
+/// Bind `action` to `hotkey`, or unbind it with None
+pub fn set_binding(action: HotkeyAction, hotkey: Option<Hotkey>) {
+    match hotkey {
+        Some(hotkey) => log_info!("Hotkey for {:?}: {}", action, hotkey),
+        None => log_info!("Hotkey for {:?} removed", action),
+    }
+    BINDINGS.lock().unwrap().set(action, hotkey);
+}
+
+pub fn bindings() -> HotkeyBindings {
+    *BINDINGS.lock().unwrap()
+}
+
+/// Tell the host about hotkeys that ran (None stops notifications)
+pub fn set_callback(callback: Option<HotkeyCallback>) {
+    *CALLBACK.lock().unwrap() = callback;
+}
+
+/// Start listening for the hotkeys
+/// `is_pending` is asked on the listener's thread for every bound key press and must
+/// not block; `perform` runs the action later, after the key has been swallowed, and
+/// says whether it succeeded (recording any error the way FFI calls do).
+pub fn start(is_pending: fn() -> bool, perform: fn(HotkeyAction) -> bool) -> Result<(), SuperspeedError> {
+    let mut filter = HotkeyFilter::default();
+    listen::start(Box::new(move |event| {
+        let bindings = bindings();
+        // Only ask about the session when the key could trigger something
+        let pending = !event.synthetic && event.down && bindings.action_for(event).is_some() && is_pending();
+        let decision = filter.handle(event, &bindings, pending);
+        if let Some(action) = decision.action {
+            log_debug!("Hotkey {:?} pressed", action);
+            listen::defer(Box::new(move || run(action, perform)));
+        }
+        decision.disposition
+    }))
+}
+
+/// Stop listening; keys reach applications untouched again
+pub fn stop() {
+    listen::stop();
+}
+
+fn run(action: HotkeyAction, perform: fn(HotkeyAction) -> bool) {
+    let succeeded = perform(action);
+    if !succeeded {
+        log_warn!("Hotkey {:?} failed", action);
+    }
+    let callback = *CALLBACK.lock().unwrap();
+    if let Some(callback) = callback {
+        callback(action as i32, succeeded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(key: InputKey, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent { modifiers, ..KeyEvent::press(key) }
+    }
+
+    fn repeat(key: InputKey) -> KeyEvent {
+        KeyEvent { repeat: true, ..KeyEvent::press(key) }
+    }
+
+    fn synthetic(event: KeyEvent) -> KeyEvent {
+        KeyEvent { synthetic: true, ..event }
+    }
+
+    // Feed `events` through one filter; `pending` is re-read before each event
+    fn run_stream(events: &[KeyEvent], bindings: &HotkeyBindings, pending: impl Fn(usize) -> bool) -> Vec<Decision> {
+        let mut filter = HotkeyFilter::default();
+        events.iter().enumerate().map(|(i, event)| filter.handle(event, bindings, pending(i))).collect()
+    }
+
+    fn dispositions(decisions: &[Decision]) -> Vec<Disposition> {
+        decisions.iter().map(|decision| decision.disposition).collect()
+    }
+
+    fn actions(decisions: &[Decision]) -> Vec<HotkeyAction> {
+        decisions.iter().filter_map(|decision| decision.action).collect()
+    }
+
+    use Disposition::{Pass, Swallow};
+
+    #[test]
+    fn tab_accepts_and_is_swallowed_with_its_release() {
+        let events = [KeyEvent::press(InputKey::Tab), KeyEvent::release(InputKey::Tab)];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |_| true);
+        assert_eq!(dispositions(&decisions), [Swallow, Swallow]);
+        assert_eq!(actions(&decisions), [HotkeyAction::Accept]);
+    }
+
+    #[test]
+    fn escape_rejects() {
+        let events = [KeyEvent::press(InputKey::Escape), KeyEvent::release(InputKey::Escape)];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |_| true);
+        assert_eq!(actions(&decisions), [HotkeyAction::Reject]);
+        assert_eq!(dispositions(&decisions), [Swallow, Swallow]);
+    }
+
+    #[test]
+    fn keys_pass_when_nothing_is_pending() {
+        let events = [
+            KeyEvent::press(InputKey::Tab),
+            KeyEvent::release(InputKey::Tab),
+            KeyEvent::press(InputKey::Escape),
+            KeyEvent::release(InputKey::Escape),
+        ];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |_| false);
+        assert_eq!(dispositions(&decisions), [Pass; 4]);
+        assert!(actions(&decisions).is_empty());
+    }
+
+    #[test]
+    fn ordinary_typing_passes_while_pending() {
+        let events = [
+            KeyEvent::press(InputKey::Char('h')),
+            KeyEvent::release(InputKey::Char('h')),
+            with(InputKey::Char('a'), Modifiers::SHIFT),
+            KeyEvent::press(InputKey::Return),
+            KeyEvent::release(InputKey::Return),
+        ];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |_| true);
+        assert_eq!(dispositions(&decisions), [Pass; 5]);
+    }
+
+    #[test]
+    fn modifiers_must_match_exactly() {
+        // Shift+Tab (reverse focus) and Cmd+Tab (app switcher) are not Tab
+        let events = [with(InputKey::Tab, Modifiers::SHIFT), with(InputKey::Tab, Modifiers::SHORTCUT)];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |_| true);
+        assert_eq!(dispositions(&decisions), [Pass, Pass]);
+
+        let mut bindings = HotkeyBindings::DEFAULT;
+        bindings.set(HotkeyAction::Accept, Some("shift+tab".parse().unwrap()));
+        let decisions = run_stream(&[KeyEvent::press(InputKey::Tab), with(InputKey::Tab, Modifiers::SHIFT)], &bindings, |_| true);
+        assert_eq!(dispositions(&decisions), [Pass, Swallow]);
+        assert_eq!(actions(&decisions), [HotkeyAction::Accept]);
+    }
+
+    #[test]
+    fn held_key_repeats_are_swallowed_after_it_fires() {
+        // The session stops being pending as soon as accept runs
+        let events = [
+            KeyEvent::press(InputKey::Tab),
+            repeat(InputKey::Tab),
+            repeat(InputKey::Tab),
+            KeyEvent::release(InputKey::Tab),
+            KeyEvent::press(InputKey::Tab),
+        ];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |i| i == 0);
+        assert_eq!(dispositions(&decisions), [Swallow, Swallow, Swallow, Swallow, Pass]);
+        assert_eq!(actions(&decisions), [HotkeyAction::Accept]);
+    }
+
+    #[test]
+    fn repeats_do_not_trigger_by_themselves() {
+        // Tab was already held when the ghost text appeared
+        let events = [repeat(InputKey::Tab), KeyEvent::release(InputKey::Tab)];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |_| true);
+        assert_eq!(dispositions(&decisions), [Pass, Pass]);
+        assert!(actions(&decisions).is_empty());
+    }
+
+    #[test]
+    fn synthetic_events_always_pass() {
+        // The crate's own delimiter tabs and backspaces while ghost text is pending
+        let events = [
+            synthetic(KeyEvent::press(InputKey::Tab)),
+            synthetic(KeyEvent::release(InputKey::Tab)),
+            synthetic(KeyEvent::press(InputKey::Escape)),
+            synthetic(KeyEvent::release(InputKey::Escape)),
+        ];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |_| true);
+        assert_eq!(dispositions(&decisions), [Pass; 4]);
+        assert!(actions(&decisions).is_empty());
+    }
+
+    #[test]
+    fn synthetic_events_interleaved_with_a_held_hotkey() {
+        // User holds Tab; accept posts its own Tab presses before the user lets go
+        let events = [
+            KeyEvent::press(InputKey::Tab),
+            synthetic(KeyEvent::press(InputKey::Tab)),
+            synthetic(KeyEvent::release(InputKey::Tab)),
+            KeyEvent::release(InputKey::Tab),
+        ];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |i| i == 0);
+        assert_eq!(dispositions(&decisions), [Swallow, Pass, Pass, Swallow]);
+    }
+
+    #[test]
+    fn release_of_a_key_pressed_before_pending_passes() {
+        // Escape went down before the ghost text was inserted
+        let events = [KeyEvent::press(InputKey::Escape), KeyEvent::release(InputKey::Escape)];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |i| i == 1);
+        assert_eq!(dispositions(&decisions), [Pass, Pass]);
+    }
+
+    #[test]
+    fn regenerate_binding_with_modifiers() {
+        let mut bindings = HotkeyBindings::DEFAULT;
+        bindings.set(HotkeyAction::Regenerate, Some("primary+shift+r".parse().unwrap()));
+        let chord = Modifiers { shift: true, ..Modifiers::SHORTCUT };
+        let events = [
+            KeyEvent::press(InputKey::Shift),
+            with(InputKey::Char('r'), chord),
+            KeyEvent { down: false, ..with(InputKey::Char('r'), chord) },
+            KeyEvent::release(InputKey::Shift),
+        ];
+        let decisions = run_stream(&events, &bindings, |_| true);
+        assert_eq!(dispositions(&decisions), [Pass, Swallow, Swallow, Pass]);
+        assert_eq!(actions(&decisions), [HotkeyAction::Regenerate]);
+    }
+
+    #[test]
+    fn unbound_actions_never_fire() {
+        let mut bindings = HotkeyBindings::DEFAULT;
+        bindings.set(HotkeyAction::Reject, None);
+        let decisions = run_stream(&[KeyEvent::press(InputKey::Escape)], &bindings, |_| true);
+        assert_eq!(dispositions(&decisions), [Pass]);
+    }
+
+    #[test]
+    fn accept_then_reject_in_one_stream() {
+        // Two suggestions in a row: Tab for the first, Esc for the second
+        let events = [
+            KeyEvent::press(InputKey::Tab),
+            KeyEvent::release(InputKey::Tab),
+            KeyEvent::press(InputKey::Char('x')),
+            KeyEvent::release(InputKey::Char('x')),
+            KeyEvent::press(InputKey::Escape),
+            KeyEvent::release(InputKey::Escape),
+        ];
+        let decisions = run_stream(&events, &HotkeyBindings::DEFAULT, |i| i == 0 || i == 4);
+        assert_eq!(dispositions(&decisions), [Swallow, Swallow, Pass, Pass, Swallow, Swallow]);
+        assert_eq!(actions(&decisions), [HotkeyAction::Accept, HotkeyAction::Reject]);
+    }
+
+    #[test]
+    fn hotkey_strings() {
+        assert_eq!("tab".parse::<Hotkey>(), Ok(Hotkey::new(InputKey::Tab)));
+        assert_eq!("Esc".parse::<Hotkey>(), Ok(Hotkey::new(InputKey::Escape)));
+        assert_eq!(
+            "ctrl+shift+R".parse::<Hotkey>(),
+            Ok(Hotkey { key: InputKey::Char('r'), modifiers: Modifiers { shift: true, control: true, command: false } })
+        );
+        assert!("shift".parse::<Hotkey>().is_err());
+        assert!("hyper+tab".parse::<Hotkey>().is_err());
+        assert!("".parse::<Hotkey>().is_err());
+    }
+}
@@ -0,0 +1,588 @@
+// Keystroke-derived intent buffer
+// Mirrors the text around the caret from the user's own key presses, so the
+// cursor context can be answered without selecting and copying. The buffer only
+// claims what it has seen: a known suffix of the text before the caret and a
+// known prefix of the text after it. Anything it can't follow (a click, a
+// selection, an unfamiliar shortcut) forgets what it knew, and typing rebuilds it.
+
+use crate::keyboard::listen::{self, Disposition, InputKey, KeyEvent};
+use crate::keyboard::synth::Modifiers;
+use crate::text;
+use std::sync::Mutex;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Whether auto-repeat events are the repeats the application receives. Under X11
+// and Wayland the server or compositor repeats on its own timer instead.
+const REPEATS_ARE_DELIVERED: bool = cfg!(target_os = "macos");
+
+/// Text around the caret as reconstructed from key presses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntentBuffer {
+    // Known end of the text before the caret
+    before: String,
+    // Known start of the text after the caret
+    after: String,
+    // `before` reaches the start of the field
+    start_known: bool,
+    // `after` reaches the end of the field
+    end_known: bool,
+    // Ghost text is on screen: keys are not mirrored until accept or reject
+    ghost: bool,
+    // The user typed while ghost text was on screen
+    ghost_disturbed: bool,
+    // A paste was seen and its clipboard hasn't been read yet
+    paste_pending: bool,
+}
+
+impl IntentBuffer {
+    pub const fn new() -> Self {
+        IntentBuffer {
+            before: String::new(),
+            after: String::new(),
+            start_known: false,
+            end_known: false,
+            ghost: false,
+            ghost_disturbed: false,
+            paste_pending: false,
+        }
+    }
+
+    /// Focus entered a field; `empty` says it had no text, so everything in it will be known
+    pub fn reset(&mut self, empty: bool) {
+        *self = IntentBuffer { start_known: empty, end_known: empty, ..IntentBuffer::new() };
+    }
+
+    /// Forget everything: the caret may be anywhere now
+    pub fn invalidate(&mut self) {
+        self.reset(false);
+    }
+
+    /// The last `count` user-perceived characters before the caret, if the buffer knows them
+    /// Shorter when the field itself is shorter and the buffer has seen all of it.
+    pub fn context(&self, count: usize) -> Option<String> {
+        if self.ghost || self.paste_pending {
+            return None;
+        }
+        let graphemes: Vec<&str> = self.before.graphemes(true).collect();
+        if graphemes.len() < count && !self.start_known {
+            return None;
+        }
+        Some(graphemes[graphemes.len().saturating_sub(count)..].concat())
+    }
+
+    /// Text typed or pasted at the caret
+    pub fn insert(&mut self, inserted: &str) {
+        self.before.push_str(&text::normalize_newlines(inserted));
+    }
+
+    /// Apply one key event; `read_paste` is called if the event pastes, and the clipboard
+    /// read it schedules is handed to pasted()
+    pub fn observe(&mut self, event: &KeyEvent, disposition: Disposition, read_paste: impl FnOnce()) {
+        // Releases change nothing; swallowed keys never reached the field; our own keys are accounted for separately
+        if !event.down || disposition == Disposition::Swallow || event.synthetic || event.key.is_modifier() {
+            return;
+        }
+        if self.ghost {
+            self.ghost_disturbed |= event.key != InputKey::Escape;
+            return;
+        }
+        if self.paste_pending {
+            // This key landed after text the buffer hasn't read yet
+            self.invalidate();
+        }
+        if event.repeat && !REPEATS_ARE_DELIVERED {
+            self.invalidate();
+            return;
+        }
+
+        let shortcut = event.modifiers.command || event.modifiers.control;
+        match event.key {
+            InputKey::Backspace if line_edit(event) => self.delete_line_back(),
+            InputKey::Backspace if word_edit(event) => self.delete_word_back(),
+            InputKey::Delete if word_edit(event) => self.delete_word_forward(),
+            InputKey::LeftArrow if word_edit(event) && !event.modifiers.shift => self.word_left(),
+            InputKey::RightArrow if word_edit(event) && !event.modifiers.shift => self.word_right(),
+            InputKey::Char('v') if event.modifiers == Modifiers::SHORTCUT && !event.alt => {
+                self.paste_pending = true;
+                read_paste();
+            }
+            // Copying leaves the field as it was
+            InputKey::Char('c') if event.modifiers == Modifiers::SHORTCUT && !event.alt => {}
+            // Undo, cut, select all, window switching, ...
+            _ if shortcut => self.invalidate(),
+            // At the edge of what the buffer knows, the deleted character was never in it
+            InputKey::Backspace => drop(pop_last_grapheme(&mut self.before)),
+            InputKey::Delete => drop(pop_first_grapheme(&mut self.after)),
+            // Shift+arrows select: typing next replaces a selection the buffer can't see
+            InputKey::LeftArrow | InputKey::RightArrow if event.modifiers.shift || event.alt => self.invalidate(),
+            InputKey::LeftArrow => self.left(),
+            InputKey::RightArrow => self.right(),
+            InputKey::Space if !event.alt => self.insert(" "),
+            // Shift+Return breaks the line everywhere; plain Return may submit and clear the field
+            InputKey::Return if event.modifiers == Modifiers::SHIFT => self.insert("\n"),
+            InputKey::Escape => {}
+            InputKey::Char(_) => match event.text {
+                Some(c) => self.before.push(c),
+                None => self.invalidate(),
+            },
+            // Tab may move focus, Up/Down lose the column, clicks move the caret
+            _ => self.invalidate(),
+        }
+    }
+
+    /// The clipboard a pending paste inserted (None if it couldn't be read)
+    /// Ignored once the buffer has been invalidated since the paste.
+    pub fn pasted(&mut self, pasted: Option<String>) {
+        if !std::mem::take(&mut self.paste_pending) {
+            return;
+        }
+        match pasted {
+            Some(pasted) => self.insert(&pasted),
+            None => self.invalidate(),
+        }
+    }
+
+    /// Ghost text is about to be inserted; the buffer answers nothing until it is resolved
+    pub fn ghost_inserted(&mut self) {
+        if self.paste_pending {
+            self.invalidate();
+        }
+        self.ghost = true;
+        self.ghost_disturbed = false;
+    }
+
+    /// Ghost text was deleted again, leaving the field as it was
+    pub fn ghost_rejected(&mut self) {
+        self.end_ghost(None);
+    }
+
+    /// Ghost text stayed: `kept` is everything that was inserted (separator and suggestion)
+    pub fn ghost_accepted(&mut self, kept: &str) {
+        self.end_ghost(Some(kept));
+    }
+
+    fn end_ghost(&mut self, kept: Option<&str>) {
+        let disturbed = self.ghost_disturbed;
+        self.ghost = false;
+        self.ghost_disturbed = false;
+        if disturbed {
+            self.invalidate();
+        } else if let Some(kept) = kept {
+            self.insert(kept);
+        }
+    }
+
+    fn left(&mut self) {
+        match pop_last_grapheme(&mut self.before) {
+            Some(grapheme) => self.after.insert_str(0, &grapheme),
+            // The caret moved onto text the buffer never saw
+            None if !self.start_known => self.forget_after(),
+            None => {}
+        }
+    }
+
+    fn right(&mut self) {
+        match pop_first_grapheme(&mut self.after) {
+            Some(grapheme) => self.before.push_str(&grapheme),
+            None if !self.end_known => self.forget_before(),
+            None => {}
+        }
+    }
+
+    fn word_left(&mut self) {
+        match word_start(&self.before, self.start_known) {
+            Some(start) => {
+                let word = self.before.split_off(start);
+                self.after.insert_str(0, &word);
+            }
+            None => self.invalidate(),
+        }
+    }
+
+    fn word_right(&mut self) {
+        match word_end(&self.after, self.end_known) {
+            Some(end) => {
+                let rest = self.after.split_off(end);
+                let word = std::mem::replace(&mut self.after, rest);
+                self.before.push_str(&word);
+            }
+            None => self.invalidate(),
+        }
+    }
+
+    fn delete_word_back(&mut self) {
+        match word_start(&self.before, self.start_known) {
+            Some(start) => self.before.truncate(start),
+            None => self.invalidate(),
+        }
+    }
+
+    fn delete_word_forward(&mut self) {
+        match word_end(&self.after, self.end_known) {
+            Some(end) => drop(self.after.drain(..end)),
+            None => self.invalidate(),
+        }
+    }
+
+    // Cmd+Backspace: back to the start of the line, or just the line break at its start
+    fn delete_line_back(&mut self) {
+        if self.before.ends_with('\n') {
+            self.before.pop();
+            return;
+        }
+        match self.before.rfind('\n') {
+            Some(newline) => self.before.truncate(newline + 1),
+            // Whatever the buffer never saw of this line is gone too
+            None => self.before.clear(),
+        }
+    }
+
+    fn forget_before(&mut self) {
+        self.before.clear();
+        self.start_known = false;
+    }
+
+    fn forget_after(&mut self) {
+        self.after.clear();
+        self.end_known = false;
+    }
+}
+
+// macOS: Option+Backspace / Option+Arrow work on words; elsewhere Ctrl does
+fn word_edit(event: &KeyEvent) -> bool {
+    if cfg!(target_os = "macos") {
+        event.alt && !event.modifiers.command && !event.modifiers.control
+    } else {
+        event.modifiers.control && !event.modifiers.command && !event.alt
+    }
+}
+
+// macOS: Cmd+Backspace deletes to the start of the line
+fn line_edit(event: &KeyEvent) -> bool {
+    cfg!(target_os = "macos") && event.modifiers.command && !event.modifiers.control && !event.alt
+}
+
+fn pop_last_grapheme(text: &mut String) -> Option<String> {
+    let (start, _) = text.grapheme_indices(true).next_back()?;
+    Some(text.split_off(start))
+}
+
+fn pop_first_grapheme(text: &mut String) -> Option<String> {
+    let len = text.graphemes(true).next()?.len();
+    Some(text.drain(..len).collect())
+}
+
+// Byte offset where the word ending `text` starts: trailing whitespace is skipped,
+// then the word runs back to the previous whitespace, or to the start of `text` if
+// that is the start of the field. None when the buffer can't tell where the caret
+// lands: the word may continue in text it never saw, or it contains punctuation,
+// which editors disagree about.
+fn word_start(text: &str, start_known: bool) -> Option<usize> {
+    let trimmed = text.trim_end();
+    let start = match trimmed.rfind(char::is_whitespace) {
+        Some(offset) => offset + trimmed[offset..].chars().next()?.len_utf8(),
+        None if start_known => 0,
+        None => return None,
+    };
+    is_plain_word(&trimmed[start..]).then_some(start)
+}
+
+// Byte offset just past the word starting `text`, mirroring word_start
+fn word_end(text: &str, end_known: bool) -> Option<usize> {
+    let trimmed = text.trim_start();
+    let skipped = text.len() - trimmed.len();
+    let end = match trimmed.find(char::is_whitespace) {
+        Some(offset) => offset,
+        None if end_known => trimmed.len(),
+        None => return None,
+    };
+    is_plain_word(&trimmed[..end]).then_some(skipped + end)
+}
+
+fn is_plain_word(word: &str) -> bool {
+    word.chars().all(char::is_alphanumeric)
+}
+
+// The buffer fed by the key listener
+static BUFFER: Mutex<IntentBuffer> = Mutex::new(IntentBuffer::new());
+
+/// Run `f` on the crate-wide buffer
+pub fn with_buffer<T>(f: impl FnOnce(&mut IntentBuffer) -> T) -> T {
+    f(&mut BUFFER.lock().unwrap())
+}
+
+/// Key listener observer
+pub fn observe(event: &KeyEvent, disposition: Disposition) {
+    let mut paste = false;
+    with_buffer(|buffer| buffer.observe(event, disposition, || paste = true));
+    if paste {
+        // The clipboard can be slow to answer, and the keyboard waits on the listener
+        listen::defer(Box::new(|| {
+            let pasted = read_clipboard();
+            with_buffer(|buffer| buffer.pasted(pasted));
+        }));
+    }
+}
+
+// What a user paste inserts
+fn read_clipboard() -> Option<String> {
+    crate::keyboard::clipboard::with_backend(|clipboard| clipboard.read_string())
+        .inspect_err(|e| log_debug!("Intent buffer cannot follow a paste: {}", e))
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(key: InputKey, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent { modifiers, ..KeyEvent::press(key) }
+    }
+
+    // Word-wise editing: Option on macOS, Ctrl elsewhere
+    fn word(key: InputKey) -> KeyEvent {
+        if cfg!(target_os = "macos") {
+            KeyEvent { alt: true, ..KeyEvent::press(key) }
+        } else {
+            with(key, Modifiers::CONTROL)
+        }
+    }
+
+    fn feed(buffer: &mut IntentBuffer, events: &[KeyEvent]) {
+        for event in events {
+            buffer.observe(event, Disposition::Pass, || panic!("unexpected paste"));
+            buffer.observe(&KeyEvent { down: false, ..*event }, Disposition::Pass, || panic!("unexpected paste"));
+        }
+    }
+
+    fn type_text(buffer: &mut IntentBuffer, text: &str) {
+        let events: Vec<KeyEvent> = text
+            .chars()
+            .map(|c| match c {
+                ' ' => KeyEvent::press(InputKey::Space),
+                '\n' => with(InputKey::Return, Modifiers::SHIFT),
+                c => KeyEvent::typed(c),
+            })
+            .collect();
+        feed(buffer, &events);
+    }
+
+    fn empty_field() -> IntentBuffer {
+        let mut buffer = IntentBuffer::new();
+        buffer.reset(true);
+        buffer
+    }
+
+    #[test]
+    fn typing_into_an_empty_field_is_fully_known() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "Hello, World");
+        assert_eq!(buffer.context(5).as_deref(), Some("World"));
+        // The field is shorter than asked for: all of it
+        assert_eq!(buffer.context(100).as_deref(), Some("Hello, World"));
+    }
+
+    #[test]
+    fn unknown_start_only_answers_what_was_typed() {
+        let mut buffer = IntentBuffer::new();
+        type_text(&mut buffer, "abc def");
+        assert_eq!(buffer.context(3).as_deref(), Some("def"));
+        assert_eq!(buffer.context(7).as_deref(), Some("abc def"));
+        assert_eq!(buffer.context(8), None);
+    }
+
+    #[test]
+    fn backspace_removes_whole_graphemes() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "cafe");
+        // e + combining acute accent is one user-perceived character
+        feed(&mut buffer, &[KeyEvent::typed('\u{301}'), KeyEvent::press(InputKey::Backspace)]);
+        assert_eq!(buffer.context(10).as_deref(), Some("caf"));
+        feed(&mut buffer, &[KeyEvent::press(InputKey::Backspace); 5]);
+        assert_eq!(buffer.context(10).as_deref(), Some(""));
+        type_text(&mut buffer, "ok");
+        assert_eq!(buffer.context(10).as_deref(), Some("ok"));
+    }
+
+    #[test]
+    fn arrows_move_the_caret_within_known_text() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "helo");
+        feed(&mut buffer, &[KeyEvent::press(InputKey::LeftArrow)]);
+        type_text(&mut buffer, "l");
+        assert_eq!(buffer.context(10).as_deref(), Some("hell"));
+        feed(&mut buffer, &[KeyEvent::press(InputKey::RightArrow)]);
+        assert_eq!(buffer.context(10).as_deref(), Some("hello"));
+        // Past the end of a known field nothing moves
+        feed(&mut buffer, &[KeyEvent::press(InputKey::RightArrow)]);
+        assert_eq!(buffer.context(10).as_deref(), Some("hello"));
+        // Forward delete in the middle
+        feed(&mut buffer, &[KeyEvent::press(InputKey::LeftArrow), KeyEvent::press(InputKey::LeftArrow)]);
+        feed(&mut buffer, &[KeyEvent::press(InputKey::Delete), KeyEvent::press(InputKey::RightArrow)]);
+        assert_eq!(buffer.context(10).as_deref(), Some("helo"));
+    }
+
+    #[test]
+    fn moving_into_unseen_text_forgets_the_other_side() {
+        let mut buffer = IntentBuffer::new();
+        type_text(&mut buffer, "ab");
+        // Caret walks left past what was typed, then back right past the start of what it remembers
+        feed(&mut buffer, &[KeyEvent::press(InputKey::LeftArrow); 3]);
+        assert_eq!(buffer.context(1), None);
+        feed(&mut buffer, &[KeyEvent::press(InputKey::RightArrow); 2]);
+        assert_eq!(buffer.context(1), None);
+        type_text(&mut buffer, "x");
+        assert_eq!(buffer.context(1).as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn word_deletion_and_movement() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "one two three  ");
+        feed(&mut buffer, &[word(InputKey::Backspace)]);
+        assert_eq!(buffer.context(100).as_deref(), Some("one two "));
+        feed(&mut buffer, &[word(InputKey::LeftArrow), word(InputKey::LeftArrow)]);
+        assert_eq!(buffer.context(100).as_deref(), Some(""));
+        feed(&mut buffer, &[word(InputKey::RightArrow)]);
+        assert_eq!(buffer.context(100).as_deref(), Some("one"));
+        // " two" goes; the trailing space is the end of the field
+        feed(&mut buffer, &[word(InputKey::Delete)]);
+        feed(&mut buffer, &[word(InputKey::RightArrow)]);
+        assert_eq!(buffer.context(100).as_deref(), Some("one "));
+    }
+
+    #[test]
+    fn word_deletion_it_cannot_follow_invalidates() {
+        // The word may continue into text before the first key the buffer saw
+        let mut buffer = IntentBuffer::new();
+        type_text(&mut buffer, "word");
+        feed(&mut buffer, &[word(InputKey::Backspace)]);
+        assert_eq!(buffer.context(1), None);
+
+        // Editors disagree on where words with punctuation break
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "see example.com");
+        feed(&mut buffer, &[word(InputKey::Backspace)]);
+        assert_eq!(buffer.context(1), None);
+    }
+
+    #[test]
+    fn paste_inserts_the_clipboard() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "see ");
+        let paste = with(InputKey::Char('v'), Modifiers::SHORTCUT);
+        let mut requested = false;
+        buffer.observe(&paste, Disposition::Pass, || requested = true);
+        assert!(requested);
+        // Nothing is claimed until the clipboard has been read
+        assert_eq!(buffer.context(1), None);
+        buffer.pasted(Some("line one\r\nline two".to_string()));
+        assert_eq!(buffer.context(100).as_deref(), Some("see line one\nline two"));
+
+        // An unreadable clipboard leaves the buffer not knowing what went in
+        buffer.observe(&paste, Disposition::Pass, || {});
+        buffer.pasted(None);
+        assert_eq!(buffer.context(1), None);
+    }
+
+    #[test]
+    fn keys_typed_before_the_paste_is_read_forget_what_came_before() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "see ");
+        buffer.observe(&with(InputKey::Char('v'), Modifiers::SHORTCUT), Disposition::Pass, || {});
+        type_text(&mut buffer, "ab");
+        // The late clipboard read can't be placed before "ab" any more
+        buffer.pasted(Some("pasted".to_string()));
+        assert_eq!(buffer.context(2).as_deref(), Some("ab"));
+        assert_eq!(buffer.context(3), None);
+    }
+
+    #[test]
+    fn copy_keeps_the_buffer() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "keep");
+        feed(&mut buffer, &[with(InputKey::Char('c'), Modifiers::SHORTCUT)]);
+        assert_eq!(buffer.context(100).as_deref(), Some("keep"));
+    }
+
+    #[test]
+    fn edits_it_cannot_follow_invalidate() {
+        let untracked = [
+            KeyEvent::press(InputKey::Pointer),
+            with(InputKey::LeftArrow, Modifiers::SHIFT),
+            KeyEvent::press(InputKey::UpArrow),
+            KeyEvent::press(InputKey::Tab),
+            KeyEvent::press(InputKey::Return),
+            with(InputKey::Char('z'), Modifiers::SHORTCUT),
+            with(InputKey::Char('a'), Modifiers::SHORTCUT),
+            KeyEvent::press(InputKey::Char('q')),
+            KeyEvent::press(InputKey::Other(183)),
+        ];
+        for event in untracked {
+            let mut buffer = empty_field();
+            type_text(&mut buffer, "text");
+            feed(&mut buffer, &[event]);
+            assert_eq!(buffer.context(1), None, "{:?}", event);
+            // Typing afterwards is known again
+            type_text(&mut buffer, "more");
+            assert_eq!(buffer.context(4).as_deref(), Some("more"));
+            assert_eq!(buffer.context(5), None);
+        }
+    }
+
+    #[test]
+    fn shift_return_breaks_the_line() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "first\nsecond");
+        assert_eq!(buffer.context(100).as_deref(), Some("first\nsecond"));
+    }
+
+    #[test]
+    fn synthetic_swallowed_and_modifier_keys_are_ignored() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "abc");
+        buffer.observe(&KeyEvent { synthetic: true, ..KeyEvent::press(InputKey::Backspace) }, Disposition::Pass, || {});
+        buffer.observe(&KeyEvent::press(InputKey::Tab), Disposition::Swallow, || {});
+        feed(&mut buffer, &[KeyEvent::press(InputKey::Shift), KeyEvent::press(InputKey::Command)]);
+        assert_eq!(buffer.context(100).as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn repeats_follow_only_where_they_are_delivered() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "abc");
+        feed(&mut buffer, &[KeyEvent { repeat: true, ..KeyEvent::press(InputKey::Backspace) }]);
+        let expected = if REPEATS_ARE_DELIVERED { Some("ab") } else { None };
+        assert_eq!(buffer.context(100).as_deref(), expected);
+    }
+
+    #[test]
+    fn accepted_ghost_text_joins_the_buffer() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "question");
+        buffer.ghost_inserted();
+        assert_eq!(buffer.context(1), None);
+        buffer.ghost_accepted("\n\nanswer");
+        assert_eq!(buffer.context(100).as_deref(), Some("question\n\nanswer"));
+    }
+
+    #[test]
+    fn rejected_ghost_text_leaves_the_buffer() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "question");
+        buffer.ghost_inserted();
+        buffer.ghost_rejected();
+        assert_eq!(buffer.context(100).as_deref(), Some("question"));
+    }
+
+    #[test]
+    fn typing_over_ghost_text_invalidates() {
+        let mut buffer = empty_field();
+        type_text(&mut buffer, "question");
+        buffer.ghost_inserted();
+        type_text(&mut buffer, "x");
+        buffer.ghost_rejected();
+        assert_eq!(buffer.context(1), None);
+    }
+}
@@ -0,0 +1,419 @@
+// Clipboard backends
+// All clipboard access goes through a Clipboard so the save/verify/restore
+// logic runs the same against NSPasteboard, X11 selections, Wayland
+// data-control and the in-memory pasteboard.
+
+use crate::error::SuperspeedError;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Flavor plain text is stored under on this platform
+#[cfg(target_os = "macos")]
+pub const TEXT_FLAVOR: &str = "public.utf8-plain-text";
+#[cfg(not(target_os = "macos"))]
+pub const TEXT_FLAVOR: &str = "text/plain;charset=utf-8";
+
+// Flavor names holding UTF-8 text across NSPasteboard, X11 and Wayland
+const TEXT_FLAVORS: [&str; 4] = ["public.utf8-plain-text", "text/plain;charset=utf-8", "UTF8_STRING", "text/plain"];
+
+// Flavors marked text is written under, so every kind of pasting client finds it
+#[cfg(target_os = "macos")]
+const WRITE_FLAVORS: [&str; 1] = ["public.utf8-plain-text"];
+#[cfg(not(target_os = "macos"))]
+const WRITE_FLAVORS: [&str; 3] = ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain"];
+
+/// Markers asking clipboard managers to keep a write out of their history
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClipboardMarkers {
+    /// The contents are only on the clipboard briefly and will be replaced
+    pub transient: bool,
+    /// The contents are private and must not be stored or shown
+    pub concealed: bool,
+}
+
+/// cbindgen:ignore
+impl ClipboardMarkers {
+    pub const NONE: ClipboardMarkers = ClipboardMarkers { transient: false, concealed: false };
+    /// Ghost suggestions and field contents: both markers
+    pub const PRIVATE: ClipboardMarkers = ClipboardMarkers { transient: true, concealed: true };
+
+    pub fn is_empty(&self) -> bool {
+        !self.transient && !self.concealed
+    }
+
+    /// Marker flavors for this platform
+    /// macOS uses the nspasteboard.org types (Maccy, Paste, Alfred, Raycast).
+    #[cfg(target_os = "macos")]
+    fn flavors(self) -> Vec<(String, Vec<u8>)> {
+        let mut flavors = Vec::new();
+        if self.transient {
+            flavors.push(("org.nspasteboard.TransientType".to_string(), Vec::new()));
+        }
+        if self.concealed {
+            flavors.push(("org.nspasteboard.ConcealedType".to_string(), Vec::new()));
+        }
+        flavors
+    }
+
+    /// Marker flavors for this platform
+    /// Linux has a single convention: Klipper honors the KDE password manager hint, and
+    /// wl-paste --watch reports it to cliphist and clipman as CLIPBOARD_STATE=sensitive.
+    #[cfg(not(target_os = "macos"))]
+    fn flavors(self) -> Vec<(String, Vec<u8>)> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        vec![("x-kde-passwordManagerHint".to_string(), b"secret".to_vec())]
+    }
+}
+
+impl Default for ClipboardMarkers {
+    fn default() -> Self {
+        ClipboardMarkers::PRIVATE
+    }
+}
+
+/// One clipboard item with every representation (flavor) it was offered in
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClipboardItem {
+    /// (flavor, bytes) in the order the owner offered them
+    pub flavors: Vec<(String, Vec<u8>)>,
+}
+
+impl ClipboardItem {
+    /// Item holding only plain text
+    pub fn text(text: &str) -> Self {
+        ClipboardItem { flavors: vec![(TEXT_FLAVOR.to_string(), text.as_bytes().to_vec())] }
+    }
+
+    /// Item holding `text` plus the flavors that mark it for clipboard managers
+    pub fn marked_text(text: &str, markers: ClipboardMarkers) -> Self {
+        let mut flavors: Vec<(String, Vec<u8>)> =
+            WRITE_FLAVORS.iter().map(|flavor| (flavor.to_string(), text.as_bytes().to_vec())).collect();
+        flavors.extend(markers.flavors());
+        ClipboardItem { flavors }
+    }
+
+    /// Bytes stored for `flavor`
+    pub fn get(&self, flavor: &str) -> Option<&[u8]> {
+        self.flavors.iter().find(|(name, _)| name == flavor).map(|(_, data)| data.as_slice())
+    }
+
+    /// The item's plain text, if it has a UTF-8 text flavor
+    pub fn to_text(&self) -> Option<String> {
+        TEXT_FLAVORS
+            .iter()
+            .find_map(|flavor| self.get(flavor))
+            .map(|data| String::from_utf8_lossy(data).into_owned())
+    }
+}
+
+/// Everything on the clipboard at one moment, restorable byte-for-byte
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClipboardSnapshot {
+    pub items: Vec<ClipboardItem>,
+}
+
+impl ClipboardSnapshot {
+    /// Snapshot of a clipboard holding only `text`
+    pub fn text(text: &str) -> Self {
+        ClipboardSnapshot { items: vec![ClipboardItem::text(text)] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.iter().all(|item| item.flavors.is_empty())
+    }
+
+    /// Plain text of the first item that has any
+    pub fn to_text(&self) -> Option<String> {
+        self.items.iter().find_map(ClipboardItem::to_text)
+    }
+}
+
+// Describes the shape only, never the contents, so snapshots are safe to log
+impl fmt::Display for ClipboardSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: usize = self.items.iter().flat_map(|item| &item.flavors).map(|(_, data)| data.len()).sum();
+        let flavors: Vec<&str> = self.items.iter().flat_map(|item| &item.flavors).map(|(name, _)| name.as_str()).collect();
+        write!(f, "{} item(s), {} bytes [{}]", self.items.len(), bytes, flavors.join(", "))
+    }
+}
+
+/// A system clipboard
+pub trait Clipboard: Send {
+    /// Short backend name for logs
+    fn name(&self) -> &'static str;
+
+    /// Current plain-text contents, if any
+    fn read_string(&mut self) -> Result<Option<String>, SuperspeedError>;
+
+    /// Replace the contents with `text`
+    fn write_string(&mut self, text: &str) -> Result<(), SuperspeedError>;
+
+    /// Replace the contents with `text`, tagged with `markers` for clipboard managers
+    /// Backends that only understand text write it unmarked.
+    fn write_marked(&mut self, text: &str, markers: ClipboardMarkers) -> Result<(), SuperspeedError> {
+        if markers.is_empty() {
+            return self.write_string(text);
+        }
+        self.restore(&ClipboardSnapshot { items: vec![ClipboardItem::marked_text(text, markers)] })
+    }
+
+    /// Remove all contents
+    fn clear(&mut self) -> Result<(), SuperspeedError>;
+
+    /// Counter that increases every time the contents change
+    fn change_count(&mut self) -> Result<i64, SuperspeedError>;
+
+    /// Every item and flavor currently on the clipboard
+    /// Backends that only understand text capture just the text.
+    fn snapshot(&mut self) -> Result<ClipboardSnapshot, SuperspeedError> {
+        Ok(self.read_string()?.map_or_else(ClipboardSnapshot::default, |text| ClipboardSnapshot::text(&text)))
+    }
+
+    /// Replace the contents with exactly what `snapshot` captured
+    fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), SuperspeedError> {
+        self.clear()?;
+        match snapshot.to_text() {
+            Some(text) => self.write_string(&text),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Which selection a Linux clipboard backend serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Selection {
+    /// The explicit copy/paste clipboard (Ctrl+C / Ctrl+V)
+    #[default]
+    Clipboard,
+    /// The select-to-copy, middle-click-to-paste selection
+    Primary,
+}
+
+/// How MemoryClipboard handles writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteBehavior {
+    /// Writes are visible immediately
+    #[default]
+    Immediate,
+    /// Writes only become visible after this many reads
+    Delayed(usize),
+    /// Writes are accepted but silently dropped
+    Dropped,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    contents: ClipboardSnapshot,
+    pending: Option<(ClipboardSnapshot, usize)>,
+    change_count: i64,
+    write_behavior: WriteBehavior,
+}
+
+impl MemoryState {
+    fn set(&mut self, contents: ClipboardSnapshot) {
+        match self.write_behavior {
+            WriteBehavior::Immediate => self.commit(contents),
+            WriteBehavior::Delayed(reads) => self.pending = Some((contents, reads)),
+            WriteBehavior::Dropped => {}
+        }
+    }
+
+    fn commit(&mut self, contents: ClipboardSnapshot) {
+        self.contents = contents;
+        self.change_count += 1;
+    }
+
+    // Count a read against any delayed write and land it once it is due
+    fn tick(&mut self) {
+        if let Some((contents, reads)) = self.pending.take() {
+            if reads == 0 {
+                self.commit(contents);
+            } else {
+                self.pending = Some((contents, reads - 1));
+            }
+        }
+    }
+}
+
+/// In-memory pasteboard for tests and headless hosts
+/// Clones share the same contents, so a test can keep one handle and install another
+#[derive(Debug, Clone, Default)]
+pub struct MemoryClipboard {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start with `text` already on the clipboard
+    pub fn with_contents(text: &str) -> Self {
+        let clipboard = Self::new();
+        clipboard.state.lock().unwrap().contents = ClipboardSnapshot::text(text);
+        clipboard
+    }
+
+    /// Start with `snapshot` already on the clipboard (images, rich text, several items)
+    pub fn with_snapshot(snapshot: ClipboardSnapshot) -> Self {
+        let clipboard = Self::new();
+        clipboard.state.lock().unwrap().contents = snapshot;
+        clipboard
+    }
+
+    /// Change how subsequent writes behave
+    pub fn set_write_behavior(&self, behavior: WriteBehavior) {
+        self.state.lock().unwrap().write_behavior = behavior;
+    }
+
+    /// Plain text as currently visible to readers, without counting as a read
+    pub fn contents(&self) -> Option<String> {
+        self.state.lock().unwrap().contents.to_text()
+    }
+
+    /// Every item and flavor as currently visible to readers, without counting as a read
+    pub fn items(&self) -> ClipboardSnapshot {
+        self.state.lock().unwrap().contents.clone()
+    }
+}
+
+impl Clipboard for MemoryClipboard {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn read_string(&mut self) -> Result<Option<String>, SuperspeedError> {
+        let mut state = self.state.lock().unwrap();
+        state.tick();
+        Ok(state.contents.to_text())
+    }
+
+    fn write_string(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        self.state.lock().unwrap().set(ClipboardSnapshot::text(text));
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), SuperspeedError> {
+        self.state.lock().unwrap().set(ClipboardSnapshot::default());
+        Ok(())
+    }
+
+    fn change_count(&mut self) -> Result<i64, SuperspeedError> {
+        // Polling the count is how callers wait for a delayed write, so it counts as a read
+        let mut state = self.state.lock().unwrap();
+        state.tick();
+        Ok(state.change_count)
+    }
+
+    fn snapshot(&mut self) -> Result<ClipboardSnapshot, SuperspeedError> {
+        let mut state = self.state.lock().unwrap();
+        state.tick();
+        Ok(state.contents.clone())
+    }
+
+    fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), SuperspeedError> {
+        self.state.lock().unwrap().set(snapshot.clone());
+        Ok(())
+    }
+}
+
+// Active backend, created lazily on first use
+static BACKEND: Mutex<Option<Box<dyn Clipboard>>> = Mutex::new(None);
+
+/// Replace the active clipboard backend
+pub fn set_backend(backend: Box<dyn Clipboard>) {
+    *BACKEND.lock().unwrap() = Some(backend);
+}
+
+/// Run `f` against the active backend, creating the platform default if none is set
+pub fn with_backend<R>(f: impl FnOnce(&mut dyn Clipboard) -> Result<R, SuperspeedError>) -> Result<R, SuperspeedError> {
+    let mut guard = BACKEND.lock().unwrap();
+    if guard.is_none() {
+        *guard = Some(default_backend()?);
+    }
+    f(guard.as_mut().unwrap().as_mut())
+}
+
+/// Platform default backend
+fn default_backend() -> Result<Box<dyn Clipboard>, SuperspeedError> {
+    #[cfg(target_os = "macos")]
+    {
+        Ok(Box::new(super::pasteboard::GeneralPasteboard))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use super::display::{self, DisplayServer};
+        match display::detect() {
+            DisplayServer::X11 => Ok(Box::new(super::x11_clipboard::X11Clipboard::connect(None, Selection::Clipboard)?)),
+            DisplayServer::Wayland => match super::data_control::DataControlClipboard::connect(None, Selection::Clipboard) {
+                Ok(clipboard) => Ok(Box::new(clipboard)),
+                // XWayland shares its CLIPBOARD with Wayland clients on most compositors
+                Err(e) if std::env::var_os("DISPLAY").is_some() => {
+                    log_warn!("wlr-data-control unavailable ({}), falling back to the XWayland selection", e);
+                    Ok(Box::new(super::x11_clipboard::X11Clipboard::connect(None, Selection::Clipboard)?))
+                }
+                Err(e) => Err(e),
+            },
+            DisplayServer::Unknown => Err(SuperspeedError::Unsupported(
+                "No clipboard available outside a graphical session".to_string(),
+            )),
+        }
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        Err(SuperspeedError::Unsupported("No clipboard backend available on this platform".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn immediate_writes_bump_the_change_count() {
+        let mut clipboard = MemoryClipboard::new();
+        let before = clipboard.change_count().unwrap();
+        clipboard.write_string("one").unwrap();
+        assert_eq!(clipboard.change_count().unwrap(), before + 1);
+        assert_eq!(clipboard.read_string().unwrap().as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn delayed_writes_land_after_the_given_reads() {
+        let mut clipboard = MemoryClipboard::with_contents("old");
+        clipboard.set_write_behavior(WriteBehavior::Delayed(2));
+        let before = clipboard.change_count().unwrap();
+
+        clipboard.write_string("new").unwrap();
+        assert_eq!(clipboard.read_string().unwrap().as_deref(), Some("old"));
+        assert_eq!(clipboard.change_count().unwrap(), before);
+        // The third read lands it
+        assert_eq!(clipboard.read_string().unwrap().as_deref(), Some("new"));
+        assert_eq!(clipboard.change_count().unwrap(), before + 1);
+    }
+
+    #[test]
+    fn dropped_writes_never_land() {
+        let mut clipboard = MemoryClipboard::with_contents("old");
+        clipboard.set_write_behavior(WriteBehavior::Dropped);
+        let before = clipboard.change_count().unwrap();
+
+        clipboard.clear().unwrap();
+        clipboard.write_string("new").unwrap();
+        for _ in 0..10 {
+            assert_eq!(clipboard.read_string().unwrap().as_deref(), Some("old"));
+        }
+        assert_eq!(clipboard.change_count().unwrap(), before);
+    }
+
+    #[test]
+    fn clones_share_contents() {
+        let clipboard = MemoryClipboard::new();
+        let mut installed = clipboard.clone();
+        installed.write_string("shared").unwrap();
+        assert_eq!(clipboard.contents().as_deref(), Some("shared"));
+    }
+}
@@ -0,0 +1,131 @@
+// CoreGraphics key synthesis backend (macOS)
+use super::layout::{self, KeyboardLayout};
+use super::listen::SYNTHETIC_EVENT_TAG;
+use super::synth::{Key, KeySynth, Modifiers};
+use crate::error::SuperspeedError;
+use crate::text;
+use core_graphics::event::{CGEvent, CGEventFlags, CGEventTapLocation, CGKeyCode, EventField};
+use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+
+// macOS virtual keycodes (from Carbon Events.h)
+const KVK_RETURN: u16 = 0x24; // Return/Enter key
+const KVK_TAB: u16 = 0x30; // Tab key
+const KVK_DELETE: u16 = 0x33; // Backspace/Delete key
+const KVK_LEFT_ARROW: u16 = 0x7B; // Left arrow
+const KVK_RIGHT_ARROW: u16 = 0x7C; // Right arrow
+const KVK_SHIFT: u16 = 0x38; // Shift modifier
+const KVK_COMMAND: u16 = 0x37; // Command modifier
+const KVK_CONTROL: u16 = 0x3B; // Control modifier
+
+// CGEventKeyboardSetUnicodeString: longer strings are truncated by some apps
+const MAX_UNICODE_STRING_UTF16: usize = 20;
+
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXIsProcessTrusted() -> bool;
+}
+
+/// Posts keyboard events to the HID event tap
+pub struct CoreGraphicsSynth;
+
+impl CoreGraphicsSynth {
+    fn post(&self, key: Key, modifiers: Modifiers, key_down: bool) -> Result<(), SuperspeedError> {
+        let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+            .map_err(|_| SuperspeedError::EventSource)?;
+
+        let event = CGEvent::new_keyboard_event(source, keycode(key, &layout::current()), key_down)
+            .map_err(|_| {
+                SuperspeedError::EventPost(format!("Failed to create {:?} {} event", key, if key_down { "down" } else { "up" }))
+            })?;
+
+        // Only override flags when asked to, so bare modifier presses keep the source state
+        if !modifiers.is_empty() {
+            event.set_flags(flags(modifiers));
+        }
+        // Lets the key listener tell our events from the user's
+        event.set_integer_value_field(EventField::EVENT_SOURCE_USER_DATA, SYNTHETIC_EVENT_TAG);
+
+        event.post(CGEventTapLocation::HID);
+        Ok(())
+    }
+
+    /// Post a key down/up pair carrying `chunk` as its Unicode string
+    fn post_unicode(&self, chunk: &str) -> Result<(), SuperspeedError> {
+        for key_down in [true, false] {
+            let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+                .map_err(|_| SuperspeedError::EventSource)?;
+            // The keycode is ignored by apps once a Unicode string is attached
+            let event = CGEvent::new_keyboard_event(source, 0, key_down)
+                .map_err(|_| SuperspeedError::EventPost("Failed to create Unicode text event".to_string()))?;
+            event.set_string(chunk);
+            // Clear flags so a held modifier can't turn the text into shortcuts
+            event.set_flags(CGEventFlags::empty());
+            event.set_integer_value_field(EventField::EVENT_SOURCE_USER_DATA, SYNTHETIC_EVENT_TAG);
+            event.post(CGEventTapLocation::HID);
+        }
+        Ok(())
+    }
+}
+
+impl KeySynth for CoreGraphicsSynth {
+    fn name(&self) -> &'static str {
+        "coregraphics"
+    }
+
+    fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        self.post(key, modifiers, true)
+    }
+
+    fn key_up(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        self.post(key, modifiers, false)
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        for chunk in text::chunks(text, MAX_UNICODE_STRING_UTF16, |g| g.encode_utf16().count()) {
+            self.post_unicode(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn check_access(&self) -> Result<(), SuperspeedError> {
+        // Untrusted processes can create and post events, but macOS drops them
+        if unsafe { AXIsProcessTrusted() } {
+            Ok(())
+        } else {
+            Err(SuperspeedError::AccessibilityDenied)
+        }
+    }
+}
+
+/// Virtual keycode for `key`; letter keys are looked up in `layout` (V is not at the ANSI V key on Dvorak)
+pub fn keycode(key: Key, layout: &KeyboardLayout) -> CGKeyCode {
+    let code = match key {
+        Key::Return => KVK_RETURN,
+        Key::Tab => KVK_TAB,
+        Key::Backspace => KVK_DELETE,
+        Key::LeftArrow => KVK_LEFT_ARROW,
+        Key::RightArrow => KVK_RIGHT_ARROW,
+        Key::Shift => KVK_SHIFT,
+        Key::Command => KVK_COMMAND,
+        Key::Control => KVK_CONTROL,
+        Key::V | Key::C | Key::U => layout
+            .shortcut_position(key)
+            .and_then(layout::mac_keycode)
+            .expect("shortcut letters always resolve to a known position"),
+    };
+    code as CGKeyCode
+}
+
+fn flags(modifiers: Modifiers) -> CGEventFlags {
+    let mut flags = CGEventFlags::empty();
+    if modifiers.shift {
+        flags |= CGEventFlags::CGEventFlagShift;
+    }
+    if modifiers.command {
+        flags |= CGEventFlags::CGEventFlagCommand;
+    }
+    if modifiers.control {
+        flags |= CGEventFlags::CGEventFlagControl;
+    }
+    flags
+}
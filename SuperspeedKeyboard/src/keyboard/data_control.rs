@@ -0,0 +1,414 @@
+// Wayland clipboard backend (wlr-data-control)
+// Regular Wayland clients only see the clipboard while focused; the
+// wlr-data-control protocol lets a background client read and set it like a
+// clipboard manager. As on X11 we must serve our own contents: a dispatch thread
+// answers `send` requests from pasting clients and tracks the current offer.
+
+use super::clipboard::{Clipboard, ClipboardItem, ClipboardSnapshot, Selection};
+use crate::error::SuperspeedError;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_registry, wl_seat::WlSeat};
+use wayland_client::{delegate_noop, event_created_child, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1};
+
+// Text MIME types we offer and accept, in order of preference
+const TEXT_MIME_TYPES: [&str; 5] = ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain", "TEXT", "STRING"];
+
+// How long to wait for the compositor to confirm a selection change, or for
+// another client to send its contents
+const SELECTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Shared {
+    // Our source and the bytes it serves for each MIME type, while we own the selection
+    source: Option<ZwlrDataControlSourceV1>,
+    contents: Option<ClipboardItem>,
+    // MIME types announced for offers that have not become the selection yet
+    offers: HashMap<ObjectId, Vec<String>>,
+    // Current selection as advertised by the compositor
+    selection: Option<(ZwlrDataControlOfferV1, Vec<String>)>,
+    change_count: i64,
+}
+
+struct State {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    selection: Selection,
+}
+
+impl State {
+    fn set_selection(&mut self, offer: Option<ZwlrDataControlOfferV1>) {
+        let (lock, changed) = &*self.shared;
+        let mut shared = lock.lock().unwrap();
+        let offer = offer.map(|offer| {
+            let mime_types = shared.offers.remove(&offer.id()).unwrap_or_default();
+            (offer, mime_types)
+        });
+        if let Some((old, _)) = std::mem::replace(&mut shared.selection, offer) {
+            old.destroy();
+        }
+        shared.change_count += 1;
+        changed.notify_all();
+    }
+
+    fn discard_offer(&mut self, offer: Option<ZwlrDataControlOfferV1>) {
+        if let Some(offer) = offer {
+            self.shared.0.lock().unwrap().offers.remove(&offer.id());
+            offer.destroy();
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwlrDataControlManagerV1);
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::DataOffer { id } => {
+                state.shared.0.lock().unwrap().offers.insert(id.id(), Vec::new());
+            }
+            zwlr_data_control_device_v1::Event::Selection { id } => match state.selection {
+                Selection::Clipboard => state.set_selection(id),
+                Selection::Primary => state.discard_offer(id),
+            },
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => match state.selection {
+                Selection::Primary => state.set_selection(id),
+                Selection::Clipboard => state.discard_offer(id),
+            },
+            zwlr_data_control_device_v1::Event::Finished => {
+                log_warn!("wlr-data-control device finished; the seat went away");
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        offer: &ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            if let Some(mime_types) = state.shared.0.lock().unwrap().offers.get_mut(&offer.id()) {
+                mime_types.push(mime_type);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        source: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let (lock, _) = &*state.shared;
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                let data = lock
+                    .lock()
+                    .unwrap()
+                    .contents
+                    .as_ref()
+                    .and_then(|item| item.get(&mime_type).map(<[u8]>::to_vec))
+                    .unwrap_or_default();
+                // Write off the dispatch thread so a slow reader can't stall the event loop
+                let _ = thread::Builder::new().name("superspeed-data-control-send".to_string()).spawn(move || {
+                    let mut file = File::from(fd);
+                    if let Err(e) = file.write_all(&data) {
+                        log_debug!("Pasting client closed the pipe early: {}", e);
+                    }
+                });
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                let mut shared = lock.lock().unwrap();
+                if shared.source.as_ref() == Some(source) {
+                    shared.source = None;
+                    log_debug!("Another client took the selection");
+                }
+                source.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Clipboard or primary selection on a wlroots-compatible compositor
+pub struct DataControlClipboard {
+    conn: Connection,
+    qh: QueueHandle<State>,
+    manager: ZwlrDataControlManagerV1,
+    device: ZwlrDataControlDeviceV1,
+    selection: Selection,
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+}
+
+impl DataControlClipboard {
+    /// Connect to `display` (None = $WAYLAND_DISPLAY) and start tracking `selection`
+    pub fn connect(display: Option<&str>, selection: Selection) -> Result<Self, SuperspeedError> {
+        let conn = match display {
+            Some(name) => {
+                let path = socket_path(name)?;
+                let stream = UnixStream::connect(&path).map_err(|e| {
+                    SuperspeedError::Unsupported(format!("Cannot connect to Wayland display {}: {}", path.display(), e))
+                })?;
+                Connection::from_socket(stream).map_err(wayland_error)?
+            }
+            None => Connection::connect_to_env()
+                .map_err(|e| SuperspeedError::Unsupported(format!("Cannot connect to Wayland display: {}", e)))?,
+        };
+
+        let (globals, mut queue) = registry_queue_init::<State>(&conn).map_err(wayland_error)?;
+        let qh = queue.handle();
+        let seat: WlSeat = globals
+            .bind(&qh, 1..=7, ())
+            .map_err(|e| SuperspeedError::Unsupported(format!("Wayland compositor has no seat: {}", e)))?;
+        let manager: ZwlrDataControlManagerV1 = globals.bind(&qh, 1..=2, ()).map_err(|_| {
+            SuperspeedError::Unsupported("Wayland compositor does not support wlr-data-control".to_string())
+        })?;
+        if selection == Selection::Primary && manager.version() < 2 {
+            return Err(SuperspeedError::Unsupported(
+                "Wayland compositor's wlr-data-control has no primary selection".to_string(),
+            ));
+        }
+        let device = manager.get_data_device(&seat, &qh, ());
+
+        let shared = Arc::new((Mutex::new(Shared::default()), Condvar::new()));
+        let mut state = State { shared: shared.clone(), selection };
+        // Receive the current selection before anyone reads it
+        queue.roundtrip(&mut state).map_err(wayland_error)?;
+
+        thread::Builder::new()
+            .name("superspeed-data-control".to_string())
+            .spawn(move || loop {
+                if let Err(e) = queue.blocking_dispatch(&mut state) {
+                    log_warn!("wlr-data-control dispatch stopped: {}", e);
+                    return;
+                }
+            })
+            .map_err(|e| SuperspeedError::Clipboard(format!("Cannot start data-control thread: {}", e)))?;
+
+        Ok(DataControlClipboard { conn, qh, manager, device, selection, shared })
+    }
+
+    // Point the selection at `source` (None clears it) and wait for the compositor to confirm
+    fn replace_selection(&mut self, source: Option<ZwlrDataControlSourceV1>, contents: Option<ClipboardItem>) -> Result<(), SuperspeedError> {
+        let (lock, changed) = &*self.shared;
+        let mut shared = lock.lock().unwrap();
+        let before = shared.change_count;
+
+        match self.selection {
+            Selection::Clipboard => self.device.set_selection(source.as_ref()),
+            Selection::Primary => self.device.set_primary_selection(source.as_ref()),
+        }
+        if let Some(old) = std::mem::replace(&mut shared.source, source) {
+            old.destroy();
+        }
+        shared.contents = contents;
+        self.conn.flush().map_err(wayland_error)?;
+
+        let deadline = Instant::now() + SELECTION_TIMEOUT;
+        while shared.change_count == before {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(SuperspeedError::Clipboard("Compositor did not confirm the selection change".to_string()));
+            }
+            shared = changed.wait_timeout(shared, remaining).unwrap().0;
+        }
+        Ok(())
+    }
+
+    // Take the selection with a source offering every flavor of `item`
+    fn offer_item(&mut self, item: ClipboardItem) -> Result<(), SuperspeedError> {
+        let source = self.manager.create_data_source(&self.qh, ());
+        for (mime_type, _) in &item.flavors {
+            source.offer(mime_type.clone());
+        }
+        self.replace_selection(Some(source), Some(item))
+    }
+
+    // Ask the owner of `offer` to send its `mime_type` representation
+    fn receive(&self, offer: &ZwlrDataControlOfferV1, mime_type: &str) -> Result<Vec<u8>, SuperspeedError> {
+        let (mut reader, writer) = pipe()?;
+        offer.receive(mime_type.to_string(), writer.as_fd());
+        self.conn.flush().map_err(wayland_error)?;
+        // Close our copy of the write end so EOF arrives when the owner is done
+        drop(writer);
+        read_with_timeout(&mut reader, SELECTION_TIMEOUT)
+    }
+}
+
+impl Clipboard for DataControlClipboard {
+    fn name(&self) -> &'static str {
+        "wlr-data-control"
+    }
+
+    fn read_string(&mut self) -> Result<Option<String>, SuperspeedError> {
+        let (offer, mime_type) = {
+            let shared = self.shared.0.lock().unwrap();
+            if shared.source.is_some() {
+                return Ok(shared.contents.as_ref().and_then(ClipboardItem::to_text));
+            }
+            let Some((offer, mime_types)) = shared.selection.as_ref() else {
+                return Ok(None);
+            };
+            let Some(mime_type) = TEXT_MIME_TYPES.iter().find(|m| mime_types.iter().any(|t| t == *m)) else {
+                // The selection holds something other than text (an image, files)
+                return Ok(None);
+            };
+            (offer.clone(), mime_type.to_string())
+        };
+
+        let bytes = self.receive(&offer, &mime_type)?;
+        let text = if mime_type == "STRING" {
+            bytes.iter().map(|&b| b as char).collect()
+        } else {
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+        Ok(Some(text))
+    }
+
+    fn write_string(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        let flavors = TEXT_MIME_TYPES.iter().map(|mime_type| (mime_type.to_string(), text.as_bytes().to_vec()));
+        self.offer_item(ClipboardItem { flavors: flavors.collect() })
+    }
+
+    fn clear(&mut self) -> Result<(), SuperspeedError> {
+        self.replace_selection(None, None)
+    }
+
+    fn change_count(&mut self) -> Result<i64, SuperspeedError> {
+        // Counts every selection event, whoever set the selection
+        Ok(self.shared.0.lock().unwrap().change_count)
+    }
+
+    fn snapshot(&mut self) -> Result<ClipboardSnapshot, SuperspeedError> {
+        let (offer, mime_types) = {
+            let shared = self.shared.0.lock().unwrap();
+            if shared.source.is_some() {
+                return Ok(ClipboardSnapshot { items: shared.contents.iter().cloned().collect() });
+            }
+            match shared.selection.as_ref() {
+                Some((offer, mime_types)) => (offer.clone(), mime_types.clone()),
+                None => return Ok(ClipboardSnapshot::default()),
+            }
+        };
+
+        // A Wayland selection is a single item offered as several MIME types
+        let mut item = ClipboardItem::default();
+        for mime_type in mime_types {
+            match self.receive(&offer, &mime_type) {
+                Ok(data) => item.flavors.push((mime_type, data)),
+                Err(e) => log_debug!("Cannot save selection type {}: {}", mime_type, e),
+            }
+        }
+        Ok(ClipboardSnapshot { items: vec![item] })
+    }
+
+    fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), SuperspeedError> {
+        let Some(item) = snapshot.items.iter().find(|item| !item.flavors.is_empty()) else {
+            return self.clear();
+        };
+        if snapshot.items.len() > 1 {
+            log_warn!("Wayland selections hold one item; restoring the first of {}", snapshot.items.len());
+        }
+        self.offer_item(item.clone())
+    }
+}
+
+impl Drop for DataControlClipboard {
+    fn drop(&mut self) {
+        self.device.destroy();
+        let _ = self.conn.flush();
+    }
+}
+
+fn socket_path(name: &str) -> Result<PathBuf, SuperspeedError> {
+    let path = PathBuf::from(name);
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| SuperspeedError::Unsupported("XDG_RUNTIME_DIR is not set".to_string()))?;
+    Ok(PathBuf::from(runtime_dir).join(path))
+}
+
+fn pipe() -> Result<(File, File), SuperspeedError> {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors pipe2 writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(SuperspeedError::Clipboard(format!("pipe: {}", std::io::Error::last_os_error())));
+    }
+    // SAFETY: both descriptors are freshly created and owned by nobody else
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+// Read until EOF, giving up if the owner goes quiet for `timeout`
+fn read_with_timeout(reader: &mut File, timeout: Duration) -> Result<Vec<u8>, SuperspeedError> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let mut poll_fd = libc::pollfd { fd: reader.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: poll_fd is a valid pollfd for the duration of the call
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+        if ready == 0 {
+            return Err(SuperspeedError::Clipboard("Selection owner did not send its contents".to_string()));
+        }
+        if ready < 0 {
+            return Err(SuperspeedError::Clipboard(format!("poll: {}", std::io::Error::last_os_error())));
+        }
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(data),
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(SuperspeedError::Clipboard(format!("Reading selection: {}", e))),
+        }
+    }
+}
+
+fn wayland_error(e: impl std::fmt::Display) -> SuperspeedError {
+    SuperspeedError::Clipboard(format!("Wayland: {}", e))
+}
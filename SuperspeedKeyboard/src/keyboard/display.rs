@@ -0,0 +1,35 @@
+// Linux graphical session detection
+// Backends are picked at runtime: the same binary runs under X11 and Wayland.
+
+use std::env;
+
+/// Kind of graphical session the process is running in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayServer {
+    X11,
+    Wayland,
+    /// No graphical session (TTY, SSH, headless CI)
+    Unknown,
+}
+
+/// Detect the session from XDG_SESSION_TYPE, falling back to WAYLAND_DISPLAY / DISPLAY
+pub fn detect() -> DisplayServer {
+    detect_from(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+/// Detection with an injectable environment lookup
+pub fn detect_from(var: impl Fn(&str) -> Option<String>) -> DisplayServer {
+    match var("XDG_SESSION_TYPE").as_deref() {
+        Some("x11") => return DisplayServer::X11,
+        Some("wayland") => return DisplayServer::Wayland,
+        _ => {}
+    }
+
+    if var("WAYLAND_DISPLAY").is_some() {
+        DisplayServer::Wayland
+    } else if var("DISPLAY").is_some() {
+        DisplayServer::X11
+    } else {
+        DisplayServer::Unknown
+    }
+}
@@ -0,0 +1,431 @@
+// Linux key listener (evdev)
+// Grabs every physical keyboard under /dev/input so no other client sees its
+// keys, asks the listener what to do with each one, and re-emits the keys that
+// pass on a uinput passthrough keyboard. Mice, touchpads and touchscreens are
+// read without grabbing them, only to report clicks. Works the same under X11,
+// any Wayland compositor and the console. Needs read access to the event devices
+// (usually the 'input' group); devices plugged in after start are not watched.
+
+use super::layout::{self, KeyboardLayout, LiveLayout};
+use super::listen::{self, Disposition, InputKey, KeyEvent};
+use super::synth::Modifiers;
+use super::uinput::{self, UinputDevice, EVENT_SIZE, EV_KEY, EV_SYN, SYN_REPORT};
+use crate::error::SuperspeedError;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const INPUT_DIR: &str = "/dev/input";
+pub const PASSTHROUGH_NAME: &str = "Superspeed Passthrough Keyboard";
+// Devices the crate creates itself: listening to them would loop our own events back
+const OWN_DEVICE_PREFIX: &str = "Superspeed";
+
+// Event types and key codes (from linux/input-event-codes.h)
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const KEY_ESC: u16 = 1;
+const KEY_BACKSPACE: u16 = 14;
+const KEY_TAB: u16 = 15;
+const KEY_ENTER: u16 = 28;
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_A: u16 = 30;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_Z: u16 = 44;
+const KEY_RIGHTSHIFT: u16 = 54;
+const KEY_LEFTALT: u16 = 56;
+const KEY_SPACE: u16 = 57;
+const KEY_CAPSLOCK: u16 = 58;
+const KEY_KPENTER: u16 = 96;
+const KEY_RIGHTCTRL: u16 = 97;
+const KEY_RIGHTALT: u16 = 100;
+const KEY_UP: u16 = 103;
+const KEY_LEFT: u16 = 105;
+const KEY_RIGHT: u16 = 106;
+const KEY_DOWN: u16 = 108;
+const KEY_DELETE: u16 = 111;
+const KEY_LEFTMETA: u16 = 125;
+const KEY_RIGHTMETA: u16 = 126;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_TOUCH: u16 = 0x14a;
+const KEY_CNT: usize = 0x300;
+
+// evdev key event values
+const KEY_RELEASED: i32 = 0;
+const KEY_REPEATED: i32 = 2;
+
+// How long each device thread blocks before checking whether to stop
+const POLL_TIMEOUT_MS: i32 = 100;
+// Keys still held at grab time would never be released for other clients
+const RELEASE_WAIT: Duration = Duration::from_secs(1);
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static THREADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Logical key for an evdev key code; character keys are looked up in `layout`
+pub fn input_key(code: u16, layout: &KeyboardLayout) -> InputKey {
+    match code {
+        KEY_TAB => InputKey::Tab,
+        KEY_ESC => InputKey::Escape,
+        KEY_ENTER | KEY_KPENTER => InputKey::Return,
+        KEY_SPACE => InputKey::Space,
+        KEY_BACKSPACE => InputKey::Backspace,
+        KEY_DELETE => InputKey::Delete,
+        KEY_LEFT => InputKey::LeftArrow,
+        KEY_RIGHT => InputKey::RightArrow,
+        KEY_UP => InputKey::UpArrow,
+        KEY_DOWN => InputKey::DownArrow,
+        KEY_LEFTSHIFT | KEY_RIGHTSHIFT => InputKey::Shift,
+        KEY_LEFTCTRL | KEY_RIGHTCTRL => InputKey::Control,
+        KEY_LEFTMETA | KEY_RIGHTMETA => InputKey::Command,
+        KEY_LEFTALT | KEY_RIGHTALT => InputKey::Option,
+        _ => match layout::position_for_evdev(code) {
+            Some(position) => InputKey::at_position(layout, position, code as u32),
+            None => InputKey::Other(code as u32),
+        },
+    }
+}
+
+/// Character typed by the key at `code`, as far as the layout file tells (no dead keys or AltGr levels)
+pub fn typed_char(code: u16, layout: &KeyboardLayout, held: &HeldModifiers) -> Option<char> {
+    if held.alt() {
+        return None;
+    }
+    if code == KEY_SPACE {
+        return Some(' ');
+    }
+    let key = layout.key_at(layout::position_for_evdev(code)?)?;
+    let base = key.base?;
+    // Caps Lock only shifts letters
+    let shifted = held.modifiers().shift ^ (held.caps_lock && base.is_alphabetic());
+    if shifted {
+        key.shifted
+    } else {
+        Some(base)
+    }
+}
+
+/// Modifiers held on one device, tracked from its own key events
+#[derive(Debug, Default)]
+pub struct HeldModifiers {
+    held: Vec<InputKey>,
+    caps_lock: bool,
+}
+
+impl HeldModifiers {
+    /// Record a press or release of the key at `code`
+    pub fn update(&mut self, code: u16, key: InputKey, down: bool, repeat: bool) {
+        if code == KEY_CAPSLOCK && down && !repeat {
+            self.caps_lock = !self.caps_lock;
+        }
+        if !key.is_modifier() || repeat {
+            return;
+        }
+        if down {
+            self.held.push(key);
+        } else if let Some(index) = self.held.iter().position(|&held| held == key) {
+            self.held.remove(index);
+        }
+    }
+
+    /// Alt or AltGr held
+    pub fn alt(&self) -> bool {
+        self.held.contains(&InputKey::Option)
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.held.contains(&InputKey::Shift),
+            command: self.held.contains(&InputKey::Command),
+            control: self.held.contains(&InputKey::Control),
+        }
+    }
+}
+
+// ioctl request numbers (asm-generic/ioctl.h encoding, 'E' type from linux/input.h)
+const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | ((b'E' as u32) << 8) | nr
+}
+const IOC_WRITE: u32 = 1;
+const IOC_READ: u32 = 2;
+const fn eviocgname(len: usize) -> u32 {
+    ioc(IOC_READ, 0x06, len)
+}
+const fn eviocgkey(len: usize) -> u32 {
+    ioc(IOC_READ, 0x18, len)
+}
+const fn eviocgbit(kind: u16, len: usize) -> u32 {
+    ioc(IOC_READ, 0x20 + kind as u32, len)
+}
+const EVIOCGRAB: u32 = ioc(IOC_WRITE, 0x90, std::mem::size_of::<libc::c_int>());
+
+// Fill `buf` from a read ioctl; returns the number of bytes the kernel wrote
+fn read_ioctl(file: &File, request: u32, buf: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: request is a read ioctl sized for buf
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, buf.as_mut_ptr()) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as usize)
+    }
+}
+
+fn bit_set(bits: &[u8], bit: u16) -> bool {
+    bits.get(bit as usize / 8).is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+}
+
+fn device_name(file: &File) -> String {
+    let mut buf = [0u8; 256];
+    let len = read_ioctl(file, eviocgname(buf.len()), &mut buf).unwrap_or(0);
+    let name = &buf[..len.min(buf.len())];
+    String::from_utf8_lossy(name.split(|&byte| byte == 0).next().unwrap_or_default()).into_owned()
+}
+
+// Has a letter block, and no pointer axes that grabbing would freeze
+fn is_keyboard(file: &File) -> bool {
+    let mut types = [0u8; 4];
+    let mut keys = [0u8; KEY_CNT / 8];
+    if read_ioctl(file, eviocgbit(0, types.len()), &mut types).is_err()
+        || read_ioctl(file, eviocgbit(EV_KEY, keys.len()), &mut keys).is_err()
+    {
+        return false;
+    }
+    let pointer = bit_set(&types, EV_REL) || bit_set(&types, EV_ABS);
+    !pointer && [KEY_A, KEY_Z, KEY_SPACE].iter().all(|&key| bit_set(&keys, key))
+}
+
+// Has pointer axes and a button or touch that can move the caret
+fn is_pointer(file: &File) -> bool {
+    let mut types = [0u8; 4];
+    let mut keys = [0u8; KEY_CNT / 8];
+    if read_ioctl(file, eviocgbit(0, types.len()), &mut types).is_err()
+        || read_ioctl(file, eviocgbit(EV_KEY, keys.len()), &mut keys).is_err()
+    {
+        return false;
+    }
+    (bit_set(&types, EV_REL) || bit_set(&types, EV_ABS)) && (bit_set(&keys, BTN_LEFT) || bit_set(&keys, BTN_TOUCH))
+}
+
+// A pointer button or touch (a touchpad tap only shows up as BTN_TOUCH)
+fn is_click(code: u16) -> bool {
+    matches!(code, BTN_LEFT | BTN_RIGHT | BTN_MIDDLE | BTN_TOUCH)
+}
+
+// Block until no key on the device is held (or give up after RELEASE_WAIT)
+fn wait_for_release(file: &File) {
+    let deadline = Instant::now() + RELEASE_WAIT;
+    let mut held = [0u8; KEY_CNT / 8];
+    while Instant::now() < deadline {
+        if read_ioctl(file, eviocgkey(held.len()), &mut held).is_err() || held.iter().all(|&byte| byte == 0) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn grab(file: &File) -> io::Result<()> {
+    // SAFETY: EVIOCGRAB takes an int flag by value
+    if unsafe { libc::ioctl(file.as_raw_fd(), EVIOCGRAB as _, 1 as libc::c_int) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+struct Device {
+    path: PathBuf,
+    name: String,
+    file: File,
+}
+
+// Every keyboard and pointer under /dev/input the process can read, except the crate's own devices
+fn open_devices() -> Result<(Vec<Device>, Vec<Device>), SuperspeedError> {
+    let entries = fs::read_dir(INPUT_DIR)
+        .map_err(|e| SuperspeedError::Unsupported(format!("Cannot list {}: {}", INPUT_DIR, e)))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("event")))
+        .collect();
+    paths.sort();
+
+    let mut keyboards = Vec::new();
+    let mut pointers = Vec::new();
+    let mut denied = false;
+    for path in paths {
+        let file = match OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                denied |= e.kind() == io::ErrorKind::PermissionDenied;
+                continue;
+            }
+        };
+        let name = device_name(&file);
+        if name.starts_with(OWN_DEVICE_PREFIX) {
+            continue;
+        }
+        if is_keyboard(&file) {
+            keyboards.push(Device { path, name, file });
+        } else if is_pointer(&file) {
+            pointers.push(Device { path, name, file });
+        }
+    }
+
+    if keyboards.is_empty() {
+        return Err(if denied {
+            SuperspeedError::PermissionDenied(format!(
+                "cannot read {}/event*: add the user to the group that owns them (usually 'input')",
+                INPUT_DIR
+            ))
+        } else {
+            SuperspeedError::Unsupported("No keyboard found under /dev/input".to_string())
+        });
+    }
+    Ok((keyboards, pointers))
+}
+
+/// Grab the keyboards, watch the pointers, and start one listening thread per device
+pub fn start() -> Result<(), SuperspeedError> {
+    let (keyboards, pointers) = open_devices()?;
+    // Created before grabbing so no key typed in between is lost
+    let passthrough = UinputDevice::create_with(
+        Path::new(uinput::DEVICE_PATH),
+        PASSTHROUGH_NAME,
+        &uinput::passthrough_requests(PASSTHROUGH_NAME),
+    )?;
+    let passthrough = Arc::new(Mutex::new(passthrough));
+    let layout = LiveLayout::new();
+
+    RUNNING.store(true, Ordering::SeqCst);
+    let mut threads = THREADS.lock().unwrap();
+    for keyboard in keyboards {
+        wait_for_release(&keyboard.file);
+        if let Err(e) = grab(&keyboard.file) {
+            log_warn!("Not listening to {} ({}): {}", keyboard.name, keyboard.path.display(), e);
+            continue;
+        }
+        log_debug!("Listening to {} ({})", keyboard.name, keyboard.path.display());
+        let passthrough = Arc::clone(&passthrough);
+        let layout = layout.clone();
+        let spawned = thread::Builder::new()
+            .name("superspeed-evdev".to_string())
+            .spawn(move || listen_to(keyboard, &passthrough, layout));
+        match spawned {
+            Ok(handle) => threads.push(handle),
+            Err(e) => log_warn!("Cannot start a listener thread: {}", e),
+        }
+    }
+
+    if threads.is_empty() {
+        RUNNING.store(false, Ordering::SeqCst);
+        return Err(SuperspeedError::Unsupported("No keyboard could be grabbed".to_string()));
+    }
+
+    for pointer in pointers {
+        log_debug!("Watching clicks on {} ({})", pointer.name, pointer.path.display());
+        let spawned = thread::Builder::new()
+            .name("superspeed-evdev-pointer".to_string())
+            .spawn(move || watch_clicks(pointer));
+        match spawned {
+            Ok(handle) => threads.push(handle),
+            Err(e) => log_warn!("Cannot start a pointer thread: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Release the keyboards and wait for the listening and pointer threads to finish
+pub fn stop() {
+    RUNNING.store(false, Ordering::SeqCst);
+    let threads = std::mem::take(&mut *THREADS.lock().unwrap());
+    for handle in threads {
+        let _ = handle.join();
+    }
+}
+
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+// Read events until stopped or the device goes away, handing each (type, code, value) to `handle`
+fn read_events(device: &mut Device, mut handle: impl FnMut(u16, u16, i32)) {
+    let mut buf = [0u8; EVENT_SIZE * 64];
+
+    while RUNNING.load(Ordering::SeqCst) {
+        let mut pollfd = libc::pollfd { fd: device.file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: pollfd points at one valid pollfd
+        if unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT_MS) } <= 0 {
+            continue;
+        }
+
+        let len = match device.file.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                log_warn!("Stopped listening to {}: {}", device.name, e);
+                return;
+            }
+        };
+
+        for chunk in buf[..len].chunks_exact(EVENT_SIZE) {
+            let (kind, code, value) = uinput::decode_event(chunk.try_into().expect("chunk is EVENT_SIZE bytes"));
+            handle(kind, code, value);
+        }
+    }
+}
+
+// Listen to a grabbed keyboard until stopped; closing the file releases the grab
+fn listen_to(mut keyboard: Device, passthrough: &Mutex<UinputDevice>, mut layout: LiveLayout) {
+    let mut held = HeldModifiers::default();
+    // Passed key events waiting for their SYN_REPORT
+    let mut report = Vec::new();
+
+    read_events(&mut keyboard, |kind, code, value| match kind {
+        EV_KEY => {
+            let layout = layout.get();
+            let key = input_key(code, layout);
+            let down = value != KEY_RELEASED;
+            let repeat = value == KEY_REPEATED;
+            let event = KeyEvent {
+                key,
+                down,
+                repeat,
+                modifiers: held.modifiers(),
+                alt: held.alt(),
+                text: if down { typed_char(code, layout, &held) } else { None },
+                // The crate's own uinput and XTest events never pass through here
+                synthetic: false,
+            };
+            held.update(code, key, down, repeat);
+            if listen::dispatch(&event) == Disposition::Pass {
+                report.extend_from_slice(&uinput::encode_event(EV_KEY, code, value));
+            }
+        }
+        EV_SYN if code == SYN_REPORT && !report.is_empty() => {
+            report.extend_from_slice(&uinput::encode_event(EV_SYN, SYN_REPORT, 0));
+            let mut device = passthrough.lock().unwrap();
+            if let Err(e) = device.write_all(&report).and_then(|_| device.flush()) {
+                log_warn!("Passthrough keyboard: {}", e);
+            }
+            report.clear();
+        }
+        // Scan codes and LED state stay with the grabbed device
+        _ => {}
+    });
+}
+
+// Clicks and touches can move the caret; the pointer itself is never grabbed
+fn watch_clicks(mut pointer: Device) {
+    read_events(&mut pointer, |kind, code, value| {
+        if kind == EV_KEY && value != KEY_RELEASED && is_click(code) {
+            listen::dispatch(&KeyEvent::press(InputKey::Pointer));
+        }
+    });
+}
@@ -0,0 +1,260 @@
+// macOS key listener (CGEventTap)
+// An active tap at the head of the session event stream sees every key before
+// the focused app does and can drop it. The tap runs on its own thread and run
+// loop: keys posted while the main thread is busy inserting ghost text reach
+// the apps without waiting for it. macOS disables taps that answer too slowly,
+// so the handler only decides; anything slow is deferred to the main queue.
+
+use super::layout::{self, KeyboardLayout};
+use super::listen::{self, Disposition, InputKey, KeyEvent, SYNTHETIC_EVENT_TAG};
+use super::synth::Modifiers;
+use crate::error::SuperspeedError;
+use core_foundation::base::TCFType;
+use core_foundation::mach_port::CFMachPortInvalidate;
+use core_foundation::runloop::{kCFRunLoopCommonModes, CFRunLoop, CFRunLoopSource};
+use core_graphics::event::{
+    CGEvent, CGEventFlags, CGEventTap, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement, CGEventType, EventField,
+};
+use foreign_types::ForeignType;
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// macOS virtual keycodes (from Carbon Events.h)
+const KVK_RETURN: u16 = 0x24;
+const KVK_TAB: u16 = 0x30;
+const KVK_SPACE: u16 = 0x31;
+const KVK_DELETE: u16 = 0x33;
+const KVK_ESCAPE: u16 = 0x35;
+const KVK_RIGHT_COMMAND: u16 = 0x36;
+const KVK_COMMAND: u16 = 0x37;
+const KVK_SHIFT: u16 = 0x38;
+const KVK_OPTION: u16 = 0x3A;
+const KVK_CONTROL: u16 = 0x3B;
+const KVK_RIGHT_SHIFT: u16 = 0x3C;
+const KVK_RIGHT_OPTION: u16 = 0x3D;
+const KVK_RIGHT_CONTROL: u16 = 0x3E;
+const KVK_KEYPAD_ENTER: u16 = 0x4C;
+const KVK_FORWARD_DELETE: u16 = 0x75;
+const KVK_LEFT_ARROW: u16 = 0x7B;
+const KVK_RIGHT_ARROW: u16 = 0x7C;
+const KVK_DOWN_ARROW: u16 = 0x7D;
+const KVK_UP_ARROW: u16 = 0x7E;
+
+// Typing one key yields a single character, except for composed input
+const MAX_TYPED_UTF16: usize = 4;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventKeyboardGetUnicodeString(event: *mut c_void, max_length: usize, actual_length: *mut usize, string: *mut u16);
+}
+
+struct Installed {
+    tap: CGEventTap<'static>,
+    source: CFRunLoopSource,
+}
+
+// The tap belongs to the tap thread's run loop, so only that thread holds it
+thread_local! {
+    static TAP: RefCell<Option<Installed>> = const { RefCell::new(None) };
+}
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+// Run loop of the tap thread, stopped to remove the tap
+static TAP_THREAD: Mutex<Option<(CFRunLoop, JoinHandle<()>)>> = Mutex::new(None);
+
+/// Logical key for a virtual keycode; character keys are looked up in `layout`
+pub fn input_key(keycode: u16, layout: &KeyboardLayout) -> InputKey {
+    match keycode {
+        KVK_TAB => InputKey::Tab,
+        KVK_ESCAPE => InputKey::Escape,
+        KVK_RETURN | KVK_KEYPAD_ENTER => InputKey::Return,
+        KVK_SPACE => InputKey::Space,
+        KVK_DELETE => InputKey::Backspace,
+        KVK_FORWARD_DELETE => InputKey::Delete,
+        KVK_LEFT_ARROW => InputKey::LeftArrow,
+        KVK_RIGHT_ARROW => InputKey::RightArrow,
+        KVK_UP_ARROW => InputKey::UpArrow,
+        KVK_DOWN_ARROW => InputKey::DownArrow,
+        KVK_SHIFT | KVK_RIGHT_SHIFT => InputKey::Shift,
+        KVK_CONTROL | KVK_RIGHT_CONTROL => InputKey::Control,
+        KVK_COMMAND | KVK_RIGHT_COMMAND => InputKey::Command,
+        KVK_OPTION | KVK_RIGHT_OPTION => InputKey::Option,
+        _ => match layout::position_for_mac(keycode) {
+            Some(position) => InputKey::at_position(layout, position, keycode as u32),
+            None => InputKey::Other(keycode as u32),
+        },
+    }
+}
+
+fn modifiers(flags: CGEventFlags) -> Modifiers {
+    Modifiers {
+        shift: flags.contains(CGEventFlags::CGEventFlagShift),
+        command: flags.contains(CGEventFlags::CGEventFlagCommand),
+        control: flags.contains(CGEventFlags::CGEventFlagControl),
+    }
+}
+
+// Flag a modifier key sets while held
+fn modifier_flag(key: InputKey) -> CGEventFlags {
+    match key {
+        InputKey::Shift => CGEventFlags::CGEventFlagShift,
+        InputKey::Control => CGEventFlags::CGEventFlagControl,
+        InputKey::Command => CGEventFlags::CGEventFlagCommand,
+        InputKey::Option => CGEventFlags::CGEventFlagAlternate,
+        _ => CGEventFlags::empty(),
+    }
+}
+
+/// Install the tap on a thread of its own (call from the main thread, where the layout is read)
+pub fn start() -> Result<(), SuperspeedError> {
+    let layout = layout::current();
+    let (ready, started) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name("superspeed-event-tap".to_string())
+        .spawn(move || run_tap(layout, ready))
+        .map_err(|_| SuperspeedError::EventSource)?;
+    // The thread reports whether the tap could be created before it starts running
+    let run_loop = match started.recv() {
+        Ok(Ok(run_loop)) => run_loop,
+        Ok(Err(e)) => {
+            let _ = handle.join();
+            return Err(e);
+        }
+        Err(_) => return Err(SuperspeedError::EventSource),
+    };
+    *TAP_THREAD.lock().unwrap() = Some((run_loop, handle));
+    RUNNING.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// Body of the tap thread: install the tap on this thread's run loop and run it until stopped
+fn run_tap(layout: KeyboardLayout, ready: mpsc::Sender<Result<CFRunLoop, SuperspeedError>>) {
+    let tap = CGEventTap::new(
+        CGEventTapLocation::Session,
+        CGEventTapPlacement::HeadInsertEventTap,
+        CGEventTapOptions::Default,
+        vec![
+            CGEventType::KeyDown,
+            CGEventType::KeyUp,
+            CGEventType::FlagsChanged,
+            // Clicks can move the caret; observers need to know, nothing is ever swallowed
+            CGEventType::LeftMouseDown,
+            CGEventType::RightMouseDown,
+            CGEventType::OtherMouseDown,
+        ],
+        move |_proxy, event_type, event| {
+            handle(&layout, event_type, event);
+            // None keeps the original event; a swallowed one has been turned into a null event
+            None
+        },
+    );
+    // Tap creation only fails when the process may not monitor input
+    let Ok(tap) = tap else {
+        let _ = ready.send(Err(SuperspeedError::AccessibilityDenied));
+        return;
+    };
+    let Ok(source) = tap.mach_port.create_runloop_source(0) else {
+        let _ = ready.send(Err(SuperspeedError::EventSource));
+        return;
+    };
+
+    let run_loop = CFRunLoop::get_current();
+    // SAFETY: kCFRunLoopCommonModes is an immutable CFString constant
+    run_loop.add_source(&source, unsafe { kCFRunLoopCommonModes });
+    tap.enable();
+    TAP.with(|installed| *installed.borrow_mut() = Some(Installed { tap, source }));
+    if ready.send(Ok(run_loop.clone())).is_err() {
+        return;
+    }
+
+    // Returns once stop() stops this run loop
+    CFRunLoop::run_current();
+
+    if let Some(Installed { tap, source }) = TAP.with(|installed| installed.borrow_mut().take()) {
+        // SAFETY: as above
+        run_loop.remove_source(&source, unsafe { kCFRunLoopCommonModes });
+        // SAFETY: the port is valid until `tap` is dropped below
+        unsafe { CFMachPortInvalidate(tap.mach_port.as_concrete_TypeRef()) };
+    }
+}
+
+/// Remove the tap and wait for its thread to finish
+pub fn stop() {
+    let Some((run_loop, handle)) = TAP_THREAD.lock().unwrap().take() else {
+        return;
+    };
+    run_loop.stop();
+    if handle.join().is_err() {
+        log_warn!("The key listener thread panicked");
+    }
+    RUNNING.store(false, Ordering::SeqCst);
+}
+
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+// The character a key press types, if it is exactly one
+fn typed_char(event: &CGEvent) -> Option<char> {
+    let mut buf = [0u16; MAX_TYPED_UTF16];
+    let mut len = 0;
+    // SAFETY: buf holds max_length UTF-16 units and len receives the count written
+    unsafe { CGEventKeyboardGetUnicodeString(event.as_ptr() as *mut c_void, buf.len(), &mut len, buf.as_mut_ptr()) };
+    let mut chars = char::decode_utf16(buf[..len.min(buf.len())].iter().copied());
+    match (chars.next(), chars.next()) {
+        // Control characters come from Return, Tab, Delete and friends
+        (Some(Ok(c)), None) if !c.is_control() => Some(c),
+        _ => None,
+    }
+}
+
+fn handle(layout: &KeyboardLayout, event_type: CGEventType, event: &CGEvent) {
+    let down = match event_type {
+        CGEventType::LeftMouseDown | CGEventType::RightMouseDown | CGEventType::OtherMouseDown => {
+            listen::dispatch(&KeyEvent::press(InputKey::Pointer));
+            return;
+        }
+        CGEventType::KeyDown => true,
+        CGEventType::KeyUp => false,
+        CGEventType::FlagsChanged => true,
+        // macOS turns a slow tap off; turn it back on so keys keep flowing through the handler
+        CGEventType::TapDisabledByTimeout => {
+            log_warn!("Key listener timed out; re-enabling");
+            TAP.with(|installed| {
+                if let Some(Installed { tap, .. }) = installed.borrow().as_ref() {
+                    tap.enable();
+                }
+            });
+            return;
+        }
+        _ => return,
+    };
+
+    let keycode = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE) as u16;
+    let key = input_key(keycode, layout);
+    let flags = event.get_flags();
+    let mut key_event = KeyEvent {
+        key,
+        down,
+        repeat: event.get_integer_value_field(EventField::KEYBOARD_EVENT_AUTOREPEAT) != 0,
+        modifiers: modifiers(flags),
+        alt: flags.contains(CGEventFlags::CGEventFlagAlternate),
+        text: if down { typed_char(event) } else { None },
+        synthetic: event.get_integer_value_field(EventField::EVENT_SOURCE_USER_DATA) == SYNTHETIC_EVENT_TAG,
+    };
+    if let CGEventType::FlagsChanged = event_type {
+        // A modifier press or release: its own flag tells which, and isn't counted as held
+        let flag = modifier_flag(key);
+        key_event.down = flags.contains(flag) && !flag.is_empty();
+        key_event.modifiers = modifiers(flags - flag);
+        key_event.alt = flags.contains(CGEventFlags::CGEventFlagAlternate) && key != InputKey::Option;
+        key_event.text = None;
+    }
+
+    if listen::dispatch(&key_event) == Disposition::Swallow {
+        event.set_type(CGEventType::Null);
+    }
+}
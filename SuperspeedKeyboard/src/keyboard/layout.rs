@@ -0,0 +1,604 @@
+// Keyboard layout resolution
+// Shortcuts are posted as physical keys, and Cmd+V means "the key that types v
+// in the active layout" - on Dvorak that is the QWERTY period key, on AZERTY
+// the letters A/Q and Z/W swap. A KeyboardLayout maps characters to physical
+// positions (XKB key names such as "AB04"), which the raw-keycode backends
+// turn into macOS virtual keycodes or evdev codes. X11 needs none of this (the
+// XTest backend looks keysyms up in the server's mapping) and the Wayland
+// backend uploads its own keymap.
+
+use super::synth::Key;
+use crate::clock;
+use crate::error::SuperspeedError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Physical key positions: XKB key name, evdev code, macOS virtual keycode (ANSI/ISO)
+const POSITIONS: [(&str, u16, u16); 48] = [
+    ("TLDE", 41, 0x32),
+    ("AE01", 2, 0x12),
+    ("AE02", 3, 0x13),
+    ("AE03", 4, 0x14),
+    ("AE04", 5, 0x15),
+    ("AE05", 6, 0x17),
+    ("AE06", 7, 0x16),
+    ("AE07", 8, 0x1A),
+    ("AE08", 9, 0x1C),
+    ("AE09", 10, 0x19),
+    ("AE10", 11, 0x1D),
+    ("AE11", 12, 0x1B),
+    ("AE12", 13, 0x18),
+    ("AD01", 16, 0x0C),
+    ("AD02", 17, 0x0D),
+    ("AD03", 18, 0x0E),
+    ("AD04", 19, 0x0F),
+    ("AD05", 20, 0x11),
+    ("AD06", 21, 0x10),
+    ("AD07", 22, 0x20),
+    ("AD08", 23, 0x22),
+    ("AD09", 24, 0x1F),
+    ("AD10", 25, 0x23),
+    ("AD11", 26, 0x21),
+    ("AD12", 27, 0x1E),
+    ("AC01", 30, 0x00),
+    ("AC02", 31, 0x01),
+    ("AC03", 32, 0x02),
+    ("AC04", 33, 0x03),
+    ("AC05", 34, 0x05),
+    ("AC06", 35, 0x04),
+    ("AC07", 36, 0x26),
+    ("AC08", 37, 0x28),
+    ("AC09", 38, 0x25),
+    ("AC10", 39, 0x29),
+    ("AC11", 40, 0x27),
+    ("BKSL", 43, 0x2A),
+    ("LSGT", 86, 0x0A),
+    ("AB01", 44, 0x06),
+    ("AB02", 45, 0x07),
+    ("AB03", 46, 0x08),
+    ("AB04", 47, 0x09),
+    ("AB05", 48, 0x0B),
+    ("AB06", 49, 0x2D),
+    ("AB07", 50, 0x2E),
+    ("AB08", 51, 0x2B),
+    ("AB09", 52, 0x2F),
+    ("AB10", 53, 0x2C),
+];
+
+/// evdev key code of a position
+pub fn evdev_code(position: &str) -> Option<u16> {
+    POSITIONS.iter().find(|(name, _, _)| *name == position).map(|&(_, code, _)| code)
+}
+
+/// macOS virtual keycode of a position
+pub fn mac_keycode(position: &str) -> Option<u16> {
+    POSITIONS.iter().find(|(name, _, _)| *name == position).map(|&(_, _, code)| code)
+}
+
+/// Position of an evdev key code
+pub fn position_for_evdev(code: u16) -> Option<&'static str> {
+    POSITIONS.iter().find(|&&(_, evdev, _)| evdev == code).map(|&(name, _, _)| name)
+}
+
+/// Position of a macOS virtual keycode
+pub fn position_for_mac(keycode: u16) -> Option<&'static str> {
+    POSITIONS.iter().find(|&&(_, _, mac)| mac == keycode).map(|&(name, _, _)| name)
+}
+
+/// Every position a layout can assign characters to
+pub fn positions() -> impl Iterator<Item = &'static str> {
+    POSITIONS.iter().map(|&(name, _, _)| name)
+}
+
+/// Letter a shortcut key stands for (None for keys that don't move between layouts)
+pub fn shortcut_char(key: Key) -> Option<char> {
+    match key {
+        Key::V => Some('v'),
+        Key::C => Some('c'),
+        Key::U => Some('u'),
+        _ => None,
+    }
+}
+
+// Where the shortcut letters sit on a US keyboard, used when a layout has no such letter
+fn qwerty_position(key: Key) -> Option<&'static str> {
+    match key {
+        Key::V => Some("AB04"),
+        Key::C => Some("AB03"),
+        Key::U => Some("AD07"),
+        _ => None,
+    }
+}
+
+/// Characters on one physical key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutKey {
+    pub position: &'static str,
+    pub base: Option<char>,
+    pub shifted: Option<char>,
+    /// Character while Command is held, where it differs (macOS "Dvorak - QWERTY ⌘")
+    pub command: Option<char>,
+}
+
+/// Character -> physical key table for one keyboard layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardLayout {
+    name: String,
+    keys: Vec<LayoutKey>,
+}
+
+// Bundled layout fixtures, by the names superspeed_set_keyboard_layout accepts
+const FIXTURES: [(&str, &str); 4] = [
+    ("us", include_str!("layouts/us.txt")),
+    ("us-dvorak", include_str!("layouts/us-dvorak.txt")),
+    ("us-colemak", include_str!("layouts/us-colemak.txt")),
+    ("fr", include_str!("layouts/fr.txt")),
+];
+
+impl KeyboardLayout {
+    pub fn new(name: &str, keys: Vec<LayoutKey>) -> Self {
+        KeyboardLayout { name: name.to_string(), keys }
+    }
+
+    /// US QWERTY, the layout the crate assumed before layouts were resolved
+    pub fn us() -> Self {
+        KeyboardLayout::fixture("us").expect("us layout fixture is bundled")
+    }
+
+    /// A bundled layout: "us", "us-dvorak", "us-colemak" or "fr"
+    pub fn fixture(name: &str) -> Option<Self> {
+        let (_, source) = FIXTURES.iter().find(|(fixture, _)| *fixture == name)?;
+        Some(KeyboardLayout::parse(name, source).expect("bundled layout fixtures are valid"))
+    }
+
+    /// Names of the bundled layouts
+    pub fn fixture_names() -> impl Iterator<Item = &'static str> {
+        FIXTURES.iter().map(|&(name, _)| name)
+    }
+
+    /// Parse the fixture format: each line names its first position, then one token per key
+    /// with the unshifted character and optionally the shifted one ("vV", "²"). `#` starts a comment.
+    pub fn parse(name: &str, source: &str) -> Result<Self, SuperspeedError> {
+        let invalid = |line: usize, detail: String| {
+            SuperspeedError::Unsupported(format!("Keyboard layout {} line {}: {}", name, line + 1, detail))
+        };
+        let mut keys: Vec<LayoutKey> = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let first = tokens.next().unwrap_or_default();
+            let start = POSITIONS
+                .iter()
+                .position(|(position, _, _)| *position == first)
+                .ok_or_else(|| invalid(number, format!("unknown key position {}", first)))?;
+
+            for (offset, token) in tokens.enumerate() {
+                let (position, _, _) = POSITIONS
+                    .get(start + offset)
+                    .filter(|(position, _, _)| offset == 0 || position[..2] == first[..2])
+                    .ok_or_else(|| invalid(number, format!("too many keys after {}", first)))?;
+                let mut chars = token.chars();
+                let (base, shifted) = (chars.next(), chars.next());
+                if chars.next().is_some() {
+                    return Err(invalid(number, format!("\"{}\" has more than two characters", token)));
+                }
+                keys.retain(|key| key.position != *position);
+                keys.push(LayoutKey { position, base, shifted, command: None });
+            }
+        }
+        Ok(KeyboardLayout::new(name, keys))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn keys(&self) -> &[LayoutKey] {
+        &self.keys
+    }
+
+    /// Characters on the key at `position`
+    pub fn key_at(&self, position: &str) -> Option<&LayoutKey> {
+        self.keys.iter().find(|key| key.position == position)
+    }
+
+    /// Position that types `c`, and whether Shift is needed (unshifted keys are preferred)
+    pub fn position_of(&self, c: char) -> Option<(&'static str, bool)> {
+        self.keys
+            .iter()
+            .find(|key| key.base == Some(c))
+            .map(|key| (key.position, false))
+            .or_else(|| self.keys.iter().find(|key| key.shifted == Some(c)).map(|key| (key.position, true)))
+    }
+
+    /// Position to press for `key` with Command/Control held
+    /// Letters are found by what the layout types (its Command level first); keys the layout
+    /// has no letter for, like V on a Cyrillic layout, stay at their US position.
+    pub fn shortcut_position(&self, key: Key) -> Option<&'static str> {
+        let letter = shortcut_char(key)?;
+        self.keys
+            .iter()
+            .find(|layout_key| layout_key.command == Some(letter))
+            .map(|layout_key| layout_key.position)
+            .or_else(|| self.position_of(letter).map(|(position, _)| position))
+            .or_else(|| qwerty_position(key))
+    }
+}
+
+// Layout chosen by the host, used instead of the system's
+static OVERRIDE: Mutex<Option<KeyboardLayout>> = Mutex::new(None);
+
+// Set once the US fallback has been warned about, so re-resolving doesn't repeat it
+static FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
+
+// How long a running listener or device keeps a resolved layout before reading it again
+const LIVE_REFRESH: Duration = Duration::from_secs(2);
+
+/// Use `layout` for every backend that posts raw keycodes (None follows the system again)
+pub fn set_layout(layout: Option<KeyboardLayout>) {
+    *OVERRIDE.lock().unwrap() = layout;
+}
+
+/// The layout shortcuts should be resolved against: the host's choice, else the system's
+pub fn current() -> KeyboardLayout {
+    if let Some(layout) = OVERRIDE.lock().unwrap().clone() {
+        return layout;
+    }
+    system().unwrap_or_else(|e| {
+        if FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+            log_debug!("Keyboard layout unknown, assuming US: {}", e);
+        } else {
+            log_warn!("Keyboard layout unknown, assuming US (set one with superspeed_set_keyboard_layout): {}", e);
+        }
+        KeyboardLayout::us()
+    })
+}
+
+/// current(), re-read every few seconds so a long-running listener or device follows
+/// layout switches made after it started
+#[derive(Debug, Clone)]
+pub struct LiveLayout {
+    layout: KeyboardLayout,
+    // None when pinned to a fixed layout
+    read_at: Option<Instant>,
+}
+
+impl LiveLayout {
+    pub fn new() -> Self {
+        LiveLayout { layout: current(), read_at: Some(clock::now()) }
+    }
+
+    /// Always `layout`, never re-read
+    pub fn fixed(layout: KeyboardLayout) -> Self {
+        LiveLayout { layout, read_at: None }
+    }
+
+    /// The layout, re-reading it first if the last read is stale
+    pub fn get(&mut self) -> &KeyboardLayout {
+        if let Some(read_at) = self.read_at {
+            let now = clock::now();
+            if now.saturating_duration_since(read_at) >= LIVE_REFRESH {
+                self.layout = current();
+                self.read_at = Some(now);
+            }
+        }
+        &self.layout
+    }
+}
+
+impl Default for LiveLayout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Active layout read from the system (TIS/UCKeyTranslate on macOS, the X server's mapping or the XKB settings on Linux)
+#[cfg(target_os = "macos")]
+pub fn system() -> Result<KeyboardLayout, SuperspeedError> {
+    macos::current_layout()
+}
+
+/// Active layout read from the system (TIS/UCKeyTranslate on macOS, the X server's mapping or the XKB settings on Linux)
+#[cfg(target_os = "linux")]
+pub fn system() -> Result<KeyboardLayout, SuperspeedError> {
+    // The X server (XWayland too) holds the keymap in effect right now; the configured
+    // names below only say what the session started with
+    if std::env::var_os("DISPLAY").is_some() {
+        match super::x11::current_layout() {
+            Ok(layout) if !layout.keys().is_empty() => return Ok(layout),
+            Ok(_) => log_warn!("X keyboard mapping has no character keys, using the configured layout"),
+            Err(e) => log_warn!("Cannot read the X keyboard mapping ({}), using the configured layout", e),
+        }
+    }
+    let (layout, variant) = xkb::configured_layout()?;
+    let name = xkb::fixture_for(&layout, &variant).ok_or_else(|| {
+        SuperspeedError::Unsupported(format!("No bundled keyboard layout for XKB layout {} variant {:?}", layout, variant))
+    })?;
+    Ok(KeyboardLayout::fixture(name).expect("fixture_for only names bundled layouts"))
+}
+
+/// Active layout read from the system (TIS/UCKeyTranslate on macOS, the X server's mapping or the XKB settings on Linux)
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub fn system() -> Result<KeyboardLayout, SuperspeedError> {
+    Err(SuperspeedError::Unsupported("Keyboard layout detection is not available on this platform".to_string()))
+}
+
+#[cfg(target_os = "linux")]
+pub mod xkb {
+    // The evdev codes a uinput keyboard sends are turned into characters by the
+    // session's XKB keymap, configured through the same RMLVO names everywhere:
+    // XKB_DEFAULT_* for Wayland compositors, /etc/default/keyboard (Debian) and
+    // /etc/vconsole.conf (systemd) for the rest. Only consulted when no X server
+    // can be asked for its live mapping (pure Wayland, the console).
+
+    use crate::error::SuperspeedError;
+    use std::env;
+    use std::fs;
+
+    const CONFIG_FILES: [&str; 2] = ["/etc/default/keyboard", "/etc/vconsole.conf"];
+
+    /// First configured XKB layout and its variant ("" when none)
+    pub fn configured_layout() -> Result<(String, String), SuperspeedError> {
+        if let Some(layout) = env::var("XKB_DEFAULT_LAYOUT").ok().filter(|value| !value.is_empty()) {
+            let variant = env::var("XKB_DEFAULT_VARIANT").unwrap_or_default();
+            return Ok(first_group(&layout, &variant));
+        }
+        for path in CONFIG_FILES {
+            if let Ok(contents) = fs::read_to_string(path) {
+                if let Some(found) = parse_config(&contents) {
+                    return Ok(found);
+                }
+            }
+        }
+        Err(SuperspeedError::Unsupported("No XKB layout configured".to_string()))
+    }
+
+    /// XKBLAYOUT / XKBVARIANT from a shell-style config file
+    pub fn parse_config(contents: &str) -> Option<(String, String)> {
+        let value = |key: &str| {
+            contents.lines().find_map(|line| {
+                let (name, value) = line.trim().split_once('=')?;
+                (name.trim() == key).then(|| value.trim().trim_matches('"').to_string())
+            })
+        };
+        let layout = value("XKBLAYOUT").filter(|layout| !layout.is_empty())?;
+        Some(first_group(&layout, &value("XKBVARIANT").unwrap_or_default()))
+    }
+
+    // "us,ru" with "dvorak," -> ("us", "dvorak"): the first group is the one in effect at login
+    fn first_group(layouts: &str, variants: &str) -> (String, String) {
+        let first = |list: &str| list.split(',').next().unwrap_or_default().trim().to_string();
+        (first(layouts), first(variants))
+    }
+
+    /// Bundled fixture matching an XKB layout and variant
+    pub fn fixture_for(layout: &str, variant: &str) -> Option<&'static str> {
+        match (layout, variant) {
+            ("us", "" | "basic") => Some("us"),
+            ("us", "dvorak") | ("dvorak", "") => Some("us-dvorak"),
+            ("us", "colemak") => Some("us-colemak"),
+            ("fr", "" | "basic" | "latin9" | "oss") => Some("fr"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    // The current keyboard layout's 'uchr' data, queried per physical key with
+    // UCKeyTranslate for the plain, Shift and Command levels.
+
+    use super::{KeyboardLayout, LayoutKey, POSITIONS};
+    use crate::error::SuperspeedError;
+    use core_foundation::base::{CFRelease, CFTypeRef};
+    use core_foundation::data::{CFDataGetBytePtr, CFDataRef};
+    use std::ffi::c_void;
+
+    // Carbon Events.h / UCKeyTranslate constants
+    const UC_KEY_ACTION_DISPLAY: u16 = 3;
+    const UC_KEY_TRANSLATE_NO_DEAD_KEYS: u32 = 1 << 0;
+    // (shiftKey >> 8) and (cmdKey >> 8)
+    const SHIFT_STATE: u32 = 0x02;
+    const COMMAND_STATE: u32 = 0x01;
+
+    #[link(name = "Carbon", kind = "framework")]
+    extern "C" {
+        static kTISPropertyUnicodeKeyLayoutData: CFTypeRef;
+        fn TISCopyCurrentKeyboardLayoutInputSource() -> CFTypeRef;
+        fn TISGetInputSourceProperty(source: CFTypeRef, key: CFTypeRef) -> CFTypeRef;
+        fn LMGetKbdType() -> u8;
+        fn UCKeyTranslate(
+            layout: *const c_void,
+            virtual_key_code: u16,
+            key_action: u16,
+            modifier_key_state: u32,
+            keyboard_type: u32,
+            key_translate_options: u32,
+            dead_key_state: *mut u32,
+            max_string_length: usize,
+            actual_string_length: *mut usize,
+            unicode_string: *mut u16,
+        ) -> i32;
+    }
+
+    /// Layout of the current keyboard input source
+    pub fn current_layout() -> Result<KeyboardLayout, SuperspeedError> {
+        unsafe {
+            let source = TISCopyCurrentKeyboardLayoutInputSource();
+            if source.is_null() {
+                return Err(SuperspeedError::Unsupported("No current keyboard layout input source".to_string()));
+            }
+            let data = TISGetInputSourceProperty(source, kTISPropertyUnicodeKeyLayoutData) as CFDataRef;
+            let result = if data.is_null() {
+                Err(SuperspeedError::Unsupported("Keyboard layout has no Unicode key layout data".to_string()))
+            } else {
+                Ok(translate_all(CFDataGetBytePtr(data) as *const c_void))
+            };
+            CFRelease(source);
+            result
+        }
+    }
+
+    unsafe fn translate_all(layout: *const c_void) -> KeyboardLayout {
+        let keyboard_type = LMGetKbdType() as u32;
+        let translate = |keycode: u16, state: u32| -> Option<char> {
+            let mut dead_key_state = 0u32;
+            let mut length = 0usize;
+            let mut buffer = [0u16; 4];
+            let status = UCKeyTranslate(
+                layout,
+                keycode,
+                UC_KEY_ACTION_DISPLAY,
+                state,
+                keyboard_type,
+                UC_KEY_TRANSLATE_NO_DEAD_KEYS,
+                &mut dead_key_state,
+                buffer.len(),
+                &mut length,
+                buffer.as_mut_ptr(),
+            );
+            if status != 0 || length == 0 {
+                return None;
+            }
+            char::decode_utf16(buffer[..length].iter().copied()).next()?.ok()
+        };
+
+        let keys = POSITIONS
+            .iter()
+            .map(|&(position, _, keycode)| {
+                let base = translate(keycode, 0);
+                let command = translate(keycode, COMMAND_STATE).filter(|&c| Some(c) != base);
+                LayoutKey { position, base, shifted: translate(keycode, SHIFT_STATE), command }
+            })
+            .collect();
+        KeyboardLayout::new("system", keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> KeyboardLayout {
+        KeyboardLayout::fixture(name).unwrap()
+    }
+
+    fn shortcuts(layout: &KeyboardLayout) -> [Option<&'static str>; 3] {
+        [Key::V, Key::C, Key::U].map(|key| layout.shortcut_position(key))
+    }
+
+    fn parse_error(source: &str) -> String {
+        match KeyboardLayout::parse("broken", source) {
+            Err(SuperspeedError::Unsupported(message)) => message,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_fixture_parses() {
+        for name in KeyboardLayout::fixture_names() {
+            let layout = fixture(name);
+            assert_eq!(layout.name(), name);
+            assert!(layout.keys().len() >= 47, "{} has {} keys", name, layout.keys().len());
+        }
+    }
+
+    #[test]
+    fn shortcut_letters_on_us_qwerty() {
+        assert_eq!(shortcuts(&fixture("us")), [Some("AB04"), Some("AB03"), Some("AD07")]);
+    }
+
+    #[test]
+    fn shortcut_letters_on_dvorak() {
+        // V sits where US has the period key
+        assert_eq!(shortcuts(&fixture("us-dvorak")), [Some("AB09"), Some("AD08"), Some("AC04")]);
+        assert_eq!(evdev_code("AB09"), Some(52));
+    }
+
+    #[test]
+    fn shortcut_letters_on_colemak() {
+        assert_eq!(shortcuts(&fixture("us-colemak")), [Some("AB04"), Some("AB03"), Some("AD08")]);
+    }
+
+    #[test]
+    fn shortcut_letters_on_azerty() {
+        let fr = fixture("fr");
+        assert_eq!(shortcuts(&fr), [Some("AB04"), Some("AB03"), Some("AD07")]);
+        // The letters that do move
+        assert_eq!(fr.position_of('a'), Some(("AD01", false)));
+        assert_eq!(fr.position_of('q'), Some(("AC01", false)));
+        assert_eq!(fr.position_of('z'), Some(("AD02", false)));
+        assert_eq!(fr.position_of('w'), Some(("AB01", false)));
+        assert_eq!(fr.position_of('m'), Some(("AC10", false)));
+        // Digits need Shift
+        assert_eq!(fr.position_of('1'), Some(("AE01", true)));
+        assert_eq!(fr.key_at("TLDE").map(|key| (key.base, key.shifted)), Some((Some('²'), None)));
+    }
+
+    #[test]
+    fn command_level_wins_over_the_typed_letter() {
+        // macOS "Dvorak - QWERTY ⌘": types Dvorak, but Command+V is the QWERTY V key
+        let mut keys = fixture("us-dvorak").keys().to_vec();
+        for key in &mut keys {
+            key.command = fixture("us").key_at(key.position).and_then(|us| us.base).filter(|&c| Some(c) != key.base);
+        }
+        let layout = KeyboardLayout::new("dvorak-qwerty", keys);
+        assert_eq!(shortcuts(&layout), [Some("AB04"), Some("AB03"), Some("AD07")]);
+    }
+
+    #[test]
+    fn letters_missing_from_the_layout_stay_at_their_us_position() {
+        let cyrillic = KeyboardLayout::parse("ru", "AB01 яЯ чЧ сС мМ").unwrap();
+        assert_eq!(shortcuts(&cyrillic), [Some("AB04"), Some("AB03"), Some("AD07")]);
+        assert_eq!(KeyboardLayout::us().shortcut_position(Key::Return), None);
+    }
+
+    #[test]
+    fn later_lines_replace_earlier_keys() {
+        let layout = KeyboardLayout::parse("remapped", "AB01 zZ xX\n# swap\nAB01 xX").unwrap();
+        assert_eq!(layout.key_at("AB01").and_then(|key| key.base), Some('x'));
+        assert_eq!(layout.keys().len(), 2);
+    }
+
+    #[test]
+    fn malformed_fixtures_are_rejected_with_the_line() {
+        assert_eq!(parse_error("# comment\nXX01 aA"), "Keyboard layout broken line 2: unknown key position XX01");
+        // AB10 is the last key of the bottom row
+        assert!(parse_error("AB09 .> /? \\|").contains("too many keys after AB09"));
+        // Rows don't run on into the next one
+        assert!(parse_error("AD12 ]} aA").contains("too many keys after AD12"));
+        assert!(parse_error("AC01 aAb").contains("\"aAb\" has more than two characters"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn xkb_config_picks_the_first_group() {
+        let config = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"us,ru\"\nXKBVARIANT=\"dvorak,\"\n";
+        let (layout, variant) = xkb::parse_config(config).unwrap();
+        assert_eq!((layout.as_str(), variant.as_str()), ("us", "dvorak"));
+        assert_eq!(xkb::fixture_for(&layout, &variant), Some("us-dvorak"));
+        assert_eq!(xkb::fixture_for("de", ""), None);
+        assert_eq!(xkb::parse_config("XKBLAYOUT=\"\""), None);
+    }
+
+    #[test]
+    fn live_layouts_follow_switches() {
+        let _globals = crate::sim::exclusive();
+        let clock = crate::sim::install_virtual_clock();
+        set_layout(Some(fixture("us")));
+        let mut live = LiveLayout::new();
+        let mut pinned = LiveLayout::fixed(fixture("us"));
+
+        set_layout(Some(fixture("us-dvorak")));
+        assert_eq!(live.get().name(), "us");
+        clock.advance(LIVE_REFRESH);
+        assert_eq!(live.get().name(), "us-dvorak");
+        assert_eq!(pinned.get().name(), "us");
+        set_layout(None);
+    }
+}
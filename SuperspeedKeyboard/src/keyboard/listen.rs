@@ -0,0 +1,296 @@
+// Global key listening
+// Lets the crate watch the user's own keystrokes and drop the ones it handles
+// itself. Backends turn native events into KeyEvents and ask a single handler
+// whether each one passes through: a CGEventTap on macOS, grabbed evdev
+// devices re-emitted through uinput on Linux. Events the crate posted itself
+// are marked synthetic so the handler can let them through untouched.
+
+use super::layout::KeyboardLayout;
+use super::synth::Modifiers;
+use crate::error::SuperspeedError;
+use std::sync::Mutex;
+
+/// Tag stored in the user-data field of events the crate posts (macOS), "SSKB" in ASCII
+pub const SYNTHETIC_EVENT_TAG: i64 = 0x5353_4B42;
+
+/// A key on the user's keyboard, as far as the listener can tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputKey {
+    Tab,
+    Escape,
+    Return,
+    Space,
+    Backspace,
+    /// Forward delete
+    Delete,
+    LeftArrow,
+    RightArrow,
+    UpArrow,
+    DownArrow,
+    Shift,
+    Control,
+    /// Command on macOS, Super/Meta elsewhere
+    Command,
+    /// Option on macOS, Alt elsewhere
+    Option,
+    /// Key in the character block, by the lowercase character it types in the active layout
+    Char(char),
+    /// Any other key, by its native code
+    Other(u32),
+    /// A mouse button went down (the caret or focus may have moved)
+    Pointer,
+}
+
+impl InputKey {
+    /// Whether this is a modifier key on its own
+    pub fn is_modifier(self) -> bool {
+        matches!(self, InputKey::Shift | InputKey::Control | InputKey::Command | InputKey::Option)
+    }
+
+    /// Key named in a hotkey string: "tab", "escape", "space", "left", ... or a single character
+    pub fn from_name(name: &str) -> Option<InputKey> {
+        let key = match name {
+            "tab" => InputKey::Tab,
+            "esc" | "escape" => InputKey::Escape,
+            "return" | "enter" => InputKey::Return,
+            "space" => InputKey::Space,
+            "backspace" => InputKey::Backspace,
+            "delete" => InputKey::Delete,
+            "left" => InputKey::LeftArrow,
+            "right" => InputKey::RightArrow,
+            "up" => InputKey::UpArrow,
+            "down" => InputKey::DownArrow,
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if !c.is_whitespace() => InputKey::Char(c.to_lowercase().next().unwrap_or(c)),
+                    _ => return None,
+                }
+            }
+        };
+        Some(key)
+    }
+
+    /// Character-block key at `position` in `layout`, or Other(`code`) if the layout leaves it empty
+    pub fn at_position(layout: &KeyboardLayout, position: &str, code: u32) -> InputKey {
+        match layout.key_at(position).and_then(|key| key.base) {
+            Some(c) => InputKey::Char(c.to_lowercase().next().unwrap_or(c)),
+            None => InputKey::Other(code),
+        }
+    }
+}
+
+/// One key press or release seen by the listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: InputKey,
+    /// Press (true) or release (false)
+    pub down: bool,
+    /// Auto-repeat of a held key
+    pub repeat: bool,
+    /// Modifiers held at the time, not counting `key` itself
+    pub modifiers: Modifiers,
+    /// Option (macOS) or Alt held
+    pub alt: bool,
+    /// Character the press types into a text field, when the backend can tell
+    pub text: Option<char>,
+    /// Posted by the crate, not typed by the user
+    pub synthetic: bool,
+}
+
+impl KeyEvent {
+    /// A plain press by the user
+    pub fn press(key: InputKey) -> Self {
+        KeyEvent { key, down: true, repeat: false, modifiers: Modifiers::NONE, alt: false, text: None, synthetic: false }
+    }
+
+    /// The user typing `c` on a character key
+    pub fn typed(c: char) -> Self {
+        KeyEvent { text: Some(c), ..KeyEvent::press(InputKey::Char(c.to_lowercase().next().unwrap_or(c))) }
+    }
+
+    /// A plain release by the user
+    pub fn release(key: InputKey) -> Self {
+        KeyEvent { down: false, ..KeyEvent::press(key) }
+    }
+}
+
+/// What the listener does with an event after the handler has seen it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// Deliver it to the focused application
+    Pass,
+    /// Drop it; the application never sees the key
+    Swallow,
+}
+
+/// Decides each event's fate; runs on the listener's thread and must return quickly
+pub type Handler = Box<dyn FnMut(&KeyEvent) -> Disposition + Send>;
+
+/// Sees every event after the handler has decided its fate (bookkeeping only, must return quickly)
+pub type Observer = fn(&KeyEvent, Disposition);
+
+/// Work scheduled from a handler to run outside the listener
+pub type Task = Box<dyn FnOnce() + Send>;
+
+static HANDLER: Mutex<Option<Handler>> = Mutex::new(None);
+static OBSERVERS: Mutex<Vec<Observer>> = Mutex::new(Vec::new());
+
+/// Start listening, sending every key event to `handler`
+/// macOS: call from the main thread; the tap gets a thread of its own and needs
+/// Accessibility (or Input Monitoring) permission. Linux: needs read access to
+/// /dev/input/event* and write access to /dev/uinput.
+pub fn start(handler: Handler) -> Result<(), SuperspeedError> {
+    if is_running() {
+        return Err(SuperspeedError::InvalidState("Key listener is already running".to_string()));
+    }
+    *HANDLER.lock().unwrap() = Some(handler);
+    if let Err(e) = start_platform() {
+        *HANDLER.lock().unwrap() = None;
+        return Err(e);
+    }
+    log_info!("Key listener started");
+    Ok(())
+}
+
+/// Stop listening and release the keyboard (no-op if not running)
+pub fn stop() {
+    if is_running() {
+        stop_platform();
+        log_info!("Key listener stopped");
+    }
+    *HANDLER.lock().unwrap() = None;
+}
+
+/// Whether a listener is installed
+pub fn is_running() -> bool {
+    running_platform()
+}
+
+/// Also show every event to `observer`, for as long as the process runs
+pub fn add_observer(observer: Observer) {
+    OBSERVERS.lock().unwrap().push(observer);
+}
+
+/// Ask the handler what to do with `event`, then tell the observers (called by the backends)
+pub fn dispatch(event: &KeyEvent) -> Disposition {
+    let disposition = match HANDLER.lock().unwrap().as_mut() {
+        Some(handler) => handler(event),
+        None => Disposition::Pass,
+    };
+    // Keystrokes are user text: log what kind of key it was, never which character
+    // (even redact()'s hash gives a single character away)
+    log_trace!("Key {} {}: {:?}", describe(&event.key), if event.down { "down" } else { "up" }, disposition);
+    let observers = OBSERVERS.lock().unwrap().clone();
+    for observer in observers {
+        observer(event, disposition);
+    }
+    disposition
+}
+
+// Key name for logs, with character keys left anonymous
+fn describe(key: &InputKey) -> String {
+    match key {
+        InputKey::Char(_) => "character".to_string(),
+        key => format!("{:?}", key),
+    }
+}
+
+/// Run `task` after the current event has been delivered
+/// macOS: on the main queue, so ghost text calls stay on the main thread while the
+/// tap keeps delivering the keys they post. Linux: on a worker thread, one task at a time.
+pub fn defer(task: Task) {
+    defer_platform(task);
+}
+
+#[cfg(target_os = "macos")]
+fn start_platform() -> Result<(), SuperspeedError> {
+    super::event_tap::start()
+}
+
+#[cfg(target_os = "macos")]
+fn stop_platform() {
+    super::event_tap::stop()
+}
+
+#[cfg(target_os = "macos")]
+fn running_platform() -> bool {
+    super::event_tap::is_running()
+}
+
+#[cfg(target_os = "macos")]
+fn defer_platform(task: Task) {
+    use std::ffi::c_void;
+
+    extern "C" {
+        static _dispatch_main_q: c_void;
+        fn dispatch_async_f(queue: *const c_void, context: *mut c_void, work: extern "C" fn(*mut c_void));
+    }
+
+    extern "C" fn run(context: *mut c_void) {
+        // SAFETY: context is the Box<Task> leaked below, run exactly once
+        let task = unsafe { Box::from_raw(context as *mut Task) };
+        task();
+    }
+
+    let context = Box::into_raw(Box::new(task)) as *mut c_void;
+    // SAFETY: _dispatch_main_q is the main queue object (dispatch_get_main_queue() is a macro for it)
+    unsafe { dispatch_async_f(std::ptr::addr_of!(_dispatch_main_q), context, run) };
+}
+
+#[cfg(target_os = "linux")]
+fn start_platform() -> Result<(), SuperspeedError> {
+    super::evdev::start()
+}
+
+#[cfg(target_os = "linux")]
+fn stop_platform() {
+    super::evdev::stop()
+}
+
+#[cfg(target_os = "linux")]
+fn running_platform() -> bool {
+    super::evdev::is_running()
+}
+
+#[cfg(target_os = "linux")]
+fn defer_platform(task: Task) {
+    use std::sync::mpsc::{self, Sender};
+
+    // Started on first use and kept for the life of the process
+    static WORKER: Mutex<Option<Sender<Task>>> = Mutex::new(None);
+
+    let mut worker = WORKER.lock().unwrap();
+    let sender = worker.get_or_insert_with(|| {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        std::thread::Builder::new()
+            .name("superspeed-listen-tasks".to_string())
+            .spawn(move || receiver.into_iter().for_each(|task| task()))
+            .expect("spawning the listener task thread");
+        sender
+    });
+    if let Err(mpsc::SendError(task)) = sender.send(task) {
+        // Only if the worker panicked; run inline rather than lose the task
+        *worker = None;
+        drop(worker);
+        task();
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn start_platform() -> Result<(), SuperspeedError> {
+    Err(SuperspeedError::Unsupported("Key listening is not available on this platform".to_string()))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn stop_platform() {}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn running_platform() -> bool {
+    false
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn defer_platform(task: Task) {
+    task();
+}
@@ -1,111 +1,281 @@
-use cocoa::appkit::{NSPasteboard, NSPasteboardTypeString};
-use cocoa::base::nil;
-use cocoa::foundation::{NSAutoreleasePool, NSString};
-use core_graphics::event::{CGEvent, CGEventFlags, CGEventTapLocation};
-use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
-use std::ffi::CStr;
-use std::thread;
-use std::time::Duration;
+use super::clipboard::{self, Clipboard, ClipboardMarkers, ClipboardSnapshot};
+use super::synth::{self, Chord};
+use crate::error::SuperspeedError;
+use crate::logging;
+use crate::timing::{sleep_ms, TimingProfile};
+
+/// Clipboard displaced by ghost text, kept until the session ends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedClipboard {
+    /// What the user had on the clipboard before we pasted
+    pub snapshot: ClipboardSnapshot,
+    /// Change count while the clipboard held our ghost text
+    pub change_count: i64,
+}
+
+impl SavedClipboard {
+    /// Pair `snapshot` with the clipboard's current change count
+    pub fn new(snapshot: ClipboardSnapshot) -> Result<Self, SuperspeedError> {
+        let change_count = clipboard::with_backend(|pasteboard| pasteboard.change_count())?;
+        Ok(SavedClipboard { snapshot, change_count })
+    }
+}
+
+/// What happened to the saved clipboard when a ghost session ended
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardRestore {
+    /// The session never touched the clipboard (or has not ended yet)
+    NotNeeded = 0,
+    /// The user's clipboard was put back
+    Restored = 1,
+    /// The user copied something while the ghost text was pending; their copy was kept
+    SkippedUserChange = 2,
+}
 
 /// Insert text via clipboard and return old clipboard for later restore
-pub fn insert_via_clipboard_and_save(text: &str) -> Result<Option<String>, String> {
-    unsafe {
-        // Create an autorelease pool for memory management
-        let _pool = NSAutoreleasePool::new(nil);
-
-        eprintln!("📋 Step 1: Getting pasteboard");
-        let pasteboard = NSPasteboard::generalPasteboard(nil);
-
-        // Store current clipboard contents to return for later restore
-        eprintln!("📋 Step 2: Saving old clipboard");
-        let old_contents = pasteboard.stringForType(NSPasteboardTypeString);
-        let old_clipboard_string = if old_contents != nil {
-            let c_str = cocoa::foundation::NSString::UTF8String(old_contents);
-            Some(CStr::from_ptr(c_str).to_string_lossy().into_owned())
-        } else {
-            None
-        };
+/// `markers` tag the text so clipboard managers leave it out of their history;
+/// `paste` is the target app's paste chord (its profile's, usually Cmd/Ctrl+V).
+pub fn insert_via_clipboard_and_save(
+    text: &str,
+    markers: ClipboardMarkers,
+    paste: Chord,
+    timing: &TimingProfile,
+) -> Result<ClipboardSnapshot, SuperspeedError> {
+    let old_clipboard = set_clipboard_and_save(text, markers, timing)?;
+
+    paste_shortcut(paste, timing)?;
+
+    // Return old clipboard for later restore (don't restore now!)
+    log_debug!("Clipboard kept with AI suggestion (will restore on Tab/Esc)");
+
+    Ok(old_clipboard)
+}
+
+/// Simulate the paste chord
+fn paste_shortcut(chord: Chord, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| {
+        // Post the events with proper delays
+        log_trace!("Step 5: Posting {:?} (key down)", chord);
+        s.key_down(chord.key, chord.modifiers)?;
+        sleep_ms(timing.key_event_ms);  // Delay after posting
+
+        log_trace!("Step 6: Posting {:?} (key up)", chord);
+        s.key_up(chord.key, chord.modifiers)?;
+        sleep_ms(timing.key_event_ms);  // Delay after posting
+
+        log_debug!("Paste chord posted successfully");
+        Ok(())
+    })
+}
+
+/// Save the current clipboard, then set it to `text` and verify it took
+fn set_clipboard_and_save(text: &str, markers: ClipboardMarkers, timing: &TimingProfile) -> Result<ClipboardSnapshot, SuperspeedError> {
+    clipboard::with_backend(|pasteboard| {
+        // Store every item and flavor (images, files, rich text) for later restore
+        log_trace!("Step 1: Saving old clipboard ({})", pasteboard.name());
+        let old_clipboard = pasteboard.snapshot()?;
+        log_trace!("Saved clipboard: {}", old_clipboard);
 
         // Clear the pasteboard and set our text
-        eprintln!("📋 Step 3: Clearing clipboard");
-        pasteboard.clearContents();
+        log_trace!("Step 2: Clearing clipboard");
+        pasteboard.clear()?;
+        // Clearing bumps the count itself, so only a change after this is our write
+        let before = pasteboard.change_count()?;
 
-        eprintln!("📋 Step 4: Setting new text to clipboard: '{}'", text);
-        let ns_string = NSString::alloc(nil).init_str(text);
-        pasteboard.setString_forType(ns_string, NSPasteboardTypeString);
+        log_trace!("Step 3: Setting new text to clipboard: {} ({:?})", logging::redact(text), markers);
+        pasteboard.write_marked(text, markers)?;
 
         // Verify clipboard was actually set by reading it back
-        eprintln!("📋 Step 5: Verifying clipboard was set");
-        let mut attempts = 0;
-        loop {
-            let current_content = pasteboard.stringForType(NSPasteboardTypeString);
-            if current_content != nil {
-                let current_str = cocoa::foundation::NSString::UTF8String(current_content);
-                let current_rust_str = CStr::from_ptr(current_str)
-                    .to_string_lossy()
-                    .into_owned();
-
-                eprintln!("📋 Verification attempt {}: clipboard = '{}'", attempts + 1, current_rust_str);
-
-                if current_rust_str == text {
-                    eprintln!("✅ Clipboard verified!");
-                    break;
-                }
-            }
-
-            attempts += 1;
-            if attempts > 50 {
-                return Err("Failed to verify clipboard content was set".to_string());
-            }
-            thread::sleep(Duration::from_millis(2));
+        log_trace!("Step 4: Verifying clipboard was set");
+        verify_clipboard(pasteboard, text, before, timing)?;
+
+        Ok(old_clipboard)
+    })
+}
+
+/// Poll until the clipboard holds `text`, giving up after the profile's attempt limit
+/// `before` is the count read after the clipboard was cleared; the text is only read
+/// back once the count has moved past it.
+fn verify_clipboard(pasteboard: &mut dyn Clipboard, text: &str, before: i64, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    let mut attempts = 0;
+    loop {
+        let current = pasteboard.change_count()?;
+        log_trace!("Verification attempt {}: change count {} (was {})", attempts + 1, current, before);
+
+        if current != before && pasteboard.read_string()?.as_deref() == Some(text) {
+            log_debug!("Clipboard verified");
+            return Ok(());
+        }
+
+        attempts += 1;
+        if attempts > timing.clipboard_poll_attempts {
+            return Err(SuperspeedError::ClipboardVerifyTimeout);
         }
+        sleep_ms(timing.clipboard_poll_ms);
+    }
+}
 
-        // Create event source
-        eprintln!("⌨️  Step 6: Creating event source");
-        let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
-            .map_err(|_| "Failed to create event source")?;
+/// Restore clipboard from a saved snapshot, byte-for-byte
+pub fn restore_clipboard(snapshot: &ClipboardSnapshot) -> Result<(), SuperspeedError> {
+    clipboard::with_backend(|pasteboard| {
+        log_trace!("Restoring clipboard: {}", snapshot);
+        pasteboard.restore(snapshot)?;
 
-        // Simulate Cmd+V (paste)
-        eprintln!("⌨️  Step 7: Creating Cmd+V events");
-        let key_v_down = CGEvent::new_keyboard_event(source.clone(), 9, true)
-            .map_err(|_| "Failed to create key down event")?;
-        let key_v_up = CGEvent::new_keyboard_event(source.clone(), 9, false)
-            .map_err(|_| "Failed to create key up event")?;
+        log_debug!("Clipboard restored");
+        Ok(())
+    })
+}
 
-        // Set the Command modifier flag
-        key_v_down.set_flags(CGEventFlags::CGEventFlagCommand);
-        key_v_up.set_flags(CGEventFlags::CGEventFlagCommand);
+/// Restore `saved` unless the user replaced our ghost text on the clipboard in the meantime
+pub fn restore_clipboard_if_ours(saved: &SavedClipboard, ghost_text: &str) -> Result<ClipboardRestore, SuperspeedError> {
+    clipboard::with_backend(|pasteboard| {
+        let current = pasteboard.change_count()?;
+        // Our own select-and-copy reads put the ghost text back with a new count, so a
+        // moved count only means a user copy when the ghost text is gone as well
+        if current != saved.change_count && pasteboard.read_string()?.as_deref() != Some(ghost_text) {
+            log_info!("Clipboard changed while ghost text was pending (count {} -> {}), keeping it", saved.change_count, current);
+            return Ok(ClipboardRestore::SkippedUserChange);
+        }
 
-        // Post the events with proper delays
-        eprintln!("⌨️  Step 8: Posting Cmd+V (key down)");
-        key_v_down.post(CGEventTapLocation::HID);
-        thread::sleep(Duration::from_millis(20));  // Delay after posting
+        log_trace!("Restoring clipboard: {}", saved.snapshot);
+        pasteboard.restore(&saved.snapshot)?;
 
-        eprintln!("⌨️  Step 9: Posting Cmd+V (key up)");
-        key_v_up.post(CGEventTapLocation::HID);
-        thread::sleep(Duration::from_millis(20));  // Delay after posting
+        log_debug!("Clipboard restored");
+        Ok(ClipboardRestore::Restored)
+    })
+}
 
-        eprintln!("✅ Cmd+V posted successfully");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::focus;
+    use crate::keyboard::clipboard::{ClipboardItem, MemoryClipboard, WriteBehavior};
+    use crate::keyboard::synth::RecordingSynth;
+    use crate::sim;
 
-        // Return old clipboard for later restore (don't restore now!)
-        eprintln!("📋 Clipboard kept with AI suggestion (will restore on Tab/Esc)");
+    const GHOST: &str = "ghost text";
 
-        Ok(old_clipboard_string)
+    // Rich text and an image, the kind of clipboard a text-only restore would lose
+    fn rich_clipboard() -> ClipboardSnapshot {
+        let mut styled = ClipboardItem::text("Quarterly report");
+        styled.flavors.push(("text/html".to_string(), b"<b>Quarterly report</b>".to_vec()));
+        let image = ClipboardItem { flavors: vec![("image/png".to_string(), vec![0x89, b'P', b'N', b'G', 0, 255])] };
+        ClipboardSnapshot { items: vec![styled, image] }
     }
-}
 
-/// Restore clipboard from saved string
-pub fn restore_clipboard(text: &str) -> Result<(), String> {
-    unsafe {
-        let _pool = NSAutoreleasePool::new(nil);
-        let pasteboard = NSPasteboard::generalPasteboard(nil);
+    fn install(snapshot: ClipboardSnapshot) -> MemoryClipboard {
+        sim::install_virtual_clock();
+        focus::set_override(Some(focus::FocusedTarget::default()));
+        synth::set_backend(Box::new(RecordingSynth::new()));
+        let clipboard = MemoryClipboard::with_snapshot(snapshot);
+        clipboard::set_backend(Box::new(clipboard.clone()));
+        clipboard
+    }
 
-        eprintln!("📋 Restoring clipboard");
-        pasteboard.clearContents();
-        let ns_string = NSString::alloc(nil).init_str(text);
-        pasteboard.setString_forType(ns_string, NSPasteboardTypeString);
+    // Clears land but writes vanish, like a clipboard owner that lost the selection race
+    struct LosesWrites(MemoryClipboard);
 
-        eprintln!("✅ Clipboard restored");
-        Ok(())
+    impl Clipboard for LosesWrites {
+        fn name(&self) -> &'static str {
+            "loses writes"
+        }
+        fn read_string(&mut self) -> Result<Option<String>, SuperspeedError> {
+            self.0.read_string()
+        }
+        fn write_string(&mut self, _text: &str) -> Result<(), SuperspeedError> {
+            Ok(())
+        }
+        fn write_marked(&mut self, _text: &str, _markers: ClipboardMarkers) -> Result<(), SuperspeedError> {
+            Ok(())
+        }
+        fn clear(&mut self) -> Result<(), SuperspeedError> {
+            self.0.clear()
+        }
+        fn change_count(&mut self) -> Result<i64, SuperspeedError> {
+            self.0.change_count()
+        }
+    }
+
+    fn insert() -> SavedClipboard {
+        let snapshot = insert_via_clipboard_and_save(GHOST, ClipboardMarkers::PRIVATE, Chord::PASTE, &TimingProfile::NATIVE).unwrap();
+        SavedClipboard::new(snapshot).unwrap()
+    }
+
+    #[test]
+    fn restore_puts_back_every_item_and_flavor() {
+        let _globals = sim::exclusive();
+        let clipboard = install(rich_clipboard());
+
+        let saved = insert();
+        assert_eq!(saved.snapshot, rich_clipboard());
+        assert_eq!(clipboard.contents().as_deref(), Some(GHOST));
+
+        restore_clipboard(&saved.snapshot).unwrap();
+        assert_eq!(clipboard.items(), rich_clipboard());
+    }
+
+    #[test]
+    fn restore_if_ours_puts_back_the_user_clipboard() {
+        let _globals = sim::exclusive();
+        let clipboard = install(rich_clipboard());
+
+        let saved = insert();
+        assert_eq!(restore_clipboard_if_ours(&saved, GHOST).unwrap(), ClipboardRestore::Restored);
+        assert_eq!(clipboard.items(), rich_clipboard());
+    }
+
+    #[test]
+    fn restore_if_ours_ignores_our_own_copies_of_the_ghost_text() {
+        let _globals = sim::exclusive();
+        let mut clipboard = install(rich_clipboard());
+
+        let saved = insert();
+        // A cursor-context read copies the ghost text again, moving the count
+        clipboard.write_string(GHOST).unwrap();
+        assert_eq!(restore_clipboard_if_ours(&saved, GHOST).unwrap(), ClipboardRestore::Restored);
+        assert_eq!(clipboard.items(), rich_clipboard());
+    }
+
+    #[test]
+    fn restore_if_ours_keeps_a_copy_made_by_someone_else() {
+        let _globals = sim::exclusive();
+        let mut clipboard = install(rich_clipboard());
+
+        let saved = insert();
+        clipboard.write_string("copied meanwhile").unwrap();
+        assert_eq!(restore_clipboard_if_ours(&saved, GHOST).unwrap(), ClipboardRestore::SkippedUserChange);
+        assert_eq!(clipboard.items(), ClipboardSnapshot::text("copied meanwhile"));
+    }
+
+    #[test]
+    fn delayed_writes_are_waited_for() {
+        let _globals = sim::exclusive();
+        let clipboard = install(ClipboardSnapshot::text("user copy"));
+        clipboard.set_write_behavior(WriteBehavior::Delayed(5));
+
+        insert();
+        assert_eq!(clipboard.contents().as_deref(), Some(GHOST));
+    }
+
+    #[test]
+    fn dropped_writes_time_out() {
+        let _globals = sim::exclusive();
+        let clipboard = install(ClipboardSnapshot::text("user copy"));
+        clipboard.set_write_behavior(WriteBehavior::Dropped);
+
+        let result = insert_via_clipboard_and_save(GHOST, ClipboardMarkers::PRIVATE, Chord::PASTE, &TimingProfile::NATIVE);
+        assert!(matches!(result, Err(SuperspeedError::ClipboardVerifyTimeout)));
+    }
+
+    #[test]
+    fn a_clear_alone_does_not_verify() {
+        let _globals = sim::exclusive();
+        let clipboard = install(ClipboardSnapshot::text("user copy"));
+        clipboard::set_backend(Box::new(LosesWrites(clipboard.clone())));
+
+        let result = insert_via_clipboard_and_save(GHOST, ClipboardMarkers::PRIVATE, Chord::PASTE, &TimingProfile::NATIVE);
+        assert!(matches!(result, Err(SuperspeedError::ClipboardVerifyTimeout)));
+        assert_eq!(clipboard.contents(), None);
     }
 }
@@ -0,0 +1,130 @@
+// NSPasteboard clipboard backend (macOS)
+use super::clipboard::{Clipboard, ClipboardItem, ClipboardSnapshot};
+use crate::error::SuperspeedError;
+use cocoa::appkit::{NSPasteboard, NSPasteboardTypeString};
+use cocoa::base::{id, nil, BOOL, NO};
+use cocoa::foundation::{NSArray, NSAutoreleasePool, NSData, NSString};
+use objc::{class, msg_send, sel, sel_impl};
+use std::ffi::{c_void, CStr};
+
+/// The general (Cmd+C / Cmd+V) pasteboard
+/// IMPORTANT: Use from the main thread (NSPasteboard is not thread-safe).
+pub struct GeneralPasteboard;
+
+impl Clipboard for GeneralPasteboard {
+    fn name(&self) -> &'static str {
+        "nspasteboard"
+    }
+
+    fn read_string(&mut self) -> Result<Option<String>, SuperspeedError> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+
+            let contents = pasteboard.stringForType(NSPasteboardTypeString);
+            if contents != nil {
+                let c_str = NSString::UTF8String(contents);
+                Ok(Some(CStr::from_ptr(c_str).to_string_lossy().into_owned()))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+
+    fn write_string(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+
+            let ns_string = NSString::alloc(nil).init_str(text);
+            if pasteboard.setString_forType(ns_string, NSPasteboardTypeString) != NO {
+                Ok(())
+            } else {
+                Err(SuperspeedError::Clipboard("NSPasteboard rejected the string".to_string()))
+            }
+        }
+    }
+
+    fn clear(&mut self) -> Result<(), SuperspeedError> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            NSPasteboard::generalPasteboard(nil).clearContents();
+            Ok(())
+        }
+    }
+
+    fn change_count(&mut self) -> Result<i64, SuperspeedError> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            let pasteboard: id = NSPasteboard::generalPasteboard(nil);
+            let count: isize = msg_send![pasteboard, changeCount];
+            Ok(count as i64)
+        }
+    }
+
+    fn snapshot(&mut self) -> Result<ClipboardSnapshot, SuperspeedError> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+
+            let ns_items: id = msg_send![pasteboard, pasteboardItems];
+            if ns_items == nil {
+                return Ok(ClipboardSnapshot::default());
+            }
+            let mut items = Vec::new();
+            for i in 0..ns_items.count() {
+                let ns_item = ns_items.objectAtIndex(i);
+                let types: id = msg_send![ns_item, types];
+                let mut item = ClipboardItem::default();
+                for j in 0..types.count() {
+                    let flavor = types.objectAtIndex(j);
+                    // Promised data the owner can no longer provide comes back nil; skip it
+                    let data: id = msg_send![ns_item, dataForType: flavor];
+                    if data == nil {
+                        continue;
+                    }
+                    let bytes = std::slice::from_raw_parts(data.bytes() as *const u8, data.length() as usize);
+                    item.flavors.push((ns_string(flavor), bytes.to_vec()));
+                }
+                items.push(item);
+            }
+            Ok(ClipboardSnapshot { items })
+        }
+    }
+
+    fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), SuperspeedError> {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+            pasteboard.clearContents();
+            if snapshot.is_empty() {
+                return Ok(());
+            }
+
+            let mut ns_items = Vec::with_capacity(snapshot.items.len());
+            for item in &snapshot.items {
+                let ns_item: id = msg_send![class!(NSPasteboardItem), new];
+                let ns_item: id = msg_send![ns_item, autorelease];
+                for (flavor, bytes) in &item.flavors {
+                    let data = NSData::dataWithBytes_length_(nil, bytes.as_ptr() as *const c_void, bytes.len() as u64);
+                    let flavor = NSString::alloc(nil).init_str(flavor);
+                    let _: BOOL = msg_send![ns_item, setData: data forType: flavor];
+                }
+                ns_items.push(ns_item);
+            }
+
+            let array = NSArray::arrayWithObjects(nil, &ns_items);
+            let written: BOOL = msg_send![pasteboard, writeObjects: array];
+            if written != NO {
+                Ok(())
+            } else {
+                Err(SuperspeedError::Clipboard("NSPasteboard rejected the saved items".to_string()))
+            }
+        }
+    }
+}
+
+// Copy an NSString into a Rust String
+unsafe fn ns_string(string: id) -> String {
+    CStr::from_ptr(NSString::UTF8String(string)).to_string_lossy().into_owned()
+}
@@ -1,194 +1,67 @@
-// Keyboard simulation using core-graphics (same as ito)
-use core_graphics::event::{
-    CGEvent, CGEventFlags, CGEventTapLocation, CGKeyCode,
-};
-use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
-use std::thread;
-use std::time::Duration;
-use cocoa::base::{id, nil};
-use cocoa::foundation::NSString;
-use objc::{msg_send, sel, sel_impl, class};
-use std::ffi::CStr;
+// Keyboard simulation on top of the active key synthesis backend
+use super::synth::{self, Key, KeySynth, Modifiers};
+use crate::error::SuperspeedError;
+use crate::profile;
+use crate::timing::{sleep_ms, TimingProfile};
 
-// macOS virtual keycodes (from Carbon Events.h)
-const KVK_RETURN: u16 = 0x24; // Return/Enter key
-const KVK_ANSI_V: u16 = 0x09; // 'V' key
-const KVK_COMMAND: u16 = 0x37; // Command modifier
-const KVK_SHIFT: u16 = 0x38; // Shift modifier
-const KVK_SPACE: u16 = 0x31; // Space key
-const KVK_TAB: u16 = 0x30; // Tab key
-const KVK_DELETE: u16 = 0x33; // Backspace/Delete key
-
-// Terminal ghost text delimiter - using Tab for better visibility
-const GHOST_TEXT_DELIMITER: &[u8] = b"\t\t"; // Two tabs for clear separation
-
-/// Check if current application is a terminal
+/// Check if current application is a terminal (per its app profile)
 pub fn is_terminal() -> bool {
-    unsafe {
-        let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
-        let front_app: id = msg_send![workspace, frontmostApplication];
-        if front_app == nil {
-            return false;
-        }
-
-        let bundle_id: id = msg_send![front_app, bundleIdentifier];
-        if bundle_id == nil {
-            return false;
-        }
-
-        let bundle_str = CStr::from_ptr(NSString::UTF8String(bundle_id));
-        match bundle_str.to_str() {
-            Ok(id) => {
-                // Common terminal bundle IDs
-                matches!(id,
-                    "com.apple.Terminal" | 
-                    "com.googlecode.iterm2" |
-                    "dev.warp.Warp-Stable" |
-                    "com.github.wez.wezterm" |
-                    "co.zeit.hyper" |
-                    "com.microsoft.VSCode" // When terminal panel is focused
-                )
-            }
-            Err(_) => false
-        }
-    }
+    profile::current().terminal
 }
 
-/// Type the ghost text delimiter (two tabs) to separate input from suggestion
-pub fn type_delimiter() -> bool {
-    let source = match CGEventSource::new(CGEventSourceStateID::CombinedSessionState) {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("Failed to create event source");
-            return false;
+/// Type the ghost text delimiter (`count` tabs) to separate input from suggestion
+pub fn type_delimiter(count: usize, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| {
+        for _ in 0..count {
+            tap(s, Key::Tab, Modifiers::NONE, timing)?;
         }
-    };
-
-    // Type two tabs for clear separation
-    for _ in 0..2 {
-        // Press tab
-        let tab_down = match CGEvent::new_keyboard_event(source.clone(), KVK_TAB as CGKeyCode, true) {
-            Ok(e) => e,
-            Err(_) => {
-                eprintln!("Failed to create tab down event");
-                return false;
-            }
-        };
-        post_event(&tab_down);
-
-        // Release tab
-        let tab_up = match CGEvent::new_keyboard_event(source.clone(), KVK_TAB as CGKeyCode, false) {
-            Ok(e) => e,
-            Err(_) => {
-                eprintln!("Failed to create tab up event");
-                return false;
-            }
-        };
-        post_event(&tab_up);
-    }
-
-    true
+        Ok(())
+    })
 }
 
-/// Post event and add small delay for reliability (20ms recommended for macOS)
-fn post_event(event: &CGEvent) {
-    event.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(20));
+/// Press and release a key, pausing after each event (20ms recommended for macOS)
+fn tap(synth: &mut dyn KeySynth, key: Key, modifiers: Modifiers, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth.key_down(key, modifiers)?;
+    sleep_ms(timing.key_event_ms);
+    synth.key_up(key, modifiers)?;
+    sleep_ms(timing.key_event_ms);
+    Ok(())
 }
 
 /// Simulate Shift+Enter keypress
-/// Returns true on success, false on failure
-pub fn shift_enter() -> bool {
-    let source = match CGEventSource::new(CGEventSourceStateID::CombinedSessionState) {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("Failed to create event source");
-            return false;
-        }
-    };
-
-    // Press Shift down (like real keyboard)
-    let shift_down = match CGEvent::new_keyboard_event(source.clone(), KVK_SHIFT as CGKeyCode, true) {
-        Ok(e) => e,
-        Err(_) => {
-            eprintln!("Failed to create Shift down event");
-            return false;
-        }
-    };
-    shift_down.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(20));
-
-    // Press Return (with Shift still held)
-    let return_down = match CGEvent::new_keyboard_event(source.clone(), KVK_RETURN as CGKeyCode, true) {
-        Ok(e) => e,
-        Err(_) => {
-            eprintln!("Failed to create Return down event");
-            return false;
-        }
-    };
-    return_down.set_flags(CGEventFlags::CGEventFlagShift);
-    return_down.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(20));
-
-    // Release Return
-    let return_up = match CGEvent::new_keyboard_event(source.clone(), KVK_RETURN as CGKeyCode, false) {
-        Ok(e) => e,
-        Err(_) => {
-            eprintln!("Failed to create Return up event");
-            return false;
-        }
-    };
-    return_up.set_flags(CGEventFlags::CGEventFlagShift);
-    return_up.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(20));
+pub fn shift_enter(timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| {
+        // Press Shift down (like real keyboard)
+        s.key_down(Key::Shift, Modifiers::NONE)?;
+        sleep_ms(timing.key_event_ms);
+
+        // Press and release Return (with Shift still held)
+        tap(s, Key::Return, Modifiers::SHIFT, timing)?;
+
+        // Release Shift
+        s.key_up(Key::Shift, Modifiers::NONE)?;
+        sleep_ms(timing.key_event_ms);
+        Ok(())
+    })
+}
 
-    // Release Shift
-    let shift_up = match CGEvent::new_keyboard_event(source, KVK_SHIFT as CGKeyCode, false) {
-        Ok(e) => e,
-        Err(_) => {
-            eprintln!("Failed to create Shift up event");
-            return false;
-        }
-    };
-    shift_up.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(20));
+/// Simulate a plain Enter keypress (only where Enter doesn't submit)
+pub fn enter(timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| tap(s, Key::Return, Modifiers::NONE, timing))
+}
 
-    true
+/// Simulate Ctrl+U: shells and readline prompts delete everything before the cursor
+/// Ctrl on every platform; terminal line editing ignores Cmd.
+pub fn kill_line(timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| tap(s, Key::U, Modifiers::CONTROL, timing))
 }
 
 /// Simulate Backspace keypress
-/// Returns true on success, false on failure
-pub fn backspace() -> bool {
-    let source = match CGEventSource::new(CGEventSourceStateID::CombinedSessionState) {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("Failed to create event source");
-            return false;
-        }
-    };
-
-    // Create Delete key down event
-    let delete_down = match CGEvent::new_keyboard_event(source.clone(), KVK_DELETE as CGKeyCode, true) {
-        Ok(e) => e,
-        Err(_) => {
-            eprintln!("Failed to create Delete down event");
-            return false;
-        }
-    };
-    delete_down.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(20));  // Delay after posting
-
-    // Create Delete key up event
-    let delete_up = match CGEvent::new_keyboard_event(source, KVK_DELETE as CGKeyCode, false) {
-        Ok(e) => e,
-        Err(_) => {
-            eprintln!("Failed to create Delete up event");
-            return false;
-        }
-    };
-    delete_up.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(20));  // Delay after posting
-
-    true
+pub fn backspace(timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| tap(s, Key::Backspace, Modifiers::NONE, timing))
 }
 
+/// Fail early if the active backend cannot post events
+pub fn check_access() -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| s.check_access())
+}
@@ -0,0 +1,221 @@
+// Insertion strategies
+// How ghost text gets into the focused field. Pasting is fast and exact but
+// borrows the clipboard; typing leaves the clipboard alone and works where
+// paste is blocked. The method can be chosen per call or per application.
+
+use super::clipboard::{ClipboardMarkers, ClipboardSnapshot};
+use super::paste::SavedClipboard;
+use super::synth::Chord;
+use super::verify::{self, Verification};
+use super::{paste, typing};
+use crate::error::SuperspeedError;
+use crate::profile::{self, AppIdentity, AppProfile};
+use crate::timing::TimingProfile;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// A way of putting text at the caret
+pub trait InsertionStrategy: Send + Sync {
+    /// Short strategy name for logs
+    fn name(&self) -> &'static str;
+
+    /// Insert `text` at the caret, tagging any clipboard write with `markers`
+    /// `paste` is the shortcut of the app being inserted into, for strategies that paste.
+    /// Returns the clipboard contents to restore once the ghost session ends, if it changed them.
+    fn insert(&self, text: &str, markers: ClipboardMarkers, paste: Chord, timing: &TimingProfile) -> Result<Option<ClipboardSnapshot>, SuperspeedError>;
+}
+
+/// Paste through the clipboard (Cmd/Ctrl+V), keeping the old contents for restore
+pub struct ClipboardPaste;
+
+impl InsertionStrategy for ClipboardPaste {
+    fn name(&self) -> &'static str {
+        "clipboard"
+    }
+
+    fn insert(&self, text: &str, markers: ClipboardMarkers, paste: Chord, timing: &TimingProfile) -> Result<Option<ClipboardSnapshot>, SuperspeedError> {
+        paste::insert_via_clipboard_and_save(text, markers, paste, timing).map(Some)
+    }
+}
+
+/// Type the text as Unicode input; the clipboard is never touched
+pub struct UnicodeTyping;
+
+impl InsertionStrategy for UnicodeTyping {
+    fn name(&self) -> &'static str {
+        "typing"
+    }
+
+    fn insert(&self, text: &str, _markers: ClipboardMarkers, _paste: Chord, timing: &TimingProfile) -> Result<Option<ClipboardSnapshot>, SuperspeedError> {
+        typing::type_text(text, timing)?;
+        Ok(None)
+    }
+}
+
+/// Insertion method selectable over FFI
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertionMethod {
+    /// Whatever is configured for the frontmost app, else the default
+    Auto = 0,
+    Clipboard = 1,
+    Typing = 2,
+}
+
+impl InsertionMethod {
+    pub fn from_i32(value: i32) -> Result<Self, SuperspeedError> {
+        match value {
+            0 => Ok(InsertionMethod::Auto),
+            1 => Ok(InsertionMethod::Clipboard),
+            2 => Ok(InsertionMethod::Typing),
+            _ => Err(SuperspeedError::Unsupported(format!("Unknown insertion method {}", value))),
+        }
+    }
+
+    /// Methods to try in order when this one doesn't land: itself first, then the rest
+    pub fn fallback_chain(self) -> Vec<InsertionMethod> {
+        let first = match self {
+            InsertionMethod::Auto => InsertionMethod::Clipboard,
+            method => method,
+        };
+        let mut chain = vec![first];
+        chain.extend([InsertionMethod::Clipboard, InsertionMethod::Typing].into_iter().filter(|&m| m != first));
+        chain
+    }
+
+    /// Strategy implementing this method (Auto falls back to clipboard paste)
+    pub fn strategy(self) -> &'static dyn InsertionStrategy {
+        match self {
+            InsertionMethod::Auto | InsertionMethod::Clipboard => &ClipboardPaste,
+            InsertionMethod::Typing => &UnicodeTyping,
+        }
+    }
+}
+
+/// Options for one ghost text insertion
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsertOptions {
+    /// A SuperspeedInsertionMethod; AUTO uses the method set for the frontmost app
+    pub method: i32,
+    /// Markers asking clipboard managers to keep the suggestion out of their history
+    pub markers: ClipboardMarkers,
+}
+
+impl Default for InsertOptions {
+    fn default() -> Self {
+        InsertOptions { method: InsertionMethod::Auto as i32, markers: ClipboardMarkers::PRIVATE }
+    }
+}
+
+// Method for apps without their own setting
+static DEFAULT_METHOD: Mutex<InsertionMethod> = Mutex::new(InsertionMethod::Clipboard);
+
+// Per-application overrides, keyed by application identifier
+static APP_METHODS: Mutex<BTreeMap<String, InsertionMethod>> = Mutex::new(BTreeMap::new());
+
+/// Set the method for `app_id`, or the default method with None
+/// Auto clears an app's override (or resets the default to clipboard paste).
+pub fn set_method(app_id: Option<&str>, method: InsertionMethod) {
+    match (app_id, method) {
+        (Some(app_id), InsertionMethod::Auto) => {
+            APP_METHODS.lock().unwrap().remove(app_id);
+        }
+        (Some(app_id), method) => {
+            APP_METHODS.lock().unwrap().insert(app_id.to_string(), method);
+        }
+        (None, InsertionMethod::Auto) => *DEFAULT_METHOD.lock().unwrap() = InsertionMethod::Clipboard,
+        (None, method) => *DEFAULT_METHOD.lock().unwrap() = method,
+    }
+}
+
+/// Method configured for `app_id`, falling back to its app profile, then the default method
+pub fn method_for(app_id: Option<&str>) -> InsertionMethod {
+    method_for_app(app_id, &profile::lookup(&AppIdentity::from_app_id(app_id)))
+}
+
+/// Method set for `app_id`, else the method in `app`, else the default method
+pub fn method_for_app(app_id: Option<&str>, app: &AppProfile) -> InsertionMethod {
+    app_id
+        .and_then(|id| APP_METHODS.lock().unwrap().get(id).copied())
+        .or(app.insertion)
+        .unwrap_or_else(|| *DEFAULT_METHOD.lock().unwrap())
+}
+
+/// Method to use for one call: an explicit request wins over the app's setting
+pub fn resolve(requested: InsertionMethod, app_id: Option<&str>, app: &AppProfile) -> InsertionMethod {
+    match requested {
+        InsertionMethod::Auto => method_for_app(app_id, app),
+        method => method,
+    }
+}
+
+/// Outcome of a successful insertion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Insertion {
+    /// Method whose text landed
+    pub method: InsertionMethod,
+    /// Clipboard to restore once the ghost session ends
+    pub saved_clipboard: Option<SavedClipboard>,
+    /// The text was read back from the field (false if verification was off or inconclusive)
+    pub verified: bool,
+}
+
+/// Insert `text` with `method`, falling back to the next method while the field shows nothing
+/// `verify` reads the field back after each attempt. When every method fails, the clipboard is
+/// restored before the error is returned. `markers` tag every clipboard write along the way.
+pub fn insert_with_fallback(
+    text: &str,
+    method: InsertionMethod,
+    verify: bool,
+    markers: ClipboardMarkers,
+    paste: Chord,
+    timing: &TimingProfile,
+) -> Result<Insertion, SuperspeedError> {
+    let before = if verify { verify::snapshot(text, markers, timing) } else { None };
+    let mut saved_clipboard = None;
+    let mut last_error = None;
+
+    for method in method.fallback_chain() {
+        let strategy = method.strategy();
+        match strategy.insert(text, markers, paste, timing) {
+            Ok(old_clipboard) => {
+                // Keep the first saved clipboard: later attempts only see our own text
+                saved_clipboard = saved_clipboard.or(old_clipboard);
+                let verification = if verify {
+                    verify::verify(text, before.as_deref(), markers, timing)
+                } else {
+                    Verification::Unknown
+                };
+                match verification {
+                    Verification::Missing => {
+                        log_warn!("Insertion via {} did not reach the field, trying the next method", strategy.name());
+                        last_error = Some(SuperspeedError::NotInserted);
+                    }
+                    Verification::Verified | Verification::Unknown => {
+                        if verify && verification == Verification::Unknown {
+                            log_warn!("Could not confirm insertion via {}; assuming it landed", strategy.name());
+                        }
+                        log_info!("Ghost text inserted via {}", strategy.name());
+                        // Counted after verification, whose select-and-copy also moves the count
+                        return Ok(Insertion {
+                            method,
+                            saved_clipboard: saved_clipboard.map(SavedClipboard::new).transpose()?,
+                            verified: verification == Verification::Verified,
+                        });
+                    }
+                }
+            }
+            Err(e) => {
+                log_warn!("Insertion via {} failed: {}", strategy.name(), e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    // Nothing landed, so no accept/reject will come to put the clipboard back
+    if let Some(snapshot) = saved_clipboard {
+        paste::restore_clipboard(&snapshot)?;
+    }
+    Err(last_error.unwrap_or(SuperspeedError::NotInserted))
+}
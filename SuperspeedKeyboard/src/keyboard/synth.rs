@@ -0,0 +1,287 @@
+// Key synthesis backends
+// Every synthetic keystroke goes through a KeySynth so the platform layer
+// (CoreGraphics on macOS) can be swapped out or recorded in tests.
+
+use crate::error::SuperspeedError;
+use std::sync::{Arc, Mutex};
+
+/// Logical keys Superspeed presses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Return,
+    Tab,
+    Backspace,
+    LeftArrow,
+    RightArrow,
+    Shift,
+    Command,
+    Control,
+    V,
+    C,
+    U,
+}
+
+/// Modifier flags attached to a key event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub command: bool,
+    pub control: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers { shift: false, command: false, control: false };
+    pub const SHIFT: Modifiers = Modifiers { shift: true, command: false, control: false };
+    pub const COMMAND: Modifiers = Modifiers { shift: false, command: true, control: false };
+    pub const CONTROL: Modifiers = Modifiers { shift: false, command: false, control: true };
+
+    /// Modifier used for copy/paste shortcuts on this platform (Cmd on macOS, Ctrl elsewhere)
+    #[cfg(target_os = "macos")]
+    pub const SHORTCUT: Modifiers = Modifiers::COMMAND;
+    #[cfg(not(target_os = "macos"))]
+    pub const SHORTCUT: Modifiers = Modifiers::CONTROL;
+
+    pub fn is_empty(&self) -> bool {
+        !self.shift && !self.command && !self.control
+    }
+
+    /// These modifiers plus the one called `name` in a chord string ("shift", "cmd", "ctrl",
+    /// or "primary" for the platform shortcut modifier); None if the name is unknown
+    pub fn with_named(self, name: &str) -> Option<Modifiers> {
+        let mut modifiers = self;
+        match name {
+            "shift" => modifiers.shift = true,
+            "cmd" | "command" | "super" | "meta" => modifiers.command = true,
+            "ctrl" | "control" => modifiers.control = true,
+            "primary" => {
+                modifiers.command |= Modifiers::SHORTCUT.command;
+                modifiers.control |= Modifiers::SHORTCUT.control;
+            }
+            _ => return None,
+        }
+        Some(modifiers)
+    }
+}
+
+/// A key pressed together with modifiers, e.g. the paste shortcut
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl Chord {
+    /// Cmd+V on macOS, Ctrl+V elsewhere
+    pub const PASTE: Chord = Chord { key: Key::V, modifiers: Modifiers::SHORTCUT };
+}
+
+// Parses "cmd+v", "ctrl+shift+v" or "primary+v" (Cmd on macOS, Ctrl elsewhere)
+impl std::str::FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<String> = s.split('+').map(|part| part.trim().to_ascii_lowercase()).collect();
+        let key = match parts.pop().as_deref() {
+            Some("v") => Key::V,
+            Some("c") => Key::C,
+            Some("u") => Key::U,
+            Some("tab") => Key::Tab,
+            Some("return" | "enter") => Key::Return,
+            Some("backspace") => Key::Backspace,
+            Some("left") => Key::LeftArrow,
+            Some("right") => Key::RightArrow,
+            _ => return Err(format!("unknown key in chord \"{}\"", s)),
+        };
+        let modifiers = parts.iter().try_fold(Modifiers::NONE, |modifiers, part| {
+            modifiers.with_named(part).ok_or_else(|| format!("unknown modifier \"{}\" in chord \"{}\"", part, s))
+        })?;
+        Ok(Chord { key, modifiers })
+    }
+}
+
+/// A platform keystroke generator
+pub trait KeySynth: Send {
+    /// Short backend name for logs
+    fn name(&self) -> &'static str;
+
+    /// Post a key down event with the given modifier flags
+    fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError>;
+
+    /// Post a key up event with the given modifier flags
+    fn key_up(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError>;
+
+    /// Type `text` directly as Unicode input, independent of the keyboard layout
+    /// Callers split long text into chunks and never pass newlines.
+    fn type_text(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        let _ = text;
+        Err(SuperspeedError::Unsupported(format!("The {} backend cannot type Unicode text", self.name())))
+    }
+
+    /// Fail early if the OS will silently drop our events (e.g. missing permission)
+    fn check_access(&self) -> Result<(), SuperspeedError> {
+        Ok(())
+    }
+}
+
+/// Turns modifier flags into real modifier key presses for backends without event flags
+/// (XTest, uinput). Modifiers pressed explicitly (e.g. Key::Shift down) stay held until
+/// released; modifiers only requested as flags are pressed around a single key.
+#[derive(Debug, Default)]
+pub struct ChordTracker {
+    held: Vec<Key>,
+    chord: Vec<Key>,
+}
+
+impl ChordTracker {
+    pub fn is_modifier(key: Key) -> bool {
+        matches!(key, Key::Shift | Key::Control | Key::Command)
+    }
+
+    /// Press `key`, first pressing any requested modifiers that aren't already down
+    pub fn key_down(
+        &mut self,
+        key: Key,
+        modifiers: Modifiers,
+        mut press: impl FnMut(Key, bool) -> Result<(), SuperspeedError>,
+    ) -> Result<(), SuperspeedError> {
+        if Self::is_modifier(key) {
+            press(key, true)?;
+            self.held.push(key);
+            return Ok(());
+        }
+
+        let requested = [
+            (modifiers.control, Key::Control),
+            (modifiers.command, Key::Command),
+            (modifiers.shift, Key::Shift),
+        ];
+        for (wanted, modifier) in requested {
+            if wanted && !self.held.contains(&modifier) && !self.chord.contains(&modifier) {
+                press(modifier, true)?;
+                self.chord.push(modifier);
+            }
+        }
+        press(key, true)
+    }
+
+    /// Release `key`, then any modifiers pressed just for it
+    pub fn key_up(
+        &mut self,
+        key: Key,
+        mut press: impl FnMut(Key, bool) -> Result<(), SuperspeedError>,
+    ) -> Result<(), SuperspeedError> {
+        if Self::is_modifier(key) {
+            self.held.retain(|&held| held != key);
+            return press(key, false);
+        }
+
+        press(key, false)?;
+        while let Some(modifier) = self.chord.pop() {
+            press(modifier, false)?;
+        }
+        Ok(())
+    }
+}
+
+/// One logical event captured by RecordingSynth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SynthEvent {
+    Down(Key, Modifiers),
+    Up(Key, Modifiers),
+    Text(String),
+}
+
+/// In-memory backend that records the logical key sequence instead of posting it
+/// Clones share the same event log, so a test can keep one handle and install another
+#[derive(Debug, Clone, Default)]
+pub struct RecordingSynth {
+    events: Arc<Mutex<Vec<SynthEvent>>>,
+}
+
+impl RecordingSynth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot of every event recorded so far
+    pub fn events(&self) -> Vec<SynthEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Drain the recorded events
+    pub fn take(&self) -> Vec<SynthEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl KeySynth for RecordingSynth {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        self.events.lock().unwrap().push(SynthEvent::Down(key, modifiers));
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        self.events.lock().unwrap().push(SynthEvent::Up(key, modifiers));
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        self.events.lock().unwrap().push(SynthEvent::Text(text.to_string()));
+        Ok(())
+    }
+}
+
+// Active backend, created lazily on first use
+static BACKEND: Mutex<Option<Box<dyn KeySynth>>> = Mutex::new(None);
+
+/// Replace the active key synthesis backend
+pub fn set_backend(backend: Box<dyn KeySynth>) {
+    *BACKEND.lock().unwrap() = Some(backend);
+}
+
+/// Run `f` against the active backend, creating the platform default if none is set
+pub fn with_backend<R>(f: impl FnOnce(&mut dyn KeySynth) -> Result<R, SuperspeedError>) -> Result<R, SuperspeedError> {
+    let mut guard = BACKEND.lock().unwrap();
+    if guard.is_none() {
+        *guard = Some(default_backend()?);
+    }
+    f(guard.as_mut().unwrap().as_mut())
+}
+
+/// Platform default backend
+fn default_backend() -> Result<Box<dyn KeySynth>, SuperspeedError> {
+    #[cfg(target_os = "macos")]
+    {
+        Ok(Box::new(super::coregraphics::CoreGraphicsSynth))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use super::display::{self, DisplayServer};
+        match display::detect() {
+            DisplayServer::X11 => match super::x11::XTestSynth::connect(None) {
+                Ok(synth) => Ok(Box::new(synth)),
+                Err(e) => {
+                    log_warn!("XTest unavailable ({}), falling back to uinput", e);
+                    Ok(Box::new(super::uinput::UinputSynth::open()?))
+                }
+            },
+            DisplayServer::Wayland => match super::wayland::WaylandSynth::connect(None) {
+                Ok(synth) => Ok(Box::new(synth)),
+                Err(e) => {
+                    log_warn!("Wayland virtual keyboard unavailable ({}), falling back to uinput", e);
+                    Ok(Box::new(super::uinput::UinputSynth::open()?))
+                }
+            },
+            // uinput sits below any display server, so it also works on the console
+            DisplayServer::Unknown => Ok(Box::new(super::uinput::UinputSynth::open()?)),
+        }
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        Err(SuperspeedError::Unsupported("No key synthesis backend available on this platform".to_string()))
+    }
+}
@@ -1,140 +1,135 @@
 // Read text from cursor using clipboard trick (like ito)
-use cocoa::appkit::{NSPasteboard, NSPasteboardTypeString};
-use cocoa::base::nil;
-use cocoa::foundation::{NSAutoreleasePool, NSString};
-use core_graphics::event::{CGEvent, CGEventFlags, CGEventTapLocation, CGKeyCode};
-use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
-use std::ffi::CStr;
-use std::thread;
-use std::time::Duration;
-
-// macOS key codes
-const KVK_LEFT_ARROW: u16 = 123;
-const KVK_RIGHT_ARROW: u16 = 124;
-const KVK_C: u16 = 8;
+use super::clipboard::{self, ClipboardMarkers};
+use super::synth::{self, Key, Modifiers};
+use crate::error::SuperspeedError;
+use crate::timing::{sleep_ms, TimingProfile};
+
+/// Options for reading the cursor context
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Markers asking clipboard managers to keep the copied text out of their history
+    pub markers: ClipboardMarkers,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions { markers: ClipboardMarkers::PRIVATE }
+    }
+}
 
 /// Read N characters before cursor using clipboard trick
 /// Returns the text before cursor (or error)
-pub fn read_cursor_context(char_count: usize) -> Result<String, String> {
-    unsafe {
-        let _pool = NSAutoreleasePool::new(nil);
-        let pasteboard = NSPasteboard::generalPasteboard(nil);
-
-        // Save original clipboard
-        let old_contents = pasteboard.stringForType(NSPasteboardTypeString);
-        let original_clipboard = if old_contents != nil {
-            let c_str = NSString::UTF8String(old_contents);
-            CStr::from_ptr(c_str).to_string_lossy().into_owned()
-        } else {
-            String::new()
-        };
+/// The app's own copy can't be marked, so it is swapped for a copy tagged with `markers`
+/// as soon as it is read; managers that sample the clipboard then skip it.
+pub fn read_cursor_context(char_count: usize, markers: ClipboardMarkers, timing: &TimingProfile) -> Result<String, SuperspeedError> {
+    // Save original clipboard (every item and flavor, not just text)
+    let original_clipboard = clipboard::with_backend(|pasteboard| {
+        let original = pasteboard.snapshot()?;
 
         // Clear clipboard
-        pasteboard.clearContents();
-
-        // Select previous N characters with Shift+Left Arrow
-        select_previous_chars(char_count)?;
-
-        // Copy selection with Cmd+C
-        simulate_cmd_c()?;
-
-        // Wait for clipboard to update
-        thread::sleep(Duration::from_millis(50));
-
-        // Read the selected text from clipboard
-        let current_contents = pasteboard.stringForType(NSPasteboardTypeString);
-        let context_text = if current_contents != nil {
-            let c_str = NSString::UTF8String(current_contents);
-            CStr::from_ptr(c_str).to_string_lossy().into_owned()
-        } else {
-            String::new()
-        };
-
-        // Restore cursor position (move right to deselect)
-        restore_cursor_position(char_count)?;
-
-        // Restore original clipboard
-        pasteboard.clearContents();
-        if !original_clipboard.is_empty() {
-            let ns_string = NSString::alloc(nil).init_str(&original_clipboard);
-            pasteboard.setString_forType(ns_string, NSPasteboardTypeString);
+        pasteboard.clear()?;
+        Ok(original)
+    })?;
+
+    let result = select_and_copy(char_count, markers, timing);
+
+    // Restore original clipboard, whether or not the read worked
+    let restored = clipboard::with_backend(|pasteboard| pasteboard.restore(&original_clipboard));
+    let context_text = result?;
+    restored?;
+
+    Ok(context_text)
+}
+
+// The keyboard half of a read; the caller owns the clipboard around it
+fn select_and_copy(char_count: usize, markers: ClipboardMarkers, timing: &TimingProfile) -> Result<String, SuperspeedError> {
+    // Select previous N characters with Shift+Left Arrow
+    select_previous_chars(char_count, timing)?;
+
+    // Copy selection with Cmd+C
+    simulate_cmd_c(timing)?;
+
+    // Wait for clipboard to update
+    sleep_ms(timing.clipboard_read_ms);
+
+    // Read the selected text from clipboard
+    let context_text = clipboard::with_backend(|pasteboard| {
+        let text = pasteboard.read_string()?.unwrap_or_default();
+        if !text.is_empty() && !markers.is_empty() {
+            pasteboard.write_marked(&text, markers)?;
         }
+        Ok(text)
+    })?;
 
-        Ok(context_text)
-    }
+    // Restore cursor position (move right to deselect)
+    restore_cursor_position(char_count, timing)?;
+
+    Ok(context_text)
 }
 
 /// Select N characters before cursor using Shift+Left Arrow
-fn select_previous_chars(char_count: usize) -> Result<(), String> {
-    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
-        .map_err(|_| "Failed to create event source")?;
-
-    for _ in 0..char_count {
-        // Press Shift+Left Arrow (selects one char to the left)
-        let left_down = CGEvent::new_keyboard_event(source.clone(), KVK_LEFT_ARROW as CGKeyCode, true)
-            .map_err(|_| "Failed to create left arrow down event")?;
-        let left_up = CGEvent::new_keyboard_event(source.clone(), KVK_LEFT_ARROW as CGKeyCode, false)
-            .map_err(|_| "Failed to create left arrow up event")?;
-
-        // Set Shift flag for selection
-        left_down.set_flags(CGEventFlags::CGEventFlagShift);
-        left_up.set_flags(CGEventFlags::CGEventFlagShift);
-
-        // Post events
-        left_down.post(CGEventTapLocation::HID);
-        thread::sleep(Duration::from_millis(2));
-        left_up.post(CGEventTapLocation::HID);
-        thread::sleep(Duration::from_millis(1));
-    }
+fn select_previous_chars(char_count: usize, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| {
+        for _ in 0..char_count {
+            // Press Shift+Left Arrow (selects one char to the left)
+            s.key_down(Key::LeftArrow, Modifiers::SHIFT)?;
+            sleep_ms(timing.arrow_down_ms);
+            s.key_up(Key::LeftArrow, Modifiers::SHIFT)?;
+            sleep_ms(timing.arrow_up_ms);
+        }
+        Ok(())
+    })?;
 
     // Allow selection to complete
-    thread::sleep(Duration::from_millis(10));
+    sleep_ms(timing.selection_settle_ms);
 
     Ok(())
 }
 
 /// Simulate Cmd+C to copy selection
-fn simulate_cmd_c() -> Result<(), String> {
-    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
-        .map_err(|_| "Failed to create event source")?;
-
-    let c_down = CGEvent::new_keyboard_event(source.clone(), KVK_C as CGKeyCode, true)
-        .map_err(|_| "Failed to create C down event")?;
-    let c_up = CGEvent::new_keyboard_event(source.clone(), KVK_C as CGKeyCode, false)
-        .map_err(|_| "Failed to create C up event")?;
-
-    // Set Command flag
-    c_down.set_flags(CGEventFlags::CGEventFlagCommand);
-    c_up.set_flags(CGEventFlags::CGEventFlagCommand);
-
-    // Post events
-    c_down.post(CGEventTapLocation::HID);
-    thread::sleep(Duration::from_millis(10));
-    c_up.post(CGEventTapLocation::HID);
-
-    Ok(())
+fn simulate_cmd_c(timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| {
+        s.key_down(Key::C, Modifiers::SHORTCUT)?;
+        sleep_ms(timing.copy_hold_ms);
+        s.key_up(Key::C, Modifiers::SHORTCUT)
+    })
 }
 
 /// Restore cursor position by moving right (deselects)
-fn restore_cursor_position(char_count: usize) -> Result<(), String> {
-    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
-        .map_err(|_| "Failed to create event source")?;
-
-    for _ in 0..char_count {
-        let right_down = CGEvent::new_keyboard_event(source.clone(), KVK_RIGHT_ARROW as CGKeyCode, true)
-            .map_err(|_| "Failed to create right arrow down event")?;
-        let right_up = CGEvent::new_keyboard_event(source.clone(), KVK_RIGHT_ARROW as CGKeyCode, false)
-            .map_err(|_| "Failed to create right arrow up event")?;
-
-        // Post events (no Shift flag = deselects while moving)
-        right_down.post(CGEventTapLocation::HID);
-        thread::sleep(Duration::from_millis(2));
-        right_up.post(CGEventTapLocation::HID);
-
-        if char_count > 1 {
-            thread::sleep(Duration::from_millis(1));
+fn restore_cursor_position(char_count: usize, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    synth::with_backend(|s| {
+        for _ in 0..char_count {
+            // No Shift flag = deselects while moving
+            s.key_down(Key::RightArrow, Modifiers::NONE)?;
+            sleep_ms(timing.arrow_down_ms);
+            s.key_up(Key::RightArrow, Modifiers::NONE)?;
+
+            if char_count > 1 {
+                sleep_ms(timing.arrow_up_ms);
+            }
         }
-    }
+        Ok(())
+    })
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::clipboard::{ClipboardItem, ClipboardSnapshot, MemoryClipboard};
+    use crate::sim;
+
+    #[test]
+    fn failed_reads_put_the_whole_clipboard_back() {
+        let _globals = sim::exclusive();
+        sim::install_virtual_clock();
+        let image = ClipboardItem { flavors: vec![("image/png".to_string(), vec![0x89, b'P', b'N', b'G'])] };
+        let original = ClipboardSnapshot { items: vec![ClipboardItem::text("caption"), image] };
+        let clipboard = MemoryClipboard::with_snapshot(original.clone());
+        clipboard::set_backend(Box::new(clipboard.clone()));
+        synth::set_backend(Box::new(sim::Unplugged));
+
+        assert!(read_cursor_context(5, ClipboardMarkers::PRIVATE, &TimingProfile::NATIVE).is_err());
+        assert_eq!(clipboard.items(), original);
+    }
 }
@@ -0,0 +1,31 @@
+// Direct Unicode typing
+// Types text through the key synthesis backend without touching the clipboard,
+// for apps that block paste and remote desktops that don't sync the clipboard.
+use super::simulate;
+use super::synth;
+use crate::error::SuperspeedError;
+use crate::logging;
+use crate::text;
+use crate::timing::{sleep_ms, TimingProfile};
+
+// User-perceived characters per typed chunk; apps drop input that arrives faster
+const CHUNK_GRAPHEMES: usize = 16;
+
+/// Type `text` at the caret; newlines become Shift+Enter so chat boxes don't submit
+pub fn type_text(text: &str, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    log_trace!("Typing text: {}", logging::redact(text));
+
+    let normalized = text::normalize_newlines(text);
+    for (index, line) in normalized.split('\n').enumerate() {
+        if index > 0 {
+            simulate::shift_enter(timing)?;
+        }
+        for chunk in text::chunks(line, CHUNK_GRAPHEMES, |_| 1) {
+            synth::with_backend(|s| s.type_text(chunk))?;
+            sleep_ms(timing.key_event_ms);
+        }
+    }
+
+    log_debug!("Text typed");
+    Ok(())
+}
@@ -0,0 +1,421 @@
+// Linux uinput key synthesis backend
+// Creates a virtual keyboard through /dev/uinput and writes evdev events to it.
+// The kernel feeds those events to libinput like a physical keyboard, so this
+// works under X11, any Wayland compositor and the console alike. Device setup
+// is described as a list of requests and events are encoded to plain bytes, so
+// both can be checked against any Write sink without a real device.
+
+use super::layout::{self, KeyboardLayout, LiveLayout};
+use super::synth::{ChordTracker, Key, KeySynth, Modifiers};
+use crate::clock;
+use crate::error::SuperspeedError;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::Duration;
+
+pub const DEVICE_PATH: &str = "/dev/uinput";
+pub const DEVICE_NAME: &str = "Superspeed Virtual Keyboard";
+
+// Event types and codes (from linux/input-event-codes.h)
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const SYN_REPORT: u16 = 0;
+
+const KEY_BACKSPACE: u16 = 14;
+const KEY_TAB: u16 = 15;
+const KEY_ENTER: u16 = 28;
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_LEFT: u16 = 105;
+const KEY_RIGHT: u16 = 106;
+const KEY_LEFTMETA: u16 = 125;
+// Last keyboard key code; mouse and joystick buttons start after it (BTN_MISC = 0x100)
+const KEY_MAX_KEYBOARD: u16 = 248;
+
+const BUS_VIRTUAL: u16 = 0x06;
+
+// Keys the device advertises besides the letter block; keys not enabled are dropped by the kernel
+const FIXED_KEYS: [Key; 8] = [
+    Key::Return,
+    Key::Tab,
+    Key::Backspace,
+    Key::LeftArrow,
+    Key::RightArrow,
+    Key::Shift,
+    Key::Command,
+    Key::Control,
+];
+
+// Time for the compositor / libinput to pick up a freshly created device;
+// events written before that are silently lost
+const DEVICE_SETTLE: Duration = Duration::from_millis(200);
+
+/// evdev key code for a logical key (Command maps to the Super/Meta key)
+/// Letter keys are looked up in `layout`: the session keymap decides what each code types.
+pub fn key_code(key: Key, layout: &KeyboardLayout) -> u16 {
+    match key {
+        Key::Return => KEY_ENTER,
+        Key::Tab => KEY_TAB,
+        Key::Backspace => KEY_BACKSPACE,
+        Key::LeftArrow => KEY_LEFT,
+        Key::RightArrow => KEY_RIGHT,
+        Key::Shift => KEY_LEFTSHIFT,
+        Key::Command => KEY_LEFTMETA,
+        Key::Control => KEY_LEFTCTRL,
+        Key::V | Key::C | Key::U => layout
+            .shortcut_position(key)
+            .and_then(layout::evdev_code)
+            .expect("shortcut letters always resolve to a known position"),
+    }
+}
+
+/// struct uinput_setup from linux/uinput.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UinputSetup {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+    pub name: [u8; 80],
+    pub ff_effects_max: u32,
+}
+
+impl UinputSetup {
+    /// Virtual-bus device called `name` (truncated to fit, always NUL-terminated)
+    pub fn new(name: &str) -> Self {
+        let mut buf = [0u8; 80];
+        let len = name.len().min(buf.len() - 1);
+        buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+        UinputSetup {
+            bustype: BUS_VIRTUAL,
+            vendor: 0x5353,
+            product: 0x0001,
+            version: 1,
+            name: buf,
+            ff_effects_max: 0,
+        }
+    }
+}
+
+/// One ioctl in the device setup sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupRequest {
+    /// UI_SET_EVBIT: enable an event type
+    EventBit(u16),
+    /// UI_SET_KEYBIT: enable a key code
+    KeyBit(u16),
+    /// UI_DEV_SETUP: name and identify the device
+    Setup(UinputSetup),
+    /// UI_DEV_CREATE: publish the device
+    Create,
+}
+
+/// Requests that turn an open /dev/uinput handle into a keyboard called `name`
+pub fn setup_requests(name: &str) -> Vec<SetupRequest> {
+    let mut requests = vec![SetupRequest::EventBit(EV_KEY)];
+    // The whole letter block, so shortcut letters work wherever the layout puts them
+    let us = KeyboardLayout::us();
+    requests.extend(FIXED_KEYS.iter().map(|&key| SetupRequest::KeyBit(key_code(key, &us))));
+    requests.extend(layout::positions().filter_map(layout::evdev_code).map(SetupRequest::KeyBit));
+    requests.push(SetupRequest::Setup(UinputSetup::new(name)));
+    requests.push(SetupRequest::Create);
+    requests
+}
+
+/// Requests for a keyboard that can re-emit any key a physical keyboard sends (the key listener's passthrough)
+pub fn passthrough_requests(name: &str) -> Vec<SetupRequest> {
+    let mut requests = vec![SetupRequest::EventBit(EV_KEY)];
+    requests.extend((1..=KEY_MAX_KEYBOARD).map(SetupRequest::KeyBit));
+    requests.push(SetupRequest::Setup(UinputSetup::new(name)));
+    requests.push(SetupRequest::Create);
+    requests
+}
+
+// ioctl request numbers (asm-generic/ioctl.h encoding, 'U' type from linux/uinput.h)
+const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | ((b'U' as u32) << 8) | nr
+}
+const IOC_NONE: u32 = 0;
+const IOC_WRITE: u32 = 1;
+const UI_DEV_CREATE: u32 = ioc(IOC_NONE, 1, 0);
+const UI_DEV_DESTROY: u32 = ioc(IOC_NONE, 2, 0);
+const UI_DEV_SETUP: u32 = ioc(IOC_WRITE, 3, std::mem::size_of::<UinputSetup>());
+const UI_SET_EVBIT: u32 = ioc(IOC_WRITE, 100, std::mem::size_of::<libc::c_int>());
+const UI_SET_KEYBIT: u32 = ioc(IOC_WRITE, 101, std::mem::size_of::<libc::c_int>());
+
+fn apply(file: &File, request: &SetupRequest) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    // SAFETY: fd is an open uinput handle and every argument matches its ioctl's type
+    let result = unsafe {
+        match request {
+            SetupRequest::EventBit(bit) => libc::ioctl(fd, UI_SET_EVBIT as _, *bit as libc::c_int),
+            SetupRequest::KeyBit(bit) => libc::ioctl(fd, UI_SET_KEYBIT as _, *bit as libc::c_int),
+            SetupRequest::Setup(setup) => libc::ioctl(fd, UI_DEV_SETUP as _, setup as *const UinputSetup),
+            SetupRequest::Create => libc::ioctl(fd, UI_DEV_CREATE as _),
+        }
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Size of one encoded struct input_event
+pub const EVENT_SIZE: usize = std::mem::size_of::<libc::input_event>();
+
+/// Encode one struct input_event; the kernel stamps the time itself
+pub fn encode_event(kind: u16, code: u16, value: i32) -> [u8; EVENT_SIZE] {
+    let event = libc::input_event {
+        time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+        type_: kind,
+        code,
+        value,
+    };
+    // SAFETY: input_event is plain old data with no padding invariants to uphold
+    unsafe { std::mem::transmute::<libc::input_event, [u8; EVENT_SIZE]>(event) }
+}
+
+/// Decode one struct input_event read from an evdev device into (type, code, value)
+pub fn decode_event(bytes: &[u8; EVENT_SIZE]) -> (u16, u16, i32) {
+    // SAFETY: every bit pattern is a valid input_event
+    let event = unsafe { std::mem::transmute::<[u8; EVENT_SIZE], libc::input_event>(*bytes) };
+    (event.type_, event.code, event.value)
+}
+
+/// A key press (1) or release (0) followed by the SYN_REPORT that delivers it
+pub fn encode_key(key: Key, layout: &KeyboardLayout, press: bool) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(EVENT_SIZE * 2);
+    bytes.extend_from_slice(&encode_event(EV_KEY, key_code(key, layout), press as i32));
+    bytes.extend_from_slice(&encode_event(EV_SYN, SYN_REPORT, 0));
+    bytes
+}
+
+/// Fail early with a clear message when `path` is missing or not writable
+pub fn check_permission(path: &Path) -> Result<(), SuperspeedError> {
+    if !path.exists() {
+        return Err(SuperspeedError::Unsupported(format!(
+            "{} not found: load the uinput kernel module (modprobe uinput)",
+            path.display()
+        )));
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| SuperspeedError::InteriorNul(e.nul_position()))?;
+    // SAFETY: c_path is a valid NUL-terminated string
+    if unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } != 0 {
+        return Err(SuperspeedError::PermissionDenied(format!(
+            "cannot write to {}: add the user to the group that owns it (usually 'input') or install a udev rule granting access",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// An open /dev/uinput keyboard; destroyed when dropped
+pub struct UinputDevice {
+    file: File,
+}
+
+impl UinputDevice {
+    /// Open `path` and create the virtual keyboard
+    pub fn create(path: &Path, name: &str) -> Result<Self, SuperspeedError> {
+        UinputDevice::create_with(path, name, &setup_requests(name))
+    }
+
+    /// Open `path` and create a device from `requests`
+    pub fn create_with(path: &Path, name: &str, requests: &[SetupRequest]) -> Result<Self, SuperspeedError> {
+        check_permission(path)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .map_err(|e| device_error(path, e))?;
+        for request in requests {
+            apply(&file, request).map_err(|e| device_error(path, e))?;
+        }
+        clock::sleep(DEVICE_SETTLE);
+        log_info!("Created uinput keyboard '{}'", name);
+        Ok(UinputDevice { file })
+    }
+}
+
+impl Write for UinputDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        // SAFETY: the fd stays open until `file` is dropped after this
+        unsafe {
+            libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY as _);
+        }
+    }
+}
+
+fn device_error(path: &Path, e: io::Error) -> SuperspeedError {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => SuperspeedError::PermissionDenied(format!("{}: {}", path.display(), e)),
+        _ => SuperspeedError::EventPost(format!("uinput: {}", e)),
+    }
+}
+
+/// Posts key events to a uinput device, or to any byte sink for testing
+pub struct UinputSynth<W: Write + Send = UinputDevice> {
+    sink: W,
+    layout: LiveLayout,
+    chords: ChordTracker,
+}
+
+impl UinputSynth<UinputDevice> {
+    /// Create the virtual keyboard at /dev/uinput
+    pub fn open() -> Result<Self, SuperspeedError> {
+        let device = UinputDevice::create(Path::new(DEVICE_PATH), DEVICE_NAME)?;
+        Ok(UinputSynth::with_sink(device))
+    }
+}
+
+impl<W: Write + Send> UinputSynth<W> {
+    /// Write encoded events to `sink` instead of a device
+    pub fn with_sink(sink: W) -> Self {
+        UinputSynth { sink, layout: LiveLayout::new(), chords: ChordTracker::default() }
+    }
+
+    /// Like with_sink, resolving shortcut letters against `layout` instead of the session's
+    pub fn with_layout(sink: W, layout: KeyboardLayout) -> Self {
+        UinputSynth { sink, layout: LiveLayout::fixed(layout), chords: ChordTracker::default() }
+    }
+
+    /// The sink events were written to
+    pub fn sink(&self) -> &W {
+        &self.sink
+    }
+}
+
+// Free function so key_down/key_up can borrow the sink alongside the chord tracker
+fn emit(sink: &mut impl Write, layout: &mut LiveLayout, key: Key, press: bool) -> Result<(), SuperspeedError> {
+    sink.write_all(&encode_key(key, layout.get(), press))
+        .and_then(|_| sink.flush())
+        .map_err(|e| SuperspeedError::EventPost(format!("uinput: {}", e)))
+}
+
+impl<W: Write + Send> KeySynth for UinputSynth<W> {
+    fn name(&self) -> &'static str {
+        "uinput"
+    }
+
+    fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        let UinputSynth { sink, layout, chords } = self;
+        chords.key_down(key, modifiers, |key, press| emit(sink, layout, key, press))
+    }
+
+    fn key_up(&mut self, key: Key, _modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        let UinputSynth { sink, layout, chords } = self;
+        chords.key_up(key, |key, press| emit(sink, layout, key, press))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Split a byte stream into (type, code, value) triples
+    fn events(bytes: &[u8]) -> Vec<(u16, u16, i32)> {
+        assert_eq!(bytes.len() % EVENT_SIZE, 0);
+        bytes.chunks_exact(EVENT_SIZE).map(|chunk| decode_event(chunk.try_into().unwrap())).collect()
+    }
+
+    fn key_bits(requests: &[SetupRequest]) -> Vec<u16> {
+        requests
+            .iter()
+            .filter_map(|request| match request {
+                SetupRequest::KeyBit(bit) => Some(*bit),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn events_use_the_kernel_input_event_layout() {
+        // struct input_event { struct timeval time; __u16 type; __u16 code; __s32 value; }
+        let time = std::mem::size_of::<libc::timeval>();
+        assert_eq!(EVENT_SIZE, time + 8);
+
+        let bytes = encode_event(EV_KEY, KEY_LEFTSHIFT, 1);
+        assert!(bytes[..time].iter().all(|&b| b == 0));
+        assert_eq!(bytes[time..time + 2], EV_KEY.to_ne_bytes());
+        assert_eq!(bytes[time + 2..time + 4], KEY_LEFTSHIFT.to_ne_bytes());
+        assert_eq!(bytes[time + 4..], 1i32.to_ne_bytes());
+        assert_eq!(decode_event(&bytes), (EV_KEY, KEY_LEFTSHIFT, 1));
+    }
+
+    #[test]
+    fn each_key_is_followed_by_a_sync_report() {
+        let us = KeyboardLayout::us();
+        assert_eq!(events(&encode_key(Key::Return, &us, true)), [(EV_KEY, KEY_ENTER, 1), (EV_SYN, SYN_REPORT, 0)]);
+        assert_eq!(events(&encode_key(Key::Return, &us, false)), [(EV_KEY, KEY_ENTER, 0), (EV_SYN, SYN_REPORT, 0)]);
+    }
+
+    #[test]
+    fn shortcut_letters_follow_the_layout() {
+        let dvorak = KeyboardLayout::fixture("us-dvorak").unwrap();
+        // V on US QWERTY is KEY_V (47); on Dvorak it is the key US calls period (KEY_DOT, 52)
+        assert_eq!(events(&encode_key(Key::V, &KeyboardLayout::us(), true))[0], (EV_KEY, 47, 1));
+        assert_eq!(events(&encode_key(Key::V, &dvorak, true))[0], (EV_KEY, 52, 1));
+    }
+
+    #[test]
+    fn paste_chord_writes_control_around_the_letter() {
+        let mut synth = UinputSynth::with_layout(Vec::new(), KeyboardLayout::us());
+        synth.key_down(Key::V, Modifiers::CONTROL).unwrap();
+        synth.key_up(Key::V, Modifiers::CONTROL).unwrap();
+
+        let keys: Vec<_> = events(synth.sink()).into_iter().filter(|&(kind, _, _)| kind == EV_KEY).collect();
+        assert_eq!(keys, [(EV_KEY, KEY_LEFTCTRL, 1), (EV_KEY, 47, 1), (EV_KEY, 47, 0), (EV_KEY, KEY_LEFTCTRL, 0)]);
+        assert_eq!(events(synth.sink()).len(), 8);
+    }
+
+    #[test]
+    fn setup_enables_every_key_the_backend_writes() {
+        let requests = setup_requests(DEVICE_NAME);
+        assert_eq!(requests.first(), Some(&SetupRequest::EventBit(EV_KEY)));
+        assert_eq!(requests.last(), Some(&SetupRequest::Create));
+        assert_eq!(requests[requests.len() - 2], SetupRequest::Setup(UinputSetup::new(DEVICE_NAME)));
+
+        let bits = key_bits(&requests);
+        let fixed = [KEY_ENTER, KEY_TAB, KEY_BACKSPACE, KEY_LEFT, KEY_RIGHT, KEY_LEFTSHIFT, KEY_LEFTMETA, KEY_LEFTCTRL];
+        assert!(fixed.iter().all(|code| bits.contains(code)));
+        // Wherever a layout puts the shortcut letters, the key is enabled
+        for name in KeyboardLayout::fixture_names() {
+            let layout = KeyboardLayout::fixture(name).unwrap();
+            for key in [Key::V, Key::C, Key::U] {
+                assert!(bits.contains(&key_code(key, &layout)), "{:?} on {}", key, name);
+            }
+        }
+        assert!(bits.iter().all(|&bit| bit <= KEY_MAX_KEYBOARD));
+    }
+
+    #[test]
+    fn passthrough_enables_the_whole_keyboard_range() {
+        let bits = key_bits(&passthrough_requests(DEVICE_NAME));
+        assert_eq!(bits, (1..=KEY_MAX_KEYBOARD).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn device_names_are_truncated_and_nul_terminated() {
+        let setup = UinputSetup::new(&"k".repeat(100));
+        assert_eq!(setup.name[78], b'k');
+        assert_eq!(setup.name[79], 0);
+        assert_eq!(&UinputSetup::new(DEVICE_NAME).name[..DEVICE_NAME.len() + 1], b"Superspeed Virtual Keyboard\0");
+    }
+}
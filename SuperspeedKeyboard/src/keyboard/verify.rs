@@ -0,0 +1,88 @@
+// Post-insert verification
+// Reads the text before the caret before and after an insertion attempt. Only
+// when the field is provably unchanged do we call the attempt missing, so a
+// slow or unreadable field never leads to the ghost text being inserted twice.
+use super::clipboard::ClipboardMarkers;
+use super::text_reader;
+use crate::logging;
+use crate::text;
+use crate::timing::TimingProfile;
+use std::sync::atomic::{AtomicBool, Ordering};
+use unicode_segmentation::UnicodeSegmentation;
+
+// Only the tail of long suggestions is read back; every character costs two arrow presses
+const MAX_VERIFY_GRAPHEMES: usize = 32;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn post-insert verification on or off
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// What reading the field back told us about an insertion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// The text before the caret ends with the inserted text
+    Verified,
+    /// The text before the caret is exactly what it was before: nothing landed
+    Missing,
+    /// The field changed but doesn't match, or couldn't be read
+    Unknown,
+}
+
+/// Graphemes read before and after inserting `text`
+fn read_len(text: &str) -> usize {
+    text::grapheme_count(text).min(MAX_VERIFY_GRAPHEMES)
+}
+
+/// Text before the caret ahead of inserting `text`; None if the field can't be read
+pub fn snapshot(text: &str, markers: ClipboardMarkers, timing: &TimingProfile) -> Option<String> {
+    match text_reader::read_cursor_context(read_len(text), markers, timing) {
+        Ok(before) => Some(before),
+        Err(e) => {
+            log_debug!("Cannot read the field before inserting: {}", e);
+            None
+        }
+    }
+}
+
+/// Check whether `text` now sits before the caret, given the snapshot taken beforehand
+pub fn verify(text: &str, before: Option<&str>, markers: ClipboardMarkers, timing: &TimingProfile) -> Verification {
+    let after = match text_reader::read_cursor_context(read_len(text), markers, timing) {
+        Ok(after) => after,
+        Err(e) => {
+            log_debug!("Cannot read the field after inserting: {}", e);
+            return Verification::Unknown;
+        }
+    };
+    log_trace!("Verification read: {}", logging::redact(&after));
+
+    let expected = tail(text, read_len(text));
+    if same_text(&after, &expected) {
+        Verification::Verified
+    } else if before.is_some_and(|before| before == after) {
+        Verification::Missing
+    } else {
+        Verification::Unknown
+    }
+}
+
+// Last `count` user-perceived characters of `text`, newlines normalized
+fn tail(text: &str, count: usize) -> String {
+    let normalized = text::normalize_newlines(text);
+    let graphemes: Vec<&str> = normalized.graphemes(true).collect();
+    graphemes[graphemes.len().saturating_sub(count)..].concat()
+}
+
+// Equal up to line endings: fields may store Shift+Enter as a line or paragraph separator
+fn same_text(a: &str, b: &str) -> bool {
+    let lines = |s: &str| -> String {
+        text::normalize_newlines(s).replace(['\u{2028}', '\u{2029}'], "\n")
+    };
+    lines(a) == lines(b)
+}
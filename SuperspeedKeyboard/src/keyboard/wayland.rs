@@ -0,0 +1,340 @@
+// Wayland key synthesis backend (zwp_virtual_keyboard_v1)
+// wlroots-based compositors (Sway, Hyprland, river, ...) accept key events from
+// a virtual keyboard bound to a seat. The client uploads its own XKB keymap, so
+// keycodes mean exactly what we say they mean: the logical keys get fixed
+// keycodes and any other character gets a keycode bound to its Unicode keysym
+// on demand. The compositor takes modifier state from explicit `modifiers`
+// requests, not from modifier key presses, so both are sent.
+// Runs against any compositor exposing the protocol, including headless ones
+// (`sway --headless`, `weston --backend=headless`) for testing.
+
+use super::synth::{Key, KeySynth, Modifiers};
+use crate::clock;
+use crate::error::SuperspeedError;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write as _;
+use std::os::fd::{AsFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Instant;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_keyboard::{KeyState, KeymapFormat};
+use wayland_client::protocol::{wl_registry, wl_seat::WlSeat};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+
+// XKB keycodes are evdev codes + 8; the protocol's `key` request takes evdev codes
+const XKB_OFFSET: u32 = 8;
+// First evdev code handed out to characters outside the fixed keys
+const FIRST_DYNAMIC_CODE: u32 = 200;
+const LAST_CODE: u32 = 255 - XKB_OFFSET;
+
+// Modifier masks for the standard XKB modifier map
+const MOD_SHIFT: u32 = 1 << 0;
+const MOD_CONTROL: u32 = 1 << 2;
+const MOD_SUPER: u32 = 1 << 6;
+
+/// evdev code, XKB key name and keysym name for each logical key
+fn fixed_key(key: Key) -> (u32, &'static str, &'static str) {
+    match key {
+        Key::Backspace => (14, "BKSP", "BackSpace"),
+        Key::Tab => (15, "TAB", "Tab"),
+        Key::U => (22, "AD07", "u"),
+        Key::Return => (28, "RTRN", "Return"),
+        Key::Control => (29, "LCTL", "Control_L"),
+        Key::Shift => (42, "LFSH", "Shift_L"),
+        Key::C => (46, "AB03", "c"),
+        Key::V => (47, "AB04", "v"),
+        Key::LeftArrow => (105, "LEFT", "Left"),
+        Key::RightArrow => (106, "RGHT", "Right"),
+        Key::Command => (125, "LWIN", "Super_L"),
+    }
+}
+
+const FIXED_KEYS: [Key; 11] = [
+    Key::Backspace,
+    Key::Tab,
+    Key::U,
+    Key::Return,
+    Key::Control,
+    Key::Shift,
+    Key::C,
+    Key::V,
+    Key::LeftArrow,
+    Key::RightArrow,
+    Key::Command,
+];
+
+fn modifier_mask(key: Key) -> u32 {
+    match key {
+        Key::Shift => MOD_SHIFT,
+        Key::Control => MOD_CONTROL,
+        Key::Command => MOD_SUPER,
+        _ => 0,
+    }
+}
+
+fn flags_mask(modifiers: Modifiers) -> u32 {
+    let mut mask = 0;
+    if modifiers.shift {
+        mask |= MOD_SHIFT;
+    }
+    if modifiers.control {
+        mask |= MOD_CONTROL;
+    }
+    if modifiers.command {
+        mask |= MOD_SUPER;
+    }
+    mask
+}
+
+/// The keymap uploaded to the compositor: fixed logical keys plus characters added on demand
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    chars: Vec<char>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Keymap::default()
+    }
+
+    /// evdev code for a logical key
+    pub fn key_code(key: Key) -> u32 {
+        fixed_key(key).0
+    }
+
+    /// evdev code typing `c`, if it already has one
+    pub fn char_code(&self, c: char) -> Option<u32> {
+        self.chars.iter().position(|&known| known == c).map(|index| FIRST_DYNAMIC_CODE + index as u32)
+    }
+
+    /// Make sure `c` has a keycode; returns it and whether the keymap changed
+    /// When the dynamic range is full, the oldest characters are recycled.
+    pub fn ensure_char(&mut self, c: char) -> (u32, bool) {
+        if let Some(code) = self.char_code(c) {
+            return (code, false);
+        }
+        if FIRST_DYNAMIC_CODE + self.chars.len() as u32 > LAST_CODE {
+            self.chars.clear();
+        }
+        self.chars.push(c);
+        (FIRST_DYNAMIC_CODE + self.chars.len() as u32 - 1, true)
+    }
+
+    /// Keymap in XKB text format (xkb_keymap_new_from_string)
+    pub fn to_xkb(&self) -> String {
+        let mut codes = String::new();
+        let mut symbols = String::new();
+        for key in FIXED_KEYS {
+            let (code, name, keysym) = fixed_key(key);
+            let _ = writeln!(codes, "        <{}> = {};", name, code + XKB_OFFSET);
+            let _ = writeln!(symbols, "        key <{}> {{ [ {} ] }};", name, keysym);
+        }
+        for (index, c) in self.chars.iter().enumerate() {
+            let code = FIRST_DYNAMIC_CODE + index as u32;
+            let _ = writeln!(codes, "        <I{}> = {};", code, code + XKB_OFFSET);
+            let _ = writeln!(symbols, "        key <I{}> {{ [ {} ] }};", code, unicode_keysym(*c));
+        }
+
+        format!(
+            "xkb_keymap {{\n\
+             \x20   xkb_keycodes \"superspeed\" {{\n\
+             \x20       minimum = 8;\n\
+             \x20       maximum = 255;\n\
+             {codes}\
+             \x20   }};\n\
+             \x20   xkb_types \"superspeed\" {{ include \"complete\" }};\n\
+             \x20   xkb_compat \"superspeed\" {{ include \"complete\" }};\n\
+             \x20   xkb_symbols \"superspeed\" {{\n\
+             {symbols}\
+             \x20       modifier_map Shift {{ <LFSH> }};\n\
+             \x20       modifier_map Control {{ <LCTL> }};\n\
+             \x20       modifier_map Mod4 {{ <LWIN> }};\n\
+             \x20   }};\n\
+             }};\n"
+        )
+    }
+}
+
+/// Keysym name for a character (U+XXXX form, understood by xkbcommon)
+fn unicode_keysym(c: char) -> String {
+    format!("U{:04X}", c as u32)
+}
+
+// Protocol objects only need their requests; none of them send events we use
+struct State;
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ignore ZwpVirtualKeyboardV1);
+
+/// Posts key events through a zwp_virtual_keyboard_v1 bound to the first seat
+pub struct WaylandSynth {
+    queue: EventQueue<State>,
+    keyboard: ZwpVirtualKeyboardV1,
+    keymap: Keymap,
+    started: Instant,
+    // Modifiers held by explicit key presses vs. requested as flags
+    held: u32,
+    chord: u32,
+}
+
+impl WaylandSynth {
+    /// Connect to `display` (None = $WAYLAND_DISPLAY) and create a virtual keyboard
+    pub fn connect(display: Option<&str>) -> Result<Self, SuperspeedError> {
+        let conn = match display {
+            Some(name) => {
+                let path = socket_path(name)?;
+                let stream = UnixStream::connect(&path).map_err(|e| {
+                    SuperspeedError::Unsupported(format!("Cannot connect to Wayland display {}: {}", path.display(), e))
+                })?;
+                Connection::from_socket(stream).map_err(wayland_error)?
+            }
+            None => Connection::connect_to_env()
+                .map_err(|e| SuperspeedError::Unsupported(format!("Cannot connect to Wayland display: {}", e)))?,
+        };
+
+        let (globals, queue) = registry_queue_init::<State>(&conn).map_err(wayland_error)?;
+        let qh = queue.handle();
+        let seat: WlSeat = globals
+            .bind(&qh, 1..=7, ())
+            .map_err(|e| SuperspeedError::Unsupported(format!("Wayland compositor has no seat: {}", e)))?;
+        let manager: ZwpVirtualKeyboardManagerV1 = globals.bind(&qh, 1..=1, ()).map_err(|_| {
+            SuperspeedError::Unsupported("Wayland compositor does not support zwp_virtual_keyboard_v1".to_string())
+        })?;
+        let keyboard = manager.create_virtual_keyboard(&seat, &qh, ());
+
+        let mut synth = WaylandSynth {
+            queue,
+            keyboard,
+            keymap: Keymap::new(),
+            started: clock::now(),
+            held: 0,
+            chord: 0,
+        };
+        synth.upload_keymap()?;
+        // Surface an unauthorized error (the manager may refuse untrusted clients) now, not on first key
+        synth.queue.roundtrip(&mut State).map_err(wayland_error)?;
+        Ok(synth)
+    }
+
+    /// Type one character, extending the keymap if it has no keycode yet
+    pub fn type_char(&mut self, c: char) -> Result<(), SuperspeedError> {
+        let (code, changed) = self.keymap.ensure_char(c);
+        if changed {
+            self.upload_keymap()?;
+        }
+        self.send_modifiers(self.held)?;
+        self.send_key(code, true)?;
+        self.send_key(code, false)
+    }
+
+    fn upload_keymap(&mut self) -> Result<(), SuperspeedError> {
+        let mut text = self.keymap.to_xkb().into_bytes();
+        text.push(0);
+        let file = memfd("superspeed-keymap")?;
+        (&file).write_all(&text).map_err(|e| wayland_error(format!("writing keymap: {}", e)))?;
+        self.keyboard.keymap(KeymapFormat::XkbV1.into(), file.as_fd(), text.len() as u32);
+        self.flush()
+    }
+
+    fn send_key(&mut self, code: u32, press: bool) -> Result<(), SuperspeedError> {
+        let time = (clock::now() - self.started).as_millis() as u32;
+        let state = if press { KeyState::Pressed } else { KeyState::Released };
+        self.keyboard.key(time, code, state.into());
+        self.flush()
+    }
+
+    fn send_modifiers(&mut self, depressed: u32) -> Result<(), SuperspeedError> {
+        self.keyboard.modifiers(depressed, 0, 0, 0);
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<(), SuperspeedError> {
+        // Round-trip so the event is processed before the caller's delay starts
+        self.queue.roundtrip(&mut State).map(|_| ()).map_err(wayland_error)
+    }
+}
+
+impl KeySynth for WaylandSynth {
+    fn name(&self) -> &'static str {
+        "wayland-virtual-keyboard"
+    }
+
+    fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        let mask = modifier_mask(key);
+        if mask != 0 {
+            self.held |= mask;
+            self.send_key(Keymap::key_code(key), true)?;
+            return self.send_modifiers(self.held | self.chord);
+        }
+
+        let wanted = flags_mask(modifiers) & !self.held;
+        if wanted != self.chord {
+            self.chord = wanted;
+            self.send_modifiers(self.held | self.chord)?;
+        }
+        self.send_key(Keymap::key_code(key), true)
+    }
+
+    fn key_up(&mut self, key: Key, _modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        let mask = modifier_mask(key);
+        if mask != 0 {
+            self.held &= !mask;
+            self.send_key(Keymap::key_code(key), false)?;
+            return self.send_modifiers(self.held | self.chord);
+        }
+
+        self.send_key(Keymap::key_code(key), false)?;
+        if self.chord != 0 {
+            self.chord = 0;
+            self.send_modifiers(self.held)?;
+        }
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        text.chars().try_for_each(|c| self.type_char(c))
+    }
+}
+
+fn socket_path(name: &str) -> Result<PathBuf, SuperspeedError> {
+    let path = PathBuf::from(name);
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| SuperspeedError::Unsupported("XDG_RUNTIME_DIR is not set".to_string()))?;
+    Ok(PathBuf::from(runtime_dir).join(path))
+}
+
+/// Anonymous in-memory file for passing data to the compositor by fd
+pub(crate) fn memfd(name: &str) -> Result<File, SuperspeedError> {
+    let c_name = std::ffi::CString::new(name).map_err(|e| SuperspeedError::InteriorNul(e.nul_position()))?;
+    // SAFETY: c_name is NUL-terminated; the returned fd is owned by the File below
+    let fd = unsafe { libc::memfd_create(c_name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(wayland_error(format!("memfd_create: {}", std::io::Error::last_os_error())));
+    }
+    // SAFETY: fd is a freshly created descriptor nobody else owns
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn wayland_error(e: impl std::fmt::Display) -> SuperspeedError {
+    SuperspeedError::EventPost(format!("Wayland: {}", e))
+}
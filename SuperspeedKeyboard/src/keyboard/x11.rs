@@ -0,0 +1,312 @@
+// X11 key synthesis backend (XTest)
+// XTest fakes physical key presses, so modifier flags become real modifier
+// key presses and every logical key is mapped keysym -> keycode through the
+// server's current keyboard mapping, re-read whenever the server announces a
+// layout change.
+
+use super::layout::{self, KeyboardLayout, LayoutKey};
+use super::synth::{ChordTracker, Key, KeySynth, Modifiers};
+use crate::error::SuperspeedError;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::protocol::xproto::{ConnectionExt as _, Keycode, Keysym, Mapping, Window, KEY_PRESS_EVENT, KEY_RELEASE_EVENT};
+use x11rb::protocol::xtest::{self, ConnectionExt as _};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+// X11 keysyms (from X11/keysymdef.h)
+const XK_RETURN: Keysym = 0xff0d;
+const XK_TAB: Keysym = 0xff09;
+const XK_BACKSPACE: Keysym = 0xff08;
+const XK_LEFT: Keysym = 0xff51;
+const XK_RIGHT: Keysym = 0xff53;
+const XK_SHIFT_L: Keysym = 0xffe1;
+const XK_CONTROL_L: Keysym = 0xffe3;
+const XK_SUPER_L: Keysym = 0xffeb;
+const XK_LOWER_V: Keysym = 0x0076;
+const XK_LOWER_C: Keysym = 0x0063;
+const XK_LOWER_U: Keysym = 0x0075;
+
+/// Keysym a logical key is typed with (Command maps to Super)
+pub fn keysym(key: Key) -> Keysym {
+    match key {
+        Key::Return => XK_RETURN,
+        Key::Tab => XK_TAB,
+        Key::Backspace => XK_BACKSPACE,
+        Key::LeftArrow => XK_LEFT,
+        Key::RightArrow => XK_RIGHT,
+        Key::Shift => XK_SHIFT_L,
+        Key::Control => XK_CONTROL_L,
+        Key::Command => XK_SUPER_L,
+        Key::V => XK_LOWER_V,
+        Key::C => XK_LOWER_C,
+        Key::U => XK_LOWER_U,
+    }
+}
+
+/// Keysym typing character `c` (Latin-1 keysyms match their code point, the rest use the Unicode range)
+pub fn char_keysym(c: char) -> Keysym {
+    match c as u32 {
+        0x09 => XK_TAB,
+        cp @ (0x20..=0x7e | 0xa0..=0xff) => cp,
+        cp => 0x0100_0000 + cp,
+    }
+}
+
+/// Character a keysym types, the inverse of char_keysym (None for function and dead keys)
+pub fn keysym_char(keysym: Keysym) -> Option<char> {
+    match keysym {
+        0x20..=0x7e | 0xa0..=0xff => char::from_u32(keysym),
+        0x0100_0100..=0x0110_ffff => char::from_u32(keysym - 0x0100_0000),
+        _ => None,
+    }
+}
+
+/// Snapshot of the server's keycode -> keysyms table
+#[derive(Debug, Clone)]
+pub struct KeyboardMapping {
+    min_keycode: Keycode,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<Keysym>,
+}
+
+impl KeyboardMapping {
+    pub fn new(min_keycode: Keycode, keysyms_per_keycode: u8, keysyms: Vec<Keysym>) -> Self {
+        KeyboardMapping {
+            min_keycode,
+            keysyms_per_keycode: keysyms_per_keycode as usize,
+            keysyms,
+        }
+    }
+
+    /// Keycode producing `keysym` in its unshifted column, falling back to any column
+    pub fn keycode_for(&self, keysym: Keysym) -> Option<Keycode> {
+        if self.keysyms_per_keycode == 0 {
+            return None;
+        }
+        let rows = || self.keysyms.chunks(self.keysyms_per_keycode).enumerate();
+        let found = rows()
+            .find(|(_, syms)| syms.first() == Some(&keysym))
+            .or_else(|| rows().find(|(_, syms)| syms.contains(&keysym)));
+        found.and_then(|(index, _)| Keycode::try_from(self.min_keycode as usize + index).ok())
+    }
+
+    /// Characters the first group puts on each layout position
+    /// The server numbers keys evdev code + 8; a letter whose Shift column is empty types its
+    /// uppercase form, as X clients do.
+    pub fn layout(&self) -> KeyboardLayout {
+        let keys = layout::positions()
+            .filter_map(|position| {
+                let index = (layout::evdev_code(position)? as usize + 8).checked_sub(self.min_keycode as usize)?;
+                let syms = self.keysyms.chunks(self.keysyms_per_keycode.max(1)).nth(index)?;
+                let base = syms.first().copied().and_then(keysym_char);
+                let shifted = match syms.get(1).copied().unwrap_or(0) {
+                    0 => base.filter(|c| c.is_lowercase()).and_then(|c| c.to_uppercase().next()),
+                    sym => keysym_char(sym),
+                };
+                Some(LayoutKey { position, base, shifted, command: None })
+            })
+            .collect();
+        KeyboardLayout::new("x11", keys)
+    }
+
+    /// Highest keycode with no keysyms bound, free to remap temporarily
+    pub fn spare_keycode(&self) -> Option<Keycode> {
+        if self.keysyms_per_keycode == 0 {
+            return None;
+        }
+        self.keysyms
+            .chunks(self.keysyms_per_keycode)
+            .enumerate()
+            .rev()
+            .find(|(_, syms)| syms.iter().all(|&sym| sym == 0))
+            .and_then(|(index, _)| Keycode::try_from(self.min_keycode as usize + index).ok())
+    }
+}
+
+/// Posts fake key events through the XTest extension
+pub struct XTestSynth {
+    conn: RustConnection,
+    root: Window,
+    mapping: KeyboardMapping,
+    chords: ChordTracker,
+}
+
+impl XTestSynth {
+    /// Connect to `display` (None = $DISPLAY) and load its keyboard mapping
+    pub fn connect(display: Option<&str>) -> Result<Self, SuperspeedError> {
+        let (conn, screen) = RustConnection::connect(display)
+            .map_err(|e| SuperspeedError::Unsupported(format!("Cannot connect to X server: {}", e)))?;
+
+        if conn.extension_information(xtest::X11_EXTENSION_NAME).map_err(x11_error)?.is_none() {
+            return Err(SuperspeedError::Unsupported("X server does not support the XTEST extension".to_string()));
+        }
+
+        let root = conn.setup().roots[screen].root;
+        let mapping = load_mapping(&conn)?;
+        Ok(XTestSynth { conn, root, mapping, chords: ChordTracker::default() })
+    }
+
+    // Every client gets MappingNotify after a layout switch or xmodmap; keycodes
+    // looked up in the old table would type the wrong keys from then on
+    fn refresh_mapping(&mut self) -> Result<(), SuperspeedError> {
+        let mut changed = false;
+        while let Some(event) = self.conn.poll_for_event().map_err(x11_error)? {
+            if let Event::MappingNotify(notify) = event {
+                changed |= notify.request == Mapping::KEYBOARD;
+            }
+        }
+        if changed {
+            log_debug!("Keyboard mapping changed, reloading");
+            self.mapping = load_mapping(&self.conn)?;
+        }
+        Ok(())
+    }
+}
+
+// Free function so key_down/key_up can borrow the connection alongside the chord tracker
+fn fake(conn: &RustConnection, root: Window, mapping: &KeyboardMapping, key: Key, press: bool) -> Result<(), SuperspeedError> {
+    let keycode = mapping
+        .keycode_for(keysym(key))
+        .ok_or_else(|| SuperspeedError::EventPost(format!("No keycode for {:?} in the current keymap", key)))?;
+    fake_keycode(conn, root, keycode, press)
+}
+
+fn fake_keycode(conn: &RustConnection, root: Window, keycode: Keycode, press: bool) -> Result<(), SuperspeedError> {
+    let event_type = if press { KEY_PRESS_EVENT } else { KEY_RELEASE_EVENT };
+
+    conn.xtest_fake_input(event_type, keycode, x11rb::CURRENT_TIME, root, 0, 0, 0)
+        .map_err(x11_error)?;
+    // Round-trip so the event is processed before the caller's delay starts
+    conn.sync().map_err(x11_error)?;
+    Ok(())
+}
+
+impl KeySynth for XTestSynth {
+    fn name(&self) -> &'static str {
+        "xtest"
+    }
+
+    fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        self.refresh_mapping()?;
+        let XTestSynth { conn, root, mapping, chords } = self;
+        chords.key_down(key, modifiers, |key, press| fake(conn, *root, mapping, key, press))
+    }
+
+    fn key_up(&mut self, key: Key, _modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        self.refresh_mapping()?;
+        let XTestSynth { conn, root, mapping, chords } = self;
+        chords.key_up(key, |key, press| fake(conn, *root, mapping, key, press))
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        // Bind each character to an unused keycode in turn and press it, like xdotool type
+        self.refresh_mapping()?;
+        let spare = self
+            .mapping
+            .spare_keycode()
+            .ok_or_else(|| SuperspeedError::EventPost("No unused keycode to type Unicode text with".to_string()))?;
+        let per_keycode = self.mapping.keysyms_per_keycode;
+        let bind = |keysym: Keysym| rebind(&self.conn, spare, per_keycode, keysym);
+
+        let result = text.chars().try_for_each(|c| {
+            bind(char_keysym(c))?;
+            fake_keycode(&self.conn, self.root, spare, true)?;
+            fake_keycode(&self.conn, self.root, spare, false)
+        });
+        // Leave the user's keymap as we found it
+        bind(0)?;
+        result
+    }
+}
+
+// Bind `keycode` to `keysym` and read the binding back: the reply comes after the server
+// has applied the change and sent MappingNotify to every client, so the key press that
+// follows is translated with the new binding rather than the previous character's
+fn rebind(conn: &RustConnection, keycode: Keycode, per_keycode: usize, keysym: Keysym) -> Result<(), SuperspeedError> {
+    conn.change_keyboard_mapping(1, keycode, per_keycode as u8, &vec![keysym; per_keycode])
+        .map_err(x11_error)?;
+    let reply = conn.get_keyboard_mapping(keycode, 1).map_err(x11_error)?.reply().map_err(x11_error)?;
+    if reply.keysyms.first() != Some(&keysym) {
+        return Err(SuperspeedError::EventPost(format!("X server did not bind keysym {:#x} to keycode {}", keysym, keycode)));
+    }
+    Ok(())
+}
+
+/// Layout of the server's current keyboard mapping, following switches made at runtime
+pub fn current_layout() -> Result<KeyboardLayout, SuperspeedError> {
+    let (conn, _) = RustConnection::connect(None)
+        .map_err(|e| SuperspeedError::Unsupported(format!("Cannot connect to X server: {}", e)))?;
+    Ok(load_mapping(&conn)?.layout())
+}
+
+fn load_mapping(conn: &RustConnection) -> Result<KeyboardMapping, SuperspeedError> {
+    let setup = conn.setup();
+    let (min, max) = (setup.min_keycode, setup.max_keycode);
+    let reply = conn
+        .get_keyboard_mapping(min, max - min + 1)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+    Ok(KeyboardMapping::new(min, reply.keysyms_per_keycode, reply.keysyms))
+}
+
+fn x11_error(e: impl std::fmt::Display) -> SuperspeedError {
+    SuperspeedError::EventPost(format!("X11: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapping_reads_back_as_a_layout() {
+        // Keycodes 38 (AC01) and 55 (AB04) on a server whose min keycode is 8
+        let mut keysyms = vec![0; (56 - 8) * 2];
+        keysyms[(38 - 8) * 2..][..2].copy_from_slice(&[char_keysym('q'), char_keysym('Q')]);
+        keysyms[(55 - 8) * 2] = char_keysym('é');
+        let layout = KeyboardMapping::new(8, 2, keysyms).layout();
+
+        assert_eq!(layout.position_of('q'), Some(("AC01", false)));
+        assert_eq!(layout.position_of('É'), Some(("AB04", true)));
+        assert_eq!(keysym_char(XK_RETURN), None);
+        assert_eq!(keysym_char(char_keysym('✓')), Some('✓'));
+    }
+
+    // These talk to a real server: xvfb-run cargo test -- --ignored
+    const NEEDS_X: &str = "needs an X server with XTEST";
+
+    fn connect() -> XTestSynth {
+        XTestSynth::connect(None).expect(NEEDS_X)
+    }
+
+    #[test]
+    #[ignore = "needs an X server with XTEST (xvfb-run cargo test -- --ignored)"]
+    fn typing_leaves_the_spare_keycode_unbound() {
+        let mut synth = connect();
+        let spare = synth.mapping.spare_keycode().unwrap();
+
+        synth.type_text("héllo ✓").unwrap();
+        synth.refresh_mapping().unwrap();
+        assert_eq!(synth.mapping.spare_keycode(), Some(spare));
+    }
+
+    #[test]
+    #[ignore = "needs an X server with XTEST (xvfb-run cargo test -- --ignored)"]
+    fn mapping_follows_changes_made_by_other_clients() {
+        let mut synth = connect();
+        let other = connect();
+        let spare = synth.mapping.spare_keycode().unwrap();
+        let per_keycode = synth.mapping.keysyms_per_keycode;
+        let keysym = char_keysym('✓');
+        assert_eq!(synth.mapping.keycode_for(keysym), None);
+
+        rebind(&other.conn, spare, per_keycode, keysym).unwrap();
+        // The round trip guarantees our MappingNotify has been sent before we look
+        synth.conn.sync().unwrap();
+        synth.refresh_mapping().unwrap();
+        assert_eq!(synth.mapping.keycode_for(keysym), Some(spare));
+
+        rebind(&other.conn, spare, per_keycode, 0).unwrap();
+    }
+}
@@ -0,0 +1,508 @@
+// X11 clipboard backend (CLIPBOARD / PRIMARY selections)
+// X11 keeps no clipboard contents of its own: the selection owner has to answer
+// every paste request. A background thread owns the event loop on a shared
+// connection, serves our contents (switching to INCR transfers for large data)
+// and forwards the replies to our own conversion requests back to the caller.
+
+use super::clipboard::{Clipboard, ClipboardItem, ClipboardSnapshot, Selection};
+use crate::error::SuperspeedError;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask, Property, PropMode,
+    SelectionNotifyEvent, SelectionRequestEvent, Window, WindowClass, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{CURRENT_TIME, NONE};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        CLIPBOARD,
+        TARGETS,
+        MULTIPLE,
+        TIMESTAMP,
+        SAVE_TARGETS,
+        DELETE,
+        TEXT,
+        UTF8_STRING,
+        INCR,
+        TEXT_PLAIN_UTF8: b"text/plain;charset=utf-8",
+        TEXT_PLAIN: b"text/plain",
+        SUPERSPEED_SELECTION,
+    }
+}
+
+impl Atoms {
+    fn selection(&self, selection: Selection) -> Atom {
+        match selection {
+            Selection::Clipboard => self.CLIPBOARD,
+            Selection::Primary => AtomEnum::PRIMARY.into(),
+        }
+    }
+
+    // Targets we can serve, in order of preference
+    fn text_targets(&self) -> [Atom; 5] {
+        [self.UTF8_STRING, self.TEXT_PLAIN_UTF8, self.TEXT, self.TEXT_PLAIN, AtomEnum::STRING.into()]
+    }
+
+    // Targets that ask the owner to do something rather than describe the data
+    fn is_meta_target(&self, target: Atom) -> bool {
+        [self.TARGETS, self.MULTIPLE, self.TIMESTAMP, self.SAVE_TARGETS, self.DELETE, self.TEXT, self.INCR].contains(&target)
+    }
+}
+
+// How long to wait for another client to answer a conversion request
+const CONVERT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// What we serve while we own the selection
+#[derive(Debug, Clone)]
+enum Contents {
+    /// Plain text, converted to whichever text target is asked for
+    Text(String),
+    /// A restored snapshot, served byte-for-byte; `targets[i]` is the atom of `item.flavors[i]`
+    Item { item: ClipboardItem, targets: Vec<Atom> },
+}
+
+impl Contents {
+    fn to_text(&self) -> Option<String> {
+        match self {
+            Contents::Text(text) => Some(text.clone()),
+            Contents::Item { item, .. } => item.to_text(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    contents: Option<Contents>,
+    owned: bool,
+    change_count: i64,
+}
+
+// An INCR transfer in progress: the next chunk goes out when the requestor deletes the property
+struct IncrTransfer {
+    requestor: Window,
+    property: Atom,
+    target: Atom,
+    data: Vec<u8>,
+    offset: usize,
+}
+
+/// CLIPBOARD or PRIMARY on an X server, served by a background owner thread
+pub struct X11Clipboard {
+    conn: Arc<RustConnection>,
+    atoms: Atoms,
+    window: Window,
+    selection: Atom,
+    shared: Arc<Mutex<Shared>>,
+    replies: Receiver<Event>,
+}
+
+impl X11Clipboard {
+    /// Connect to `display` (None = $DISPLAY) and start serving `selection`
+    pub fn connect(display: Option<&str>, selection: Selection) -> Result<Self, SuperspeedError> {
+        let (conn, screen) = RustConnection::connect(display)
+            .map_err(|e| SuperspeedError::Unsupported(format!("Cannot connect to X server: {}", e)))?;
+        let conn = Arc::new(conn);
+        let atoms = Atoms::new(&*conn).map_err(x11_error)?.reply().map_err(x11_error)?;
+
+        let root = conn.setup().roots[screen].root;
+        let window = conn.generate_id().map_err(x11_error)?;
+        conn.create_window(
+            0,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY),
+        )
+        .map_err(x11_error)?;
+        conn.flush().map_err(x11_error)?;
+
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let (sender, replies) = mpsc::channel();
+        let owner = Owner {
+            conn: conn.clone(),
+            atoms,
+            window,
+            selection: atoms.selection(selection),
+            shared: shared.clone(),
+            replies: sender,
+            transfers: Vec::new(),
+        };
+        thread::Builder::new()
+            .name("superspeed-x11-selection".to_string())
+            .spawn(move || owner.run())
+            .map_err(|e| SuperspeedError::Clipboard(format!("Cannot start selection owner thread: {}", e)))?;
+
+        Ok(X11Clipboard { conn, atoms, window, selection: atoms.selection(selection), shared, replies })
+    }
+
+    // Take the selection and serve `contents` from the owner thread
+    fn own(&mut self, contents: Contents) -> Result<(), SuperspeedError> {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared.contents = Some(contents);
+            shared.owned = true;
+            shared.change_count += 1;
+        }
+        self.conn.set_selection_owner(self.window, self.selection, CURRENT_TIME).map_err(x11_error)?;
+
+        // The server may refuse ownership (e.g. an outdated timestamp); check instead of assuming
+        let owner = self.conn.get_selection_owner(self.selection).map_err(x11_error)?.reply().map_err(x11_error)?;
+        if owner.owner != self.window {
+            self.shared.lock().unwrap().owned = false;
+            return Err(SuperspeedError::Clipboard("X server did not grant selection ownership".to_string()));
+        }
+        Ok(())
+    }
+
+    fn has_owner(&self) -> Result<bool, SuperspeedError> {
+        let owner = self.conn.get_selection_owner(self.selection).map_err(x11_error)?.reply().map_err(x11_error)?;
+        Ok(owner.owner != NONE)
+    }
+
+    fn atom_name(&self, atom: Atom) -> Result<String, SuperspeedError> {
+        let reply = self.conn.get_atom_name(atom).map_err(x11_error)?.reply().map_err(x11_error)?;
+        Ok(String::from_utf8_lossy(&reply.name).into_owned())
+    }
+
+    // Ask the current owner to convert the selection to `target` and read the result
+    fn convert(&mut self, target: Atom) -> Result<Option<Vec<u8>>, SuperspeedError> {
+        // Drop anything left over from an earlier timed-out request
+        while self.replies.try_recv().is_ok() {}
+
+        let property = self.atoms.SUPERSPEED_SELECTION;
+        self.conn
+            .convert_selection(self.window, self.selection, target, property, CURRENT_TIME)
+            .map_err(x11_error)?;
+        self.conn.flush().map_err(x11_error)?;
+
+        loop {
+            match self.next_reply()? {
+                Event::SelectionNotify(event) if event.property == NONE => return Ok(None),
+                Event::SelectionNotify(_) => break,
+                _ => continue,
+            }
+        }
+
+        let reply = self
+            .conn
+            .get_property(true, self.window, property, AtomEnum::ANY, 0, u32::MAX / 4)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        if reply.type_ != self.atoms.INCR {
+            return Ok(Some(reply.value));
+        }
+
+        // INCR: every time the owner stores a chunk, read and delete it; an empty chunk ends it
+        let mut data = Vec::new();
+        loop {
+            match self.next_reply()? {
+                Event::PropertyNotify(event) if event.atom == property && event.state == Property::NEW_VALUE => {}
+                _ => continue,
+            }
+            let chunk = self
+                .conn
+                .get_property(true, self.window, property, AtomEnum::ANY, 0, u32::MAX / 4)
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?;
+            if chunk.value.is_empty() {
+                return Ok(Some(data));
+            }
+            data.extend_from_slice(&chunk.value);
+        }
+    }
+
+    fn next_reply(&self) -> Result<Event, SuperspeedError> {
+        self.replies.recv_timeout(CONVERT_TIMEOUT).map_err(|e| match e {
+            RecvTimeoutError::Timeout => {
+                SuperspeedError::Clipboard("Selection owner did not answer the conversion request".to_string())
+            }
+            RecvTimeoutError::Disconnected => SuperspeedError::Clipboard("Selection owner thread stopped".to_string()),
+        })
+    }
+}
+
+impl Clipboard for X11Clipboard {
+    fn name(&self) -> &'static str {
+        "x11-selection"
+    }
+
+    fn read_string(&mut self) -> Result<Option<String>, SuperspeedError> {
+        {
+            let shared = self.shared.lock().unwrap();
+            if shared.owned {
+                return Ok(shared.contents.as_ref().and_then(Contents::to_text));
+            }
+        }
+
+        if !self.has_owner()? {
+            return Ok(None);
+        }
+        if let Some(bytes) = self.convert(self.atoms.UTF8_STRING)? {
+            return Ok(Some(String::from_utf8_lossy(&bytes).into_owned()));
+        }
+        // Older clients only offer Latin-1 STRING
+        Ok(self.convert(AtomEnum::STRING.into())?.map(|bytes| bytes.iter().map(|&b| b as char).collect()))
+    }
+
+    fn write_string(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        self.own(Contents::Text(text.to_string()))
+    }
+
+    fn clear(&mut self) -> Result<(), SuperspeedError> {
+        let was_owned = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.contents = None;
+            shared.change_count += 1;
+            std::mem::replace(&mut shared.owned, false)
+        };
+        if was_owned {
+            self.conn.set_selection_owner(NONE, self.selection, CURRENT_TIME).map_err(x11_error)?;
+            self.conn.flush().map_err(x11_error)?;
+        }
+        Ok(())
+    }
+
+    fn change_count(&mut self) -> Result<i64, SuperspeedError> {
+        // Counts our own writes and every time another client takes the selection from us
+        Ok(self.shared.lock().unwrap().change_count)
+    }
+
+    fn snapshot(&mut self) -> Result<ClipboardSnapshot, SuperspeedError> {
+        {
+            let shared = self.shared.lock().unwrap();
+            if shared.owned {
+                return Ok(match &shared.contents {
+                    Some(Contents::Text(text)) => ClipboardSnapshot::text(text),
+                    Some(Contents::Item { item, .. }) => ClipboardSnapshot { items: vec![item.clone()] },
+                    None => ClipboardSnapshot::default(),
+                });
+            }
+        }
+
+        if !self.has_owner()? {
+            return Ok(ClipboardSnapshot::default());
+        }
+        let Some(targets) = self.convert(self.atoms.TARGETS)? else {
+            // Owners that can't list their targets still answer text requests
+            log_debug!("Selection owner has no TARGETS, saving text only");
+            return Ok(self.read_string()?.map_or_else(ClipboardSnapshot::default, |text| ClipboardSnapshot::text(&text)));
+        };
+
+        // An X selection is a single item offered as several targets
+        let mut item = ClipboardItem::default();
+        let mut seen = Vec::new();
+        for target in targets.chunks_exact(4).map(|b| Atom::from_ne_bytes([b[0], b[1], b[2], b[3]])) {
+            if self.atoms.is_meta_target(target) || seen.contains(&target) {
+                continue;
+            }
+            seen.push(target);
+            let name = self.atom_name(target)?;
+            match self.convert(target) {
+                Ok(Some(data)) => item.flavors.push((name, data)),
+                Ok(None) => log_debug!("Selection owner refused to convert to {}", name),
+                Err(e) => log_debug!("Cannot save selection target {}: {}", name, e),
+            }
+        }
+        Ok(ClipboardSnapshot { items: vec![item] })
+    }
+
+    fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), SuperspeedError> {
+        let Some(item) = snapshot.items.iter().find(|item| !item.flavors.is_empty()) else {
+            return self.clear();
+        };
+        if snapshot.items.len() > 1 {
+            log_warn!("X11 selections hold one item; restoring the first of {}", snapshot.items.len());
+        }
+
+        let mut targets = Vec::with_capacity(item.flavors.len());
+        for (name, _) in &item.flavors {
+            let atom = self.conn.intern_atom(false, name.as_bytes()).map_err(x11_error)?.reply().map_err(x11_error)?;
+            targets.push(atom.atom);
+        }
+        self.own(Contents::Item { item: item.clone(), targets })
+    }
+}
+
+impl Drop for X11Clipboard {
+    fn drop(&mut self) {
+        // DestroyNotify on our window stops the owner thread
+        let _ = self.conn.destroy_window(self.window);
+        let _ = self.conn.flush();
+    }
+}
+
+// Event loop of the owner thread
+struct Owner {
+    conn: Arc<RustConnection>,
+    atoms: Atoms,
+    window: Window,
+    selection: Atom,
+    shared: Arc<Mutex<Shared>>,
+    replies: Sender<Event>,
+    transfers: Vec<IncrTransfer>,
+}
+
+impl Owner {
+    fn run(mut self) {
+        loop {
+            let event = match self.conn.wait_for_event() {
+                Ok(event) => event,
+                Err(e) => {
+                    log_warn!("X11 selection owner stopped: {}", e);
+                    return;
+                }
+            };
+            let result = match event {
+                Event::SelectionRequest(request) => self.serve(&request),
+                Event::SelectionClear(event) if event.selection == self.selection => {
+                    let mut shared = self.shared.lock().unwrap();
+                    shared.owned = false;
+                    shared.change_count += 1;
+                    log_debug!("Another client took the selection");
+                    Ok(())
+                }
+                Event::PropertyNotify(event) if event.window != self.window && event.state == Property::DELETE => {
+                    self.continue_transfer(event.window, event.atom)
+                }
+                Event::SelectionNotify(_) | Event::PropertyNotify(_) => {
+                    let _ = self.replies.send(event);
+                    Ok(())
+                }
+                Event::DestroyNotify(event) if event.window == self.window => return,
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                log_warn!("X11 selection request failed: {}", e);
+            }
+        }
+    }
+
+    fn serve(&mut self, request: &SelectionRequestEvent) -> Result<(), SuperspeedError> {
+        // Obsolete clients pass no property and expect the target name to be used
+        let property = if request.property == NONE { request.target } else { request.property };
+        let contents = self.shared.lock().unwrap().contents.clone();
+
+        let served = match contents {
+            Some(contents) if request.selection == self.selection => self.answer(request, property, &contents)?,
+            _ => false,
+        };
+
+        let notify = SelectionNotifyEvent {
+            response_type: SELECTION_NOTIFY_EVENT,
+            sequence: 0,
+            time: request.time,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if served { property } else { NONE },
+        };
+        self.conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify).map_err(x11_error)?;
+        self.conn.flush().map_err(x11_error)?;
+        Ok(())
+    }
+
+    // Store the requested conversion of `contents` on the requestor; false if the target is unsupported
+    fn answer(&mut self, request: &SelectionRequestEvent, property: Atom, contents: &Contents) -> Result<bool, SuperspeedError> {
+        let requestor = request.requestor;
+        if request.target == self.atoms.TARGETS {
+            let mut targets = vec![self.atoms.TARGETS];
+            match contents {
+                Contents::Text(_) => targets.extend(self.atoms.text_targets()),
+                Contents::Item { targets: item_targets, .. } => targets.extend(item_targets),
+            }
+            self.conn
+                .change_property32(PropMode::REPLACE, requestor, property, AtomEnum::ATOM, &targets)
+                .map_err(x11_error)?;
+            return Ok(true);
+        }
+
+        let (target, data) = match contents {
+            Contents::Text(text) => {
+                if !self.atoms.text_targets().contains(&request.target) {
+                    return Ok(false);
+                }
+                if request.target == u32::from(AtomEnum::STRING) {
+                    // STRING is Latin-1; anything outside it cannot be represented
+                    let latin1 = text.chars().map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' }).collect();
+                    (request.target, latin1)
+                } else if request.target == self.atoms.TEXT {
+                    (self.atoms.UTF8_STRING, text.as_bytes().to_vec())
+                } else {
+                    (request.target, text.as_bytes().to_vec())
+                }
+            }
+            Contents::Item { item, targets } => match targets.iter().position(|&t| t == request.target) {
+                Some(index) => (request.target, item.flavors[index].1.clone()),
+                None => return Ok(false),
+            },
+        };
+
+        if data.len() <= self.chunk_size() {
+            self.conn
+                .change_property8(PropMode::REPLACE, requestor, property, target, &data)
+                .map_err(x11_error)?;
+            return Ok(true);
+        }
+
+        // Too big for one request: announce INCR and send chunks as the requestor deletes the property
+        log_debug!("Starting INCR transfer of {} bytes", data.len());
+        self.conn
+            .change_window_attributes(requestor, &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE))
+            .map_err(x11_error)?;
+        self.conn
+            .change_property32(PropMode::REPLACE, requestor, property, self.atoms.INCR, &[data.len() as u32])
+            .map_err(x11_error)?;
+        self.transfers.retain(|t| !(t.requestor == requestor && t.property == property));
+        self.transfers.push(IncrTransfer { requestor, property, target, data, offset: 0 });
+        Ok(true)
+    }
+
+    fn continue_transfer(&mut self, requestor: Window, property: Atom) -> Result<(), SuperspeedError> {
+        let Some(index) = self.transfers.iter().position(|t| t.requestor == requestor && t.property == property) else {
+            return Ok(());
+        };
+        let chunk_size = self.chunk_size();
+        let transfer = &mut self.transfers[index];
+        let end = (transfer.offset + chunk_size).min(transfer.data.len());
+        let chunk = &transfer.data[transfer.offset..end];
+        // A zero-length chunk tells the requestor the transfer is complete
+        self.conn
+            .change_property8(PropMode::REPLACE, requestor, property, transfer.target, chunk)
+            .map_err(x11_error)?;
+
+        if chunk.is_empty() {
+            self.transfers.remove(index);
+            self.conn
+                .change_window_attributes(requestor, &ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT))
+                .map_err(x11_error)?;
+        } else {
+            transfer.offset = end;
+        }
+        self.conn.flush().map_err(x11_error)?;
+        Ok(())
+    }
+
+    // Largest property payload we send in one request
+    fn chunk_size(&self) -> usize {
+        (self.conn.maximum_request_bytes() / 4).max(4096)
+    }
+}
+
+fn x11_error(e: impl std::fmt::Display) -> SuperspeedError {
+    SuperspeedError::Clipboard(format!("X11: {}", e))
+}
@@ -0,0 +1,88 @@
+// X11 focused window introspection (EWMH)
+// The window manager publishes the focused window as _NET_ACTIVE_WINDOW on the
+// root window; the app identity comes from that window's WM_CLASS, _NET_WM_PID
+// and _NET_WM_NAME (WM_NAME for clients that predate EWMH).
+
+use crate::error::SuperspeedError;
+use crate::focus::FocusedTarget;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, Window};
+use x11rb::rust_connection::RustConnection;
+use x11rb::NONE;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_PID,
+        _NET_WM_NAME,
+        UTF8_STRING,
+    }
+}
+
+/// Window the window manager reports as active on `display` (None = $DISPLAY)
+pub fn focused_target(display: Option<&str>) -> Result<FocusedTarget, SuperspeedError> {
+    let (conn, screen) = RustConnection::connect(display)
+        .map_err(|e| SuperspeedError::Unsupported(format!("Cannot connect to X server: {}", e)))?;
+    let atoms = Atoms::new(&conn).map_err(x11_error)?.reply().map_err(x11_error)?;
+    let root = conn.setup().roots[screen].root;
+
+    let active = property(&conn, root, atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW.into())?
+        .and_then(|value| first_u32(&value))
+        .filter(|&window| window != NONE);
+    let Some(window) = active else {
+        return Err(SuperspeedError::Unsupported(
+            "The window manager does not report an active window (_NET_ACTIVE_WINDOW)".to_string(),
+        ));
+    };
+
+    let wm_class = property(&conn, window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?
+        .map(|value| parse_wm_class(&value))
+        .unwrap_or_default();
+    let pid = property(&conn, window, atoms._NET_WM_PID, AtomEnum::CARDINAL.into())?.and_then(|value| first_u32(&value));
+    let title = match property(&conn, window, atoms._NET_WM_NAME, atoms.UTF8_STRING)? {
+        Some(value) => Some(value),
+        None => property(&conn, window, AtomEnum::WM_NAME.into(), AtomEnum::ANY.into())?,
+    }
+    .map(|value| String::from_utf8_lossy(&value).into_owned());
+
+    Ok(FocusedTarget {
+        // The class half of WM_CLASS names the application; the instance can vary per window
+        app_id: wm_class.last().cloned(),
+        name: wm_class.last().cloned(),
+        pid,
+        title,
+        window_id: Some(window as u64),
+        wm_class,
+    })
+}
+
+/// Split a WM_CLASS value ("instance\0Class\0") into its non-empty parts
+pub fn parse_wm_class(value: &[u8]) -> Vec<String> {
+    value
+        .split(|&byte| byte == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).into_owned())
+        .collect()
+}
+
+// Raw bytes of `property` on `window`, or None if it isn't set
+fn property(conn: &RustConnection, window: Window, property: Atom, kind: Atom) -> Result<Option<Vec<u8>>, SuperspeedError> {
+    let reply = conn
+        .get_property(false, window, property, kind, 0, u32::MAX / 4)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+    if reply.type_ == NONE || reply.value.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(reply.value))
+}
+
+// First 32-bit item of a property value (WINDOW and CARDINAL are sent in native byte order)
+fn first_u32(value: &[u8]) -> Option<u32> {
+    value.get(..4).map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn x11_error(e: impl std::fmt::Display) -> SuperspeedError {
+    SuperspeedError::Unsupported(format!("X11: {}", e))
+}
@@ -1,158 +1,825 @@
+// FFI entry points take raw C pointers from Swift by design
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+#[macro_use]
+pub mod logging;
+
 pub mod keyboard {
     pub mod simulate;
     pub mod paste;
+    pub mod typing;
+    pub mod strategy;
+    pub mod verify;
     pub mod text_reader;
+    pub mod synth;
+    pub mod clipboard;
+    pub mod layout;
+    pub mod listen;
+    #[cfg(target_os = "macos")]
+    pub mod coregraphics;
+    #[cfg(target_os = "macos")]
+    pub mod event_tap;
+    #[cfg(target_os = "macos")]
+    pub mod pasteboard;
+    #[cfg(target_os = "linux")]
+    pub mod data_control;
+    #[cfg(target_os = "linux")]
+    pub mod display;
+    #[cfg(target_os = "linux")]
+    pub mod evdev;
+    #[cfg(target_os = "linux")]
+    pub mod uinput;
+    #[cfg(target_os = "linux")]
+    pub mod wayland;
+    #[cfg(target_os = "linux")]
+    pub mod x11;
+    #[cfg(target_os = "linux")]
+    pub mod x11_clipboard;
+    #[cfg(target_os = "linux")]
+    pub mod x11_focus;
 }
 
-use std::ffi::{CStr, c_char};
-use std::sync::Mutex;
+pub mod clock;
+pub mod error;
+pub mod focus;
+pub mod hotkey;
+pub mod intent;
+pub mod pause;
+pub mod profile;
+pub mod session;
+pub mod sim;
+pub mod text;
+pub mod timing;
 
-// Global state to track ghost text and old clipboard
-static OLD_CLIPBOARD: Mutex<Option<String>> = Mutex::new(None);
-static GHOST_TEXT_LENGTH: Mutex<usize> = Mutex::new(0);
+use error::{ErrorCode, SuperspeedError};
+use hotkey::HotkeyAction;
+use keyboard::clipboard::ClipboardMarkers;
+use keyboard::layout::KeyboardLayout;
+use keyboard::paste::{ClipboardRestore, SavedClipboard};
+use keyboard::text_reader::ReadOptions;
+use keyboard::strategy::{self, InsertOptions, InsertionMethod};
+use profile::{AppIdentity, Separator};
+use session::{GhostLayout, GhostSession, GhostState};
+use timing::TimingProfile;
+use std::ffi::{CStr, CString, c_char};
+use std::path::Path;
+use std::sync::{Mutex, Once};
+use std::time::Duration;
 
-/// FFI: Insert ghost text (Shift+Enter x2 + paste)
+// The current ghost text session
+static SESSION: Mutex<GhostSession> = Mutex::new(GhostSession::new());
+
+/// FFI: Insert ghost text (separator keys from the app's profile + paste)
+/// Shift+Enter x2 by default; two tabs instead in a terminal
 /// Saves old clipboard for later restore
+/// `options` picks the insertion method and clipboard markers; NULL uses the method
+/// set for the frontmost app and marks the clipboard write transient and concealed.
+/// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
 #[no_mangle]
+pub extern "C" fn superspeed_insert_ghost_text_with_options(text_ptr: *const c_char, options: *const InsertOptions) -> bool {
+    let options = unsafe { options.as_ref() }.copied().unwrap_or_default();
+    report(
+        InsertionMethod::from_i32(options.method)
+            .and_then(|method| read_c_str(text_ptr).map(|text| (text, method)))
+            .and_then(|(text, method)| insert_ghost_text(&text, method, options.markers)),
+    )
+    .is_some()
+}
+
+/// FFI: Deprecated, use superspeed_insert_ghost_text_with_options() with NULL options
+#[no_mangle]
+#[deprecated(note = "use superspeed_insert_ghost_text_with_options")]
 pub extern "C" fn superspeed_insert_ghost_text_v2(text_ptr: *const c_char) -> bool {
-    let text = unsafe {
-        if text_ptr.is_null() {
-            eprintln!("Null text pointer");
-            return false;
-        }
-        CStr::from_ptr(text_ptr).to_string_lossy().into_owned()
-    };
+    superspeed_insert_ghost_text_with_options(text_ptr, std::ptr::null())
+}
 
-    eprintln!("Rust: Insert ghost text: '{}'", text);
+fn insert_ghost_text(text: &str, method: InsertionMethod, markers: ClipboardMarkers) -> Result<(), SuperspeedError> {
+    log_info!("Insert ghost text: {}", logging::redact(text));
 
     if text.is_empty() {
-        return true;
+        return Ok(());
     }
 
-    // Step 1: Shift+Enter x2 for layout
-    eprintln!("Rust: Creating layout (Shift+Enter x2)");
-    if !keyboard::simulate::shift_enter() {
-        eprintln!("Rust: Shift+Enter 1 failed");
-        return false;
-    }
-    if !keyboard::simulate::shift_enter() {
-        eprintln!("Rust: Shift+Enter 2 failed");
-        return false;
+    keyboard::simulate::check_access()?;
+    let target = focus::current();
+    let identity = AppIdentity::from_target(&target);
+    let app = profile::lookup(&identity);
+    log_debug!("App profile: {}", app.name);
+    let timing = timing::resolve(identity.app_id(), &app);
+    let method = strategy::resolve(method, identity.app_id(), &app);
+    let layout = GhostLayout::for_app(&app);
+    // Terminals can't select with Shift+Arrow, so reading back would always look unchanged
+    let verify = keyboard::verify::enabled() && !layout.terminal;
+
+    if layout.terminal && text.contains(['\n', '\r']) {
+        return Err(SuperspeedError::Unsupported(
+            "Multi-line suggestions cannot be shown in a terminal: every line break would run the command".to_string(),
+        ));
     }
 
-    // Wait for Shift+Enter to complete before pasting
-    eprintln!("⏳ Waiting for layout to complete...");
-    std::thread::sleep(std::time::Duration::from_millis(100));
+    // Refuse to stack a second suggestion on top of a pending one
+    let mut session = SESSION.lock().unwrap();
+    session.begin_insert(text, layout, markers, target)?;
+    intent::with_buffer(|buffer| buffer.ghost_inserted());
+
+    // Step 1: Separate the suggestion from the user's text
+    if let Err(e) = type_separator(layout, &timing) {
+        return Err(fail_session(&mut session, None, e));
+    }
 
-    // Step 2: Save text length for later deletion
-    *GHOST_TEXT_LENGTH.lock().unwrap() = text.len();
+    // Wait for the separator to complete before pasting
+    log_debug!("Waiting for layout to complete");
+    timing::sleep_ms(timing.layout_settle_ms);
 
-    // Step 3: Paste ghost text (saves old clipboard internally)
-    eprintln!("Rust: Pasting ghost text");
-    match keyboard::paste::insert_via_clipboard_and_save(&text) {
-        Ok(old_clipboard) => {
-            // Save old clipboard to global for Tab/Esc handling
-            *OLD_CLIPBOARD.lock().unwrap() = old_clipboard;
-            eprintln!("Rust: ✅ Ghost text inserted");
-            true
+    // Step 2: Insert ghost text, falling back to other methods if it doesn't show up
+    log_debug!("Inserting ghost text ({:?})", method);
+    match strategy::insert_with_fallback(text, method, verify, markers, app.paste, &timing) {
+        Ok(insertion) => {
+            // Keep old clipboard in the session for Tab/Esc handling
+            session
+                .finish_insert(insertion.saved_clipboard.clone(), insertion.method)
+                .map_err(|e| fail_session(&mut session, insertion.saved_clipboard, e))
         }
         Err(e) => {
-            eprintln!("Rust: Paste failed: {}", e);
-            false
+            // Failed, not pending: a later reject must not backspace the user's own text.
+            // Some of it may have gone in anyway, so the typed-text buffer is dropped too.
+            log_error!("Ghost text was not inserted");
+            Err(fail_session(&mut session, None, e))
         }
     }
 }
 
+/// Helper: Line breaks below the user's text, or the tab delimiter on the same line
+/// Plain Enter is only used where the profile says it doesn't submit.
+fn type_separator(layout: GhostLayout, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    let line_break = match layout.separator {
+        Separator::ShiftEnter => keyboard::simulate::shift_enter,
+        Separator::Enter => keyboard::simulate::enter,
+        Separator::Tab => {
+            log_debug!("Typing delimiter (Tab x{})", layout.separator_count);
+            return keyboard::simulate::type_delimiter(layout.separator_count, timing)
+                .inspect_err(|_| log_error!("Typing the delimiter failed"));
+        }
+    };
+
+    log_debug!("Creating layout ({:?} x{})", layout.separator, layout.separator_count);
+    for i in 0..layout.separator_count {
+        if let Err(e) = line_break(timing) {
+            log_error!("{:?} {} failed", layout.separator, i + 1);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
 /// FFI: Accept ghost text (Tab key)
 /// Restores old clipboard, keeps ghost text
+/// In a terminal the user's request is replaced by the suggested command; Enter is never pressed.
+/// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
 #[no_mangle]
 pub extern "C" fn superspeed_accept_ghost_text() -> bool {
-    eprintln!("Rust: Accept ghost text (Tab)");
+    report(accept_ghost_text()).is_some()
+}
 
-    // Just restore old clipboard
-    if let Err(e) = restore_old_clipboard() {
-        eprintln!("Rust: Failed to restore clipboard: {}", e);
-        return false;
+fn accept_ghost_text() -> Result<(), SuperspeedError> {
+    log_info!("Accept ghost text (Tab)");
+
+    let mut session = SESSION.lock().unwrap();
+    if session.layout().terminal {
+        session.expect_pending("accept")?;
+        keyboard::simulate::check_access()?;
+        if let Err(e) = replace_intent(&session, &timing::current()) {
+            return Err(fail_session(&mut session, None, e));
+        }
     }
+    let old_clipboard = session.accept()?;
+    pause::with_detector(|detector| detector.accepted());
+    // In a terminal the line was replaced; elsewhere the separator and suggestion stay after the user's text
+    let kept = format!("{}{}", session.layout().separator_text(), session.text());
+    intent::with_buffer(|buffer| {
+        if session.layout().terminal {
+            buffer.invalidate();
+        } else {
+            buffer.ghost_accepted(&kept);
+        }
+    });
+
+    // Just restore old clipboard
+    restore_old_clipboard(&mut session, old_clipboard)?;
+
+    log_info!("Ghost text accepted");
+    Ok(())
+}
+
+/// Helper: In a terminal, swap the user's request and the delimiter for the suggested command
+/// Ctrl+U clears the whole line, then the command goes back in the way it was first inserted.
+fn replace_intent(session: &GhostSession, timing: &TimingProfile) -> Result<(), SuperspeedError> {
+    log_debug!("Replacing terminal input with the suggestion (Ctrl+U)");
+    keyboard::simulate::kill_line(timing)?;
+    timing::sleep_ms(timing.layout_settle_ms);
 
-    eprintln!("Rust: ✅ Ghost text accepted, clipboard restored");
-    true
+    let method = session.insertion_method().unwrap_or(InsertionMethod::Clipboard);
+    // Paste the way the app the suggestion went into expects
+    let app = session.target().map_or_else(profile::current, |target| profile::lookup(&AppIdentity::from_target(target)));
+    // A clipboard snapshot taken now only holds our own ghost text; the session keeps the user's
+    method.strategy().insert(session.text(), session.markers(), app.paste, timing)?;
+    // The terminal reads the clipboard when it gets to the paste; the user's contents
+    // go back right after this returns, so give it that time first
+    timing::sleep_ms(timing.layout_settle_ms);
+    Ok(())
 }
 
 /// FFI: Reject ghost text (Esc key)
 /// Deletes ghost text and restores old clipboard
+/// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
 #[no_mangle]
 pub extern "C" fn superspeed_reject_ghost_text() -> bool {
-    eprintln!("Rust: Reject ghost text (Esc)");
+    report(reject_ghost_text()).is_some()
+}
+
+fn reject_ghost_text() -> Result<(), SuperspeedError> {
+    log_info!("Reject ghost text (Esc)");
+
+    let mut session = SESSION.lock().unwrap();
+    session.expect_pending("reject")?;
+    keyboard::simulate::check_access()?;
+    warn_if_focus_moved(&session);
+    let timing = timing::current();
 
     // Step 1: Delete ghost text (backspace N times)
-    let delete_count = {
-        let length = *GHOST_TEXT_LENGTH.lock().unwrap();
-        length + 2  // Ghost text + 2 newlines from Shift+Enter
-    };
+    // Ghost text + newlines from Shift+Enter (or the terminal delimiter)
+    let delete_count = session.delete_count();
 
-    eprintln!("Rust: Deleting {} characters", delete_count);
+    log_debug!("Deleting {} characters", delete_count);
     for i in 0..delete_count {
-        if !keyboard::simulate::backspace() {
-            eprintln!("Rust: Backspace {} failed", i);
-            return false;
+        if let Err(e) = keyboard::simulate::backspace(&timing) {
+            log_error!("Backspace {} failed", i);
+            return Err(fail_session(&mut session, None, e));
         }
     }
 
+    let old_clipboard = session.reject()?;
+    pause::with_detector(|detector| detector.rejected());
+    intent::with_buffer(|buffer| buffer.ghost_rejected());
+
     // Step 2: Restore old clipboard
-    if let Err(e) = restore_old_clipboard() {
-        eprintln!("Rust: Failed to restore clipboard: {}", e);
-        return false;
+    restore_old_clipboard(&mut session, old_clipboard)?;
+
+    log_info!("Ghost text rejected");
+    Ok(())
+}
+
+/// Helper: Log when the user has switched windows since the ghost text was inserted
+/// The backspaces would then land in the wrong window; the host should reject before focus moves.
+fn warn_if_focus_moved(session: &GhostSession) {
+    let Some(inserted_into) = session.target() else { return };
+    let now = focus::current();
+    if inserted_into.window_id.is_some() && now.window_id.is_some() && !inserted_into.same_window(&now) {
+        log_warn!(
+            "Focus moved from {} to {} since the ghost text was inserted",
+            inserted_into.app_id.as_deref().unwrap_or("unknown app"),
+            now.app_id.as_deref().unwrap_or("unknown app")
+        );
+    }
+}
+
+/// Helper: End a session that went wrong, giving back the clipboard it held
+/// `unclaimed` is a clipboard saved by an insert the session never took over. The field's
+/// contents are unknown afterwards, so the typed-text buffer is dropped as well.
+fn fail_session(session: &mut GhostSession, unclaimed: Option<SavedClipboard>, error: SuperspeedError) -> SuperspeedError {
+    let saved = session.fail().or(unclaimed);
+    intent::with_buffer(|buffer| buffer.invalidate());
+    if let Err(e) = restore_old_clipboard(session, saved) {
+        log_error!("Could not restore the clipboard after a failed ghost text action: {}", e);
     }
+    error
+}
 
-    eprintln!("Rust: ✅ Ghost text rejected, clipboard restored");
-    true
+/// Helper: Restore the clipboard saved by a ghost session, unless the user has copied since
+fn restore_old_clipboard(session: &mut GhostSession, old_clipboard: Option<SavedClipboard>) -> Result<(), SuperspeedError> {
+    let outcome = match old_clipboard {
+        Some(saved) => keyboard::paste::restore_clipboard_if_ours(&saved, session.text())?,
+        None => {
+            log_debug!("No old clipboard to restore");
+            ClipboardRestore::NotNeeded
+        }
+    };
+    session.set_clipboard_restore(outcome);
+    Ok(())
 }
 
-/// Helper: Restore old clipboard from global state
-fn restore_old_clipboard() -> Result<(), String> {
-    let old_clipboard = OLD_CLIPBOARD.lock().unwrap().take();
+/// FFI: Accept, reject and regenerate with hotkeys handled inside the crate
+/// While ghost text is pending the bound keys (Tab and Esc by default) are swallowed
+/// and run the action, so the host no longer has to watch the keyboard. Needs
+/// Accessibility permission on macOS and read access to /dev/input on Linux.
+/// IMPORTANT: Call from the main thread; on macOS hotkey actions are queued onto it.
+#[no_mangle]
+pub extern "C" fn superspeed_start_hotkey_listener() -> bool {
+    install_key_observers();
+    // Keys typed while nobody listened never reached the buffer
+    intent::with_buffer(|buffer| buffer.invalidate());
+    report(hotkey::start(ghost_text_pending, run_hotkey)).is_some()
+}
 
-    if let Some(old_text) = old_clipboard {
-        keyboard::paste::restore_clipboard(&old_text)
-    } else {
-        eprintln!("Rust: No old clipboard to restore");
+/// FFI: Stop handling hotkeys; keys reach applications untouched again
+/// IMPORTANT: Call from the main thread.
+#[no_mangle]
+pub extern "C" fn superspeed_stop_hotkey_listener() {
+    hotkey::stop();
+}
+
+/// FFI: Bind a SuperspeedHotkeyAction to a key, e.g. "tab", "escape", "primary+shift+r"
+/// Modifiers must match exactly; `hotkey` NULL unbinds the action.
+#[no_mangle]
+pub extern "C" fn superspeed_set_hotkey(action: i32, hotkey: *const c_char) -> bool {
+    report(HotkeyAction::from_i32(action).and_then(|action| {
+        let hotkey = match read_optional_c_str(hotkey)? {
+            Some(text) => Some(text.parse().map_err(SuperspeedError::Unsupported)?),
+            None => None,
+        };
+        hotkey::set_binding(action, hotkey);
         Ok(())
-    }
+    }))
+    .is_some()
+}
+
+/// FFI: Be told when a hotkey has run (pass NULL to stop)
+/// REGENERATE has already deleted the ghost text; the host fetches and inserts a new suggestion.
+/// When `succeeded` is false, superspeed_last_error_code() and _message() called from the
+/// callback say why.
+#[no_mangle]
+pub extern "C" fn superspeed_set_hotkey_callback(callback: Option<extern "C" fn(action: i32, succeeded: bool)>) {
+    hotkey::set_callback(callback);
+}
+
+/// Helper: Let the crate's bookkeeping see keys from the listener (once per process)
+fn install_key_observers() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        keyboard::listen::add_observer(pause::observe);
+        keyboard::listen::add_observer(intent::observe);
+    });
+}
+
+/// FFI: Set how long the user must stop typing before it counts as a pause
+/// `threshold_ms` defaults to 3000. After an accept, keystrokes are ignored for
+/// `accept_cooldown_ms` (default 3000) so continuing to type doesn't trigger again.
+#[no_mangle]
+pub extern "C" fn superspeed_configure_typing_pause(threshold_ms: u32, accept_cooldown_ms: u32) {
+    pause::with_detector(|detector| {
+        detector.set_threshold(Duration::from_millis(threshold_ms as u64));
+        detector.set_accept_cooldown(Duration::from_millis(accept_cooldown_ms as u64));
+    });
+}
+
+/// FFI: Record a keystroke by the user, for hosts that watch the keyboard themselves
+/// Not needed while the hotkey listener runs: it feeds every key to the pause detector.
+/// Don't report modifier-only presses or keys the host posted itself.
+#[no_mangle]
+pub extern "C" fn superspeed_record_keystroke() {
+    pause::with_detector(|detector| detector.keystroke());
+}
+
+/// FFI: Whether the user has paused after typing; true once per pause
+/// Poll from a timer; superspeed_ms_until_typing_pause() says when to look next.
+#[no_mangle]
+pub extern "C" fn superspeed_poll_typing_pause() -> bool {
+    pause::with_detector(|detector| detector.poll())
+}
+
+/// FFI: Milliseconds until the current burst of typing becomes a pause, or -1 if there is none
+#[no_mangle]
+pub extern "C" fn superspeed_ms_until_typing_pause() -> i64 {
+    pause::with_detector(|detector| detector.time_until_pause()).map_or(-1, |left| left.as_millis() as i64)
+}
+
+/// FFI: Tell the pause detector whether the focused field is a password field
+/// While it is, typing never reports a pause.
+#[no_mangle]
+pub extern "C" fn superspeed_set_secure_field(secure: bool) {
+    pause::with_detector(|detector| detector.set_secure_field(secure));
+}
+
+/// FFI: Tell the intent buffer that focus entered a text field
+/// `field_empty` says the field has no text yet; then everything the user types is
+/// known from the first key and superspeed_read_cursor_context() can answer without
+/// touching the clipboard. Call on every focus change: clicks are not seen on Linux.
+#[no_mangle]
+pub extern "C" fn superspeed_reset_intent_buffer(field_empty: bool) {
+    intent::with_buffer(|buffer| buffer.reset(field_empty));
+}
+
+/// Helper: Whether hotkeys apply right now (asked on the listener thread, so never waits for the session)
+fn ghost_text_pending() -> bool {
+    // Busy means an insert, accept or reject is running: not a time to trigger another
+    SESSION.try_lock().is_ok_and(|session| session.state() == GhostState::Pending)
+}
+
+/// Helper: Run the action a hotkey is bound to, recording the outcome like an FFI call
+fn run_hotkey(action: HotkeyAction) -> bool {
+    report(match action {
+        HotkeyAction::Accept => accept_ghost_text(),
+        // The host is told afterwards and inserts the next suggestion
+        HotkeyAction::Reject | HotkeyAction::Regenerate => reject_ghost_text(),
+    })
+    .is_some()
 }
 
 /// FFI: Read cursor context (text before cursor)
 /// Returns null-terminated C string, or null pointer on error
 /// Caller must free the returned string with superspeed_free_string()
+/// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
 #[no_mangle]
 pub extern "C" fn superspeed_read_cursor_context(char_count: usize) -> *mut c_char {
-    eprintln!("Rust: Reading {} characters before cursor", char_count);
-
-    match keyboard::text_reader::read_cursor_context(char_count) {
-        Ok(text) => {
-            eprintln!("Rust: ✅ Read cursor context: '{}'", text);
-            // Convert Rust String to C string
-            match std::ffi::CString::new(text) {
-                Ok(c_string) => c_string.into_raw(),
-                Err(e) => {
-                    eprintln!("Rust: Failed to convert to C string: {}", e);
-                    std::ptr::null_mut()
-                }
-            }
-        }
-        Err(e) => {
-            eprintln!("Rust: ❌ Failed to read cursor context: {}", e);
-            std::ptr::null_mut()
+    superspeed_read_cursor_context_with_options(char_count, std::ptr::null())
+}
+
+/// FFI: Read cursor context with explicit options
+/// `options` sets the markers the copied text is tagged with for clipboard managers;
+/// NULL marks it transient and concealed.
+/// Caller must free the returned string with superspeed_free_string()
+/// IMPORTANT: Call from the main thread (NSPasteboard is not thread-safe).
+#[no_mangle]
+pub extern "C" fn superspeed_read_cursor_context_with_options(char_count: usize, options: *const ReadOptions) -> *mut c_char {
+    let options = unsafe { options.as_ref() }.copied().unwrap_or_default();
+    report(read_cursor_context(char_count, options.markers).and_then(into_c_string))
+        .map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+fn read_cursor_context(char_count: usize, markers: ClipboardMarkers) -> Result<String, SuperspeedError> {
+    log_debug!("Reading {} characters before cursor", char_count);
+
+    // Without the listener the buffer isn't fed and may be stale
+    if keyboard::listen::is_running() {
+        if let Some(text) = intent::with_buffer(|buffer| buffer.context(char_count)) {
+            log_info!("Read cursor context from typed keys: {}", logging::redact(&text));
+            return Ok(text);
         }
     }
+
+    keyboard::simulate::check_access()?;
+    let text = keyboard::text_reader::read_cursor_context(char_count, markers, &timing::current())?;
+
+    log_info!("Read cursor context: {}", logging::redact(&text));
+    Ok(text)
+}
+
+/// FFI: Describe the focused application and window as JSON
+/// {"app_id", "name", "pid", "title", "window_id", "wm_class"}; unknown fields are null.
+/// Returns null if the platform cannot tell (Wayland, no graphical session).
+/// Caller must free the returned string with superspeed_free_string()
+#[no_mangle]
+pub extern "C" fn superspeed_focused_target_json() -> *mut c_char {
+    report(focus::query().and_then(|target| target_json(&target)))
+        .map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// FFI: The application and window the current ghost session was inserted into, as JSON
+/// Same shape as superspeed_focused_target_json(); null before the first insert.
+/// Caller must free the returned string with superspeed_free_string()
+#[no_mangle]
+pub extern "C" fn superspeed_ghost_session_target_json() -> *mut c_char {
+    let target = SESSION.lock().unwrap().target().cloned();
+    match target {
+        Some(target) => report(target_json(&target)).map_or(std::ptr::null_mut(), CString::into_raw),
+        None => std::ptr::null_mut(),
+    }
 }
 
 /// FFI: Free string allocated by Rust
+/// Safe to call with NULL pointer (no-op)
 #[no_mangle]
 pub extern "C" fn superspeed_free_string(ptr: *mut c_char) {
     if !ptr.is_null() {
         unsafe {
-            let _ = std::ffi::CString::from_raw(ptr);
+            let _ = CString::from_raw(ptr);
+        }
+    }
+}
+
+/// FFI: Error code of the most recent failed call on this thread (0 if it succeeded)
+#[no_mangle]
+pub extern "C" fn superspeed_last_error_code() -> i32 {
+    error::last_error().map_or(ErrorCode::Ok, |e| e.code()) as i32
+}
+
+/// FFI: Human-readable message for the most recent failed call on this thread
+/// Returns null if the last call succeeded
+/// Caller must free the returned string with superspeed_free_string()
+#[no_mangle]
+pub extern "C" fn superspeed_last_error_message() -> *mut c_char {
+    match error::last_error() {
+        Some(e) => CString::new(e.to_string()).map_or(std::ptr::null_mut(), CString::into_raw),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// FFI: Set the timing profile for an application identifier (NULL = fallback for all apps)
+#[no_mangle]
+pub extern "C" fn superspeed_set_timing_profile(app_id: *const c_char, profile: *const TimingProfile) -> bool {
+    report(set_timing_profile(app_id, profile)).is_some()
+}
+
+fn set_timing_profile(app_id: *const c_char, profile: *const TimingProfile) -> Result<(), SuperspeedError> {
+    if profile.is_null() {
+        return Err(SuperspeedError::NullPointer);
+    }
+    let app_id = read_optional_c_str(app_id)?;
+    timing::set_profile(app_id.as_deref(), unsafe { *profile });
+    Ok(())
+}
+
+/// FFI: Copy the timing profile used for an application identifier into `out` (NULL = fallback)
+#[no_mangle]
+pub extern "C" fn superspeed_get_timing_profile(app_id: *const c_char, out: *mut TimingProfile) -> bool {
+    report(read_optional_c_str(app_id).and_then(|app_id| {
+        write_out(out, timing::profile_for(app_id.as_deref()))
+    }))
+    .is_some()
+}
+
+/// FFI: Measure the focused field's input latency and store a tuned profile
+/// `app_id` NULL calibrates the frontmost application. The caret must be in an
+/// editable field; a probe character is typed and removed again.
+/// On success the tuned profile is copied into `out` (may be NULL).
+#[no_mangle]
+pub extern "C" fn superspeed_calibrate_timing(app_id: *const c_char, out: *mut TimingProfile) -> bool {
+    report(read_optional_c_str(app_id).and_then(|app_id| {
+        keyboard::simulate::check_access()?;
+        let profile = timing::calibrate(app_id.as_deref())?;
+        if !out.is_null() {
+            write_out(out, profile)?;
+        }
+        Ok(())
+    }))
+    .is_some()
+}
+
+/// FFI: Set the insertion method for an application identifier (NULL = default for all apps)
+/// `method` is a SuperspeedInsertionMethod; AUTO removes the app's override.
+#[no_mangle]
+pub extern "C" fn superspeed_set_insertion_method(app_id: *const c_char, method: i32) -> bool {
+    report(InsertionMethod::from_i32(method).and_then(|method| {
+        let app_id = read_optional_c_str(app_id)?;
+        strategy::set_method(app_id.as_deref(), method);
+        Ok(())
+    }))
+    .is_some()
+}
+
+/// FFI: Resolve shortcut keys (Cmd/Ctrl+V, C, U) against a bundled keyboard layout
+/// `name` is "us", "us-dvorak", "us-colemak" or "fr"; NULL follows the system layout again.
+#[no_mangle]
+pub extern "C" fn superspeed_set_keyboard_layout(name: *const c_char) -> bool {
+    report(read_optional_c_str(name).and_then(|name| {
+        let layout = match name {
+            Some(name) => Some(KeyboardLayout::fixture(&name).ok_or_else(|| {
+                SuperspeedError::Unsupported(format!("Unknown keyboard layout \"{}\"", name))
+            })?),
+            None => None,
+        };
+        keyboard::layout::set_layout(layout);
+        Ok(())
+    }))
+    .is_some()
+}
+
+/// FFI: Load application profiles from a TOML file, searched before the built-in ones
+/// `path` NULL drops the host file and keeps only the built-in profiles. On error
+/// (unreadable file, bad TOML, invalid profile) the previous profiles stay in effect.
+#[no_mangle]
+pub extern "C" fn superspeed_load_app_profiles(path: *const c_char) -> bool {
+    report(read_optional_c_str(path).and_then(|path| profile::load_file(path.as_deref().map(Path::new)))).is_some()
+}
+
+/// FFI: Read the application profile file again after it was edited
+#[no_mangle]
+pub extern "C" fn superspeed_reload_app_profiles() -> bool {
+    report(profile::reload()).is_some()
+}
+
+/// FFI: Insertion method that got the current ghost text into the field (AUTO if none did)
+#[no_mangle]
+pub extern "C" fn superspeed_last_insertion_method() -> i32 {
+    SESSION.lock().unwrap().insertion_method().unwrap_or(InsertionMethod::Auto) as i32
+}
+
+/// FFI: What happened to the saved clipboard when the last ghost session ended
+/// Returns a SuperspeedClipboardRestore; SKIPPED_USER_CHANGE means the user copied
+/// something while the ghost text was pending and their copy was left in place.
+#[no_mangle]
+pub extern "C" fn superspeed_last_clipboard_restore() -> i32 {
+    SESSION.lock().unwrap().clipboard_restore() as i32
+}
+
+/// FFI: Read the field back after inserting and fall back to another method if nothing landed
+/// Enabled by default; each check selects and copies the end of the inserted text.
+#[no_mangle]
+pub extern "C" fn superspeed_set_insert_verification(enabled: bool) {
+    keyboard::verify::set_enabled(enabled);
+}
+
+/// FFI: Route crate logs to a host callback (pass NULL to log to stderr again)
+/// The callback may be invoked from any thread that calls into the crate
+#[no_mangle]
+pub extern "C" fn superspeed_set_log_callback(
+    callback: Option<extern "C" fn(level: i32, target: *const c_char, message: *const c_char)>,
+) {
+    logging::set_callback(callback);
+}
+
+/// FFI: Set the most verbose level that is emitted (1 = error ... 5 = trace)
+#[no_mangle]
+pub extern "C" fn superspeed_set_log_level(level: i32) {
+    logging::set_max_level(logging::Level::from_i32(level));
+}
+
+/// FFI: Debug opt-in to log user text verbatim instead of as length + hash
+#[no_mangle]
+pub extern "C" fn superspeed_set_log_user_text(enabled: bool) {
+    logging::set_log_user_text(enabled);
+}
+
+/// Helper: Record the outcome of an FFI call so the host can ask why it failed
+fn report<T>(result: Result<T, SuperspeedError>) -> Option<T> {
+    match result {
+        Ok(value) => {
+            error::set_last_error(None);
+            Some(value)
+        }
+        Err(e) => {
+            log_error!("{} (code {})", e, e.code() as i32);
+            error::set_last_error(Some(e));
+            None
         }
     }
 }
+
+/// Helper: Borrow a C string argument as UTF-8
+fn read_c_str(ptr: *const c_char) -> Result<String, SuperspeedError> {
+    if ptr.is_null() {
+        return Err(SuperspeedError::NullPointer);
+    }
+    let c_str = unsafe { CStr::from_ptr(ptr) };
+    c_str.to_str().map(str::to_owned).map_err(|_| SuperspeedError::InvalidUtf8)
+}
+
+/// Helper: Like read_c_str, but NULL means "not given"
+fn read_optional_c_str(ptr: *const c_char) -> Result<Option<String>, SuperspeedError> {
+    if ptr.is_null() {
+        Ok(None)
+    } else {
+        read_c_str(ptr).map(Some)
+    }
+}
+
+/// Helper: Write a value through a caller-provided out pointer
+fn write_out<T>(out: *mut T, value: T) -> Result<(), SuperspeedError> {
+    if out.is_null() {
+        return Err(SuperspeedError::NullPointer);
+    }
+    unsafe { out.write(value) };
+    Ok(())
+}
+
+/// Helper: Serialize a focused target for the host
+fn target_json(target: &focus::FocusedTarget) -> Result<CString, SuperspeedError> {
+    // Plain strings and numbers only, so serialization can't fail
+    into_c_string(serde_json::to_string(target).unwrap_or_default())
+}
+
+/// Helper: Convert a Rust string into an owned C string
+fn into_c_string(text: String) -> Result<CString, SuperspeedError> {
+    CString::new(text).map_err(|e| SuperspeedError::InteriorNul(e.nul_position()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keyboard::clipboard::MemoryClipboard;
+    use keyboard::synth::{Key, Modifiers, RecordingSynth, SynthEvent};
+
+    // Recorder and clipboard installed as backends, virtual time, no read-back
+    fn record(target: focus::FocusedTarget) -> (RecordingSynth, MemoryClipboard) {
+        sim::install_virtual_clock();
+        focus::set_override(Some(target));
+        keyboard::verify::set_enabled(false);
+        *SESSION.lock().unwrap() = GhostSession::new();
+
+        let synth = RecordingSynth::new();
+        let clipboard = MemoryClipboard::with_contents("user copy");
+        keyboard::synth::set_backend(Box::new(synth.clone()));
+        keyboard::clipboard::set_backend(Box::new(clipboard.clone()));
+        (synth, clipboard)
+    }
+
+    fn terminal() -> focus::FocusedTarget {
+        focus::FocusedTarget { wm_class: vec!["kitty".to_string()], ..focus::FocusedTarget::default() }
+    }
+
+    fn tap(key: Key, modifiers: Modifiers) -> [SynthEvent; 2] {
+        [SynthEvent::Down(key, modifiers), SynthEvent::Up(key, modifiers)]
+    }
+
+    fn shift_enter() -> [SynthEvent; 4] {
+        [
+            SynthEvent::Down(Key::Shift, Modifiers::NONE),
+            SynthEvent::Down(Key::Return, Modifiers::SHIFT),
+            SynthEvent::Up(Key::Return, Modifiers::SHIFT),
+            SynthEvent::Up(Key::Shift, Modifiers::NONE),
+        ]
+    }
+
+    const CTRL_SHIFT: Modifiers = Modifiers { shift: true, command: false, control: true };
+
+    #[test]
+    fn insert_types_the_separator_then_pastes() {
+        let _globals = sim::exclusive();
+        let (synth, clipboard) = record(focus::FocusedTarget::default());
+
+        insert_ghost_text("Hi there", InsertionMethod::Clipboard, ClipboardMarkers::PRIVATE).unwrap();
+        let expected: Vec<SynthEvent> =
+            [&shift_enter()[..], &shift_enter(), &tap(Key::V, Modifiers::SHORTCUT)].concat();
+        assert_eq!(synth.take(), expected);
+        assert_eq!(clipboard.contents().as_deref(), Some("Hi there"));
+    }
+
+    #[test]
+    fn reject_backspaces_over_suggestion_and_separator() {
+        let _globals = sim::exclusive();
+        let (synth, clipboard) = record(focus::FocusedTarget::default());
+
+        // "é" written as e + combining accent is one Backspace
+        insert_ghost_text("cafe\u{301} ok", InsertionMethod::Clipboard, ClipboardMarkers::PRIVATE).unwrap();
+        synth.take();
+        reject_ghost_text().unwrap();
+        let expected: Vec<SynthEvent> = (0..7 + 2).flat_map(|_| tap(Key::Backspace, Modifiers::NONE)).collect();
+        assert_eq!(synth.take(), expected);
+        assert_eq!(clipboard.contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn failed_reject_still_restores_the_clipboard() {
+        let _globals = sim::exclusive();
+        let (_synth, clipboard) = record(focus::FocusedTarget::default());
+
+        insert_ghost_text("half gone", InsertionMethod::Clipboard, ClipboardMarkers::PRIVATE).unwrap();
+        keyboard::synth::set_backend(Box::new(sim::Unplugged));
+        assert!(reject_ghost_text().is_err());
+        assert_eq!(SESSION.lock().unwrap().state(), GhostState::Failed);
+        assert_eq!(clipboard.contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn failed_terminal_accept_still_restores_the_clipboard() {
+        let _globals = sim::exclusive();
+        let (_synth, clipboard) = record(terminal());
+
+        insert_ghost_text("ls -la", InsertionMethod::Clipboard, ClipboardMarkers::PRIVATE).unwrap();
+        keyboard::synth::set_backend(Box::new(sim::Unplugged));
+        assert!(accept_ghost_text().is_err());
+        assert_eq!(clipboard.contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn terminal_accept_kills_the_line_and_pastes_again() {
+        let _globals = sim::exclusive();
+        let (synth, clipboard) = record(terminal());
+
+        insert_ghost_text("ls -la", InsertionMethod::Clipboard, ClipboardMarkers::PRIVATE).unwrap();
+        let expected: Vec<SynthEvent> =
+            [&tap(Key::Tab, Modifiers::NONE)[..], &tap(Key::Tab, Modifiers::NONE), &tap(Key::V, CTRL_SHIFT)].concat();
+        assert_eq!(synth.take(), expected);
+
+        accept_ghost_text().unwrap();
+        let expected: Vec<SynthEvent> = [&tap(Key::U, Modifiers::CONTROL)[..], &tap(Key::V, CTRL_SHIFT)].concat();
+        assert_eq!(synth.take(), expected);
+        assert_eq!(clipboard.contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn accept_elsewhere_posts_no_keys() {
+        let _globals = sim::exclusive();
+        let (synth, _clipboard) = record(focus::FocusedTarget::default());
+
+        insert_ghost_text("done", InsertionMethod::Clipboard, ClipboardMarkers::PRIVATE).unwrap();
+        synth.take();
+        accept_ghost_text().unwrap();
+        assert_eq!(synth.take(), Vec::new());
+    }
+
+    #[test]
+    fn failed_hotkeys_leave_the_error_for_the_host() {
+        let _globals = sim::exclusive();
+        let (synth, _clipboard) = record(focus::FocusedTarget::default());
+
+        // Nothing pending to reject
+        assert!(!run_hotkey(HotkeyAction::Reject));
+        assert!(error::last_error().is_some());
+        assert_eq!(synth.take(), Vec::new());
+
+        insert_ghost_text("done", InsertionMethod::Clipboard, ClipboardMarkers::PRIVATE).unwrap();
+        assert!(run_hotkey(HotkeyAction::Accept));
+        assert!(error::last_error().is_none());
+    }
+}
@@ -0,0 +1,133 @@
+// Leveled logging with a host-registrable sink
+// Logs go to stderr until the host installs a callback. User text is
+// redacted to its length and a hash unless raw text logging is turned on.
+
+use std::ffi::{c_char, CString};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Mutex;
+
+/// Log severity, also used as the integer level passed to the C callback
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    /// Level for a C integer; values past Trace clamp to Trace, below Error to Error
+    pub fn from_i32(level: i32) -> Level {
+        match level {
+            i32::MIN..=1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Host log sink: (level, target, message), both strings NUL-terminated and only valid during the call
+pub type LogCallback = extern "C" fn(level: i32, target: *const c_char, message: *const c_char);
+
+static CALLBACK: Mutex<Option<LogCallback>> = Mutex::new(None);
+static MAX_LEVEL: AtomicI32 = AtomicI32::new(if cfg!(debug_assertions) { Level::Debug as i32 } else { Level::Info as i32 });
+static LOG_USER_TEXT: AtomicBool = AtomicBool::new(false);
+
+/// Route logs to `callback`, or back to stderr with None
+pub fn set_callback(callback: Option<LogCallback>) {
+    *CALLBACK.lock().unwrap() = callback;
+}
+
+/// Drop messages more verbose than `level`
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as i32, Ordering::Relaxed);
+}
+
+/// Debug opt-in: log user text verbatim instead of redacting it
+pub fn set_log_user_text(enabled: bool) {
+    LOG_USER_TEXT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as i32 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Emit one record; use the log_* macros instead of calling this directly
+pub fn log(level: Level, target: &str, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+
+    let callback = *CALLBACK.lock().unwrap();
+    match callback {
+        Some(callback) => {
+            let target = CString::new(target).unwrap_or_default();
+            let message = CString::new(args.to_string().replace('\0', "\\0")).unwrap_or_default();
+            callback(level as i32, target.as_ptr(), message.as_ptr());
+        }
+        None => eprintln!("[{} {}] {}", level.as_str(), target, args),
+    }
+}
+
+/// Display wrapper that hides user text unless raw text logging is on
+pub struct Redacted<'a>(&'a str);
+
+/// Wrap user text (ghost text, clipboard, cursor context) before logging it
+pub fn redact(text: &str) -> Redacted<'_> {
+    Redacted(text)
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_USER_TEXT.load(Ordering::Relaxed) {
+            write!(f, "'{}'", self.0)
+        } else {
+            write!(f, "<{} chars, hash {:016x}>", self.0.chars().count(), fnv1a(self.0.as_bytes()))
+        }
+    }
+}
+
+// Stable across runs and builds, so the same text can be matched up between log lines
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Error, module_path!(), format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Warn, module_path!(), format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Info, module_path!(), format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Debug, module_path!(), format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Trace, module_path!(), format_args!($($arg)*)) };
+}
@@ -0,0 +1,317 @@
+// Typing pause detection
+// Generation starts when the user stops typing for a moment (3 seconds by
+// default). The detector is fed key presses - from the key listener or from the
+// host - and reports each pause once. Modifier-only presses and the crate's own
+// synthetic keys don't count as typing, and neither do the Tab/Esc presses that
+// accept or reject ghost text; nothing fires in a password field or for a short
+// while after ghost text was accepted.
+
+use crate::clock::{self, Clock};
+use crate::keyboard::listen::{Disposition, InputKey, KeyEvent};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Idle time after the last keystroke that counts as a pause
+pub const DEFAULT_THRESHOLD: Duration = Duration::from_secs(3);
+/// How long after an accept keystrokes are ignored (the user is usually moving on)
+pub const DEFAULT_ACCEPT_COOLDOWN: Duration = Duration::from_secs(3);
+
+/// Turns a stream of key presses into "the user paused after typing" reports
+pub struct PauseDetector {
+    // None = the crate-wide clock
+    clock: Option<Arc<dyn Clock>>,
+    threshold: Duration,
+    accept_cooldown: Duration,
+    // Last keystroke of a burst that hasn't been reported yet
+    last_keystroke: Option<Instant>,
+    // Keystrokes before this are ignored
+    suppressed_until: Option<Instant>,
+    secure_field: bool,
+}
+
+impl PauseDetector {
+    pub const fn new() -> Self {
+        PauseDetector {
+            clock: None,
+            threshold: DEFAULT_THRESHOLD,
+            accept_cooldown: DEFAULT_ACCEPT_COOLDOWN,
+            last_keystroke: None,
+            suppressed_until: None,
+            secure_field: false,
+        }
+    }
+
+    /// Detector that reads time from `clock` instead of the crate-wide clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        PauseDetector { clock: Some(clock), ..PauseDetector::new() }
+    }
+
+    fn now(&self) -> Instant {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => clock::now(),
+        }
+    }
+
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: Duration) {
+        self.threshold = threshold;
+    }
+
+    pub fn set_accept_cooldown(&mut self, cooldown: Duration) {
+        self.accept_cooldown = cooldown;
+    }
+
+    /// Count `event` as typing if the user pressed a non-modifier key
+    pub fn observe(&mut self, event: &KeyEvent) {
+        if event.synthetic || !event.down || event.key.is_modifier() || event.key == InputKey::Pointer {
+            return;
+        }
+        self.keystroke();
+    }
+
+    /// Record a keystroke now (for hosts that watch the keyboard themselves)
+    pub fn keystroke(&mut self) {
+        let now = self.now();
+        let suppressed = self.suppressed_until.is_some_and(|until| now < until);
+        // Secrets typed into a password field must never start a suggestion
+        self.last_keystroke = if self.secure_field || suppressed { None } else { Some(now) };
+    }
+
+    /// Report a pause once the user has been idle for the threshold after typing
+    /// Returns true at most once per burst of typing.
+    pub fn poll(&mut self) -> bool {
+        match self.time_until_pause() {
+            Some(Duration::ZERO) => {
+                self.last_keystroke = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Time left until the current burst becomes a pause (None if there is nothing to report)
+    pub fn time_until_pause(&self) -> Option<Duration> {
+        let last = self.last_keystroke?;
+        Some(self.threshold.saturating_sub(self.now().saturating_duration_since(last)))
+    }
+
+    /// Ghost text was accepted: drop the burst and ignore keystrokes for the cooldown
+    pub fn accepted(&mut self) {
+        self.last_keystroke = None;
+        self.suppressed_until = Some(self.now() + self.accept_cooldown);
+    }
+
+    /// Ghost text was rejected: the Esc that did it is not typing, so drop the burst
+    pub fn rejected(&mut self) {
+        self.last_keystroke = None;
+    }
+
+    /// Whether the focused field is a password field; while it is, nothing is reported
+    pub fn set_secure_field(&mut self, secure: bool) {
+        self.secure_field = secure;
+        if secure {
+            self.last_keystroke = None;
+        }
+    }
+}
+
+impl Default for PauseDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The detector fed by the key listener and queried over FFI
+static DETECTOR: Mutex<PauseDetector> = Mutex::new(PauseDetector::new());
+
+/// Run `f` on the crate-wide detector
+pub fn with_detector<T>(f: impl FnOnce(&mut PauseDetector) -> T) -> T {
+    f(&mut DETECTOR.lock().unwrap())
+}
+
+/// Key listener observer: swallowed keys ran a hotkey and never reached the field
+pub fn observe(event: &KeyEvent, disposition: Disposition) {
+    if disposition == Disposition::Pass {
+        with_detector(|detector| detector.observe(event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::keyboard::synth::Modifiers;
+
+    fn detector() -> (PauseDetector, VirtualClock) {
+        let clock = VirtualClock::new();
+        (PauseDetector::with_clock(Arc::new(clock.clone())), clock)
+    }
+
+    fn type_keys(detector: &mut PauseDetector, clock: &VirtualClock, keys: &str, gap: Duration) {
+        for c in keys.chars() {
+            detector.observe(&KeyEvent::press(InputKey::Char(c)));
+            detector.observe(&KeyEvent::release(InputKey::Char(c)));
+            clock.advance(gap);
+        }
+    }
+
+    const GAP: Duration = Duration::from_millis(150);
+
+    #[test]
+    fn pause_after_typing_is_reported_once() {
+        let (mut detector, clock) = detector();
+        type_keys(&mut detector, &clock, "hello", GAP);
+        assert!(!detector.poll());
+
+        clock.advance(DEFAULT_THRESHOLD - GAP - Duration::from_millis(1));
+        assert!(!detector.poll());
+        clock.advance(Duration::from_millis(1));
+        assert!(detector.poll());
+        assert!(!detector.poll());
+
+        clock.advance(Duration::from_secs(30));
+        assert!(!detector.poll());
+    }
+
+    #[test]
+    fn no_pause_without_typing() {
+        let (mut detector, clock) = detector();
+        clock.advance(Duration::from_secs(10));
+        assert!(!detector.poll());
+        assert_eq!(detector.time_until_pause(), None);
+    }
+
+    #[test]
+    fn each_keystroke_restarts_the_wait() {
+        let (mut detector, clock) = detector();
+        // Slow typing, 2.5 s between keys, never pauses for 3 s
+        type_keys(&mut detector, &clock, "abc", Duration::from_millis(2500));
+        detector.observe(&KeyEvent::press(InputKey::Char('d')));
+        assert!(!detector.poll());
+        assert_eq!(detector.time_until_pause(), Some(DEFAULT_THRESHOLD));
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn modifier_presses_and_releases_do_not_count() {
+        let (mut detector, clock) = detector();
+        type_keys(&mut detector, &clock, "hi", GAP);
+        clock.advance(Duration::from_secs(2));
+        // Reaching for a shortcut: Shift and Cmd alone, then their releases
+        detector.observe(&KeyEvent::press(InputKey::Shift));
+        detector.observe(&KeyEvent::press(InputKey::Command));
+        detector.observe(&KeyEvent::release(InputKey::Command));
+        detector.observe(&KeyEvent::release(InputKey::Shift));
+        clock.advance(DEFAULT_THRESHOLD - Duration::from_secs(2) - GAP);
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn shortcuts_with_a_real_key_count() {
+        let (mut detector, clock) = detector();
+        detector.observe(&KeyEvent { modifiers: Modifiers::SHORTCUT, ..KeyEvent::press(InputKey::Char('v')) });
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn synthetic_events_are_ignored() {
+        let (mut detector, clock) = detector();
+        // The crate's own separator, paste and backspaces
+        for key in [InputKey::Return, InputKey::Char('v'), InputKey::Backspace] {
+            detector.observe(&KeyEvent { synthetic: true, ..KeyEvent::press(key) });
+        }
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(!detector.poll());
+
+        // ... including while the user's own burst is waiting
+        type_keys(&mut detector, &clock, "x", Duration::ZERO);
+        clock.advance(Duration::from_secs(2));
+        detector.observe(&KeyEvent { synthetic: true, ..KeyEvent::press(InputKey::Tab) });
+        clock.advance(Duration::from_secs(1));
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn threshold_is_configurable() {
+        let (mut detector, clock) = detector();
+        detector.set_threshold(Duration::from_millis(800));
+        type_keys(&mut detector, &clock, "ok", Duration::ZERO);
+        clock.advance(Duration::from_millis(799));
+        assert!(!detector.poll());
+        clock.advance(Duration::from_millis(1));
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn password_fields_never_trigger() {
+        let (mut detector, clock) = detector();
+        type_keys(&mut detector, &clock, "user", GAP);
+        // Focus moves to the password field before the pause completes
+        detector.set_secure_field(true);
+        type_keys(&mut detector, &clock, "hunter2", GAP);
+        clock.advance(Duration::from_secs(10));
+        assert!(!detector.poll());
+
+        detector.set_secure_field(false);
+        clock.advance(Duration::from_secs(10));
+        assert!(!detector.poll());
+        type_keys(&mut detector, &clock, "hi", Duration::ZERO);
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn accept_suppresses_triggers_for_the_cooldown() {
+        let (mut detector, clock) = detector();
+        type_keys(&mut detector, &clock, "draft", GAP);
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+
+        // Tab accepts; the user keeps typing right away
+        detector.observe(&KeyEvent::press(InputKey::Tab));
+        detector.accepted();
+        type_keys(&mut detector, &clock, "ok", GAP);
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(!detector.poll());
+
+        // Typing after the cooldown starts a new burst as usual
+        type_keys(&mut detector, &clock, "more", GAP);
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn reject_does_not_count_as_typing() {
+        let (mut detector, clock) = detector();
+        type_keys(&mut detector, &clock, "draft", GAP);
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+
+        // Esc rejects; the user reads on without typing
+        detector.observe(&KeyEvent::press(InputKey::Escape));
+        detector.rejected();
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(!detector.poll());
+
+        // No cooldown: the next burst is reported as usual
+        type_keys(&mut detector, &clock, "more", GAP);
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+    }
+
+    #[test]
+    fn accept_cooldown_is_configurable() {
+        let (mut detector, clock) = detector();
+        detector.set_accept_cooldown(Duration::ZERO);
+        detector.accepted();
+        type_keys(&mut detector, &clock, "a", Duration::ZERO);
+        clock.advance(DEFAULT_THRESHOLD);
+        assert!(detector.poll());
+    }
+}
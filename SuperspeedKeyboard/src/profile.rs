@@ -0,0 +1,454 @@
+// Per-application profiles
+// How ghost text is laid out, pasted and timed in each app comes from data:
+// built-in profiles compiled in from profiles.toml, plus an optional host file
+// searched first. The host can reload the file while the app is running.
+
+use crate::error::SuperspeedError;
+use crate::focus::{self, FocusedTarget};
+use crate::keyboard::strategy::InsertionMethod;
+use crate::keyboard::synth::Chord;
+use crate::timing::TimingProfile;
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Profiles shipped with the crate
+const BUILTIN_PROFILES: &str = include_str!("profiles.toml");
+
+// Name of the profile used when nothing else matches
+const DEFAULT_NAME: &str = "default";
+
+// Separator keys typed before the suggestion when a profile doesn't say
+const DEFAULT_SEPARATOR_COUNT: usize = 2;
+
+/// Key typed between the user's text and the ghost text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Separator {
+    /// Line break that doesn't send (chat boxes, editors)
+    ShiftEnter,
+    /// Plain line break, only for fields where Enter doesn't submit
+    Enter,
+    /// Same-line delimiter (terminal prompts)
+    Tab,
+}
+
+/// What identifies the focused application
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppIdentity {
+    /// macOS bundle identifier
+    pub bundle_id: Option<String>,
+    /// X11 WM_CLASS (instance and class) or Wayland app-id
+    pub wm_class: Vec<String>,
+    /// Title of the focused window
+    pub title: Option<String>,
+}
+
+impl AppIdentity {
+    /// The frontmost application
+    pub fn current() -> Self {
+        AppIdentity::from_target(&focus::current())
+    }
+
+    /// Identity of a focused application and window
+    pub fn from_target(target: &FocusedTarget) -> Self {
+        AppIdentity {
+            // X11 reports WM_CLASS instead of a bundle ID
+            bundle_id: target.app_id.clone().filter(|_| target.wm_class.is_empty()),
+            wm_class: target.wm_class.clone(),
+            title: target.title.clone(),
+        }
+    }
+
+    /// Identity for an application identifier: a bundle ID on macOS, WM_CLASS or app-id on Linux
+    pub fn from_app_id(app_id: Option<&str>) -> Self {
+        AppIdentity {
+            bundle_id: app_id.map(str::to_owned),
+            wm_class: app_id.map(str::to_owned).into_iter().collect(),
+            title: None,
+        }
+    }
+
+    /// Identifier that per-app overrides (timing, insertion method) are keyed by
+    pub fn app_id(&self) -> Option<&str> {
+        self.bundle_id.as_deref().or(self.wm_class.last().map(String::as_str))
+    }
+}
+
+/// How ghost text behaves in one application (or family of applications)
+#[derive(Debug, Clone)]
+pub struct AppProfile {
+    pub name: String,
+    /// macOS bundle identifiers, matched exactly
+    pub bundle_ids: Vec<String>,
+    /// X11 WM_CLASS or Wayland app-ids, matched ignoring case
+    pub wm_classes: Vec<String>,
+    /// Searched in the focused window's title
+    pub title: Option<Regex>,
+    /// Key typed between the user's text and the suggestion
+    pub separator: Separator,
+    /// How many separator keys are typed
+    pub separator_count: usize,
+    /// Shortcut that pastes
+    pub paste: Chord,
+    /// Insertion method, unless the host set one for the app (None = host default)
+    pub insertion: Option<InsertionMethod>,
+    /// Timing, unless the host set or calibrated one for the app (None = host default)
+    pub timing: Option<TimingProfile>,
+    /// Plain Enter sends the message or runs the command
+    pub enter_submits: bool,
+    /// Shell prompt: tab delimiter, no read-back, accept replaces the line
+    pub terminal: bool,
+}
+
+impl AppProfile {
+    /// Profile used when no file provides a "default"
+    pub fn fallback() -> Self {
+        AppProfile {
+            name: DEFAULT_NAME.to_string(),
+            bundle_ids: Vec::new(),
+            wm_classes: Vec::new(),
+            title: None,
+            separator: Separator::ShiftEnter,
+            separator_count: DEFAULT_SEPARATOR_COUNT,
+            paste: Chord::PASTE,
+            insertion: None,
+            timing: None,
+            enter_submits: false,
+            terminal: false,
+        }
+    }
+
+    /// Whether any of this profile's matchers selects `identity`
+    pub fn matches(&self, identity: &AppIdentity) -> bool {
+        let bundle_id = identity.bundle_id.as_deref().is_some_and(|id| self.bundle_ids.iter().any(|b| b == id));
+        let wm_class = identity
+            .wm_class
+            .iter()
+            .any(|class| self.wm_classes.iter().any(|w| w.eq_ignore_ascii_case(class)));
+        let title = match (&self.title, &identity.title) {
+            (Some(pattern), Some(title)) => pattern.is_match(title),
+            _ => false,
+        };
+        bundle_id || wm_class || title
+    }
+
+    fn has_matchers(&self) -> bool {
+        !self.bundle_ids.is_empty() || !self.wm_classes.is_empty() || self.title.is_some()
+    }
+}
+
+// On-disk shape of a profile file: an array of [[profile]] tables
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    #[serde(default)]
+    profile: Vec<ProfileSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileSpec {
+    name: String,
+    #[serde(default)]
+    bundle_ids: Vec<String>,
+    #[serde(default)]
+    wm_classes: Vec<String>,
+    title: Option<String>,
+    separator: Option<Separator>,
+    separator_count: Option<usize>,
+    paste: Option<String>,
+    insertion: Option<String>,
+    timing: Option<TimingSpec>,
+    #[serde(default)]
+    enter_submits: bool,
+    #[serde(default)]
+    terminal: bool,
+}
+
+// A preset name or a table of delays (missing fields come from the default preset)
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TimingSpec {
+    Preset(String),
+    Custom(TimingProfile),
+}
+
+impl ProfileSpec {
+    fn into_profile(self) -> Result<AppProfile, String> {
+        let title = self
+            .title
+            .map(|pattern| Regex::new(&pattern).map_err(|e| format!("bad title pattern: {}", e)))
+            .transpose()?;
+        let paste = match self.paste {
+            Some(chord) => chord.parse()?,
+            None => Chord::PASTE,
+        };
+        let insertion = match self.insertion.as_deref() {
+            None | Some("auto") => None,
+            Some("clipboard") => Some(InsertionMethod::Clipboard),
+            Some("typing") => Some(InsertionMethod::Typing),
+            Some(other) => return Err(format!("unknown insertion method \"{}\"", other)),
+        };
+        let timing = match self.timing {
+            None => None,
+            Some(TimingSpec::Preset(name)) => {
+                Some(TimingProfile::preset(&name).ok_or_else(|| format!("unknown timing preset \"{}\"", name))?)
+            }
+            Some(TimingSpec::Custom(timing)) => Some(timing),
+        };
+        let separator = self.separator.unwrap_or(if self.terminal { Separator::Tab } else { Separator::ShiftEnter });
+
+        if separator == Separator::Enter && self.enter_submits {
+            return Err("separator \"enter\" would submit: Enter submits in this app".to_string());
+        }
+        if self.terminal && separator != Separator::Tab {
+            return Err("terminal profiles must use the \"tab\" separator: any line break runs the command".to_string());
+        }
+
+        let profile = AppProfile {
+            name: self.name,
+            bundle_ids: self.bundle_ids,
+            wm_classes: self.wm_classes,
+            title,
+            separator,
+            separator_count: self.separator_count.unwrap_or(DEFAULT_SEPARATOR_COUNT),
+            paste,
+            insertion,
+            timing,
+            enter_submits: self.enter_submits,
+            terminal: self.terminal,
+        };
+        if profile.name != DEFAULT_NAME && !profile.has_matchers() {
+            return Err("no bundle_ids, wm_classes or title to match".to_string());
+        }
+        Ok(profile)
+    }
+}
+
+/// Parse a profile file; `origin` names it in error messages
+pub fn parse(source: &str, origin: &str) -> Result<Vec<AppProfile>, SuperspeedError> {
+    let file: ProfileFile =
+        toml::from_str(source).map_err(|e| SuperspeedError::InvalidProfile(format!("{}: {}", origin, e)))?;
+    file.profile
+        .into_iter()
+        .map(|spec| {
+            let name = spec.name.clone();
+            spec.into_profile()
+                .map_err(|e| SuperspeedError::InvalidProfile(format!("{}: profile \"{}\": {}", origin, name, e)))
+        })
+        .collect()
+}
+
+// Profiles in search order: host file first, then built-ins
+#[derive(Debug)]
+struct Registry {
+    path: Option<PathBuf>,
+    host: Vec<AppProfile>,
+    builtin: Vec<AppProfile>,
+}
+
+impl Registry {
+    fn builtin() -> Self {
+        let builtin = parse(BUILTIN_PROFILES, "built-in profiles").expect("built-in profiles.toml is valid");
+        Registry { path: None, host: Vec::new(), builtin }
+    }
+
+    fn lookup(&self, identity: &AppIdentity) -> AppProfile {
+        let profiles = || self.host.iter().chain(&self.builtin);
+        profiles()
+            .find(|profile| profile.name != DEFAULT_NAME && profile.matches(identity))
+            .or_else(|| profiles().find(|profile| profile.name == DEFAULT_NAME))
+            .cloned()
+            .unwrap_or_else(AppProfile::fallback)
+    }
+}
+
+// Built lazily on first use
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+fn with_registry<T>(f: impl FnOnce(&mut Registry) -> T) -> T {
+    let mut registry = REGISTRY.lock().unwrap();
+    f(registry.get_or_insert_with(Registry::builtin))
+}
+
+fn read_file(path: &Path) -> Result<Vec<AppProfile>, SuperspeedError> {
+    let origin = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| SuperspeedError::InvalidProfile(format!("{}: {}", origin, e)))?;
+    parse(&source, &origin)
+}
+
+/// Load host profiles from the TOML file at `path` (None drops them, leaving the built-ins)
+/// An unreadable or invalid file leaves the current profiles in place.
+pub fn load_file(path: Option<&Path>) -> Result<(), SuperspeedError> {
+    let host = path.map(read_file).transpose()?.unwrap_or_default();
+    match path {
+        Some(path) => log_info!("Loaded {} app profiles from {}", host.len(), path.display()),
+        None => log_info!("Using built-in app profiles only"),
+    }
+    with_registry(|registry| {
+        registry.path = path.map(Path::to_path_buf);
+        registry.host = host;
+    });
+    Ok(())
+}
+
+/// Read the host profile file again, picking up edits made since it was loaded
+pub fn reload() -> Result<(), SuperspeedError> {
+    let path = with_registry(|registry| registry.path.clone());
+    load_file(path.as_deref())
+}
+
+/// Profile for `identity`: first matching host profile, then built-in, then "default"
+pub fn lookup(identity: &AppIdentity) -> AppProfile {
+    with_registry(|registry| registry.lookup(identity))
+}
+
+/// Profile for whichever application currently has focus
+pub fn current() -> AppProfile {
+    lookup(&AppIdentity::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::synth::{Key, Modifiers};
+
+    fn registry(host: &str) -> Registry {
+        Registry { path: None, host: parse(host, "test profiles").unwrap(), builtin: Registry::builtin().builtin }
+    }
+
+    fn wm_class(class: &str) -> AppIdentity {
+        AppIdentity { wm_class: vec![class.to_string()], ..AppIdentity::default() }
+    }
+
+    fn profile(source: &str) -> Result<AppProfile, SuperspeedError> {
+        parse(source, "test profile").map(|mut profiles| profiles.remove(0))
+    }
+
+    #[test]
+    fn builtin_profiles_parse() {
+        let builtin = parse(BUILTIN_PROFILES, "built-in profiles").unwrap();
+        assert!(builtin.iter().any(|profile| profile.name == DEFAULT_NAME));
+        assert!(builtin.iter().filter(|profile| profile.name != DEFAULT_NAME).all(AppProfile::has_matchers));
+    }
+
+    #[test]
+    fn unknown_apps_get_the_default_profile() {
+        let registry = registry("");
+        let app = registry.lookup(&AppIdentity::from_app_id(Some("org.example.Notes")));
+        assert_eq!(app.name, DEFAULT_NAME);
+        assert_eq!(app.separator, Separator::ShiftEnter);
+        assert_eq!(app.paste, Chord::PASTE);
+        assert!(!app.terminal);
+    }
+
+    #[test]
+    fn wm_classes_match_ignoring_case() {
+        let app = registry("").lookup(&wm_class("Kitty"));
+        assert_eq!(app.name, "Linux terminals");
+        assert!(app.terminal);
+        assert_eq!(app.separator, Separator::Tab);
+        assert_eq!(app.paste, "ctrl+shift+v".parse().unwrap());
+    }
+
+    #[test]
+    fn bundle_ids_match_exactly() {
+        let registry = registry("");
+        assert_eq!(registry.lookup(&AppIdentity::from_app_id(Some("com.apple.Terminal"))).name, "macOS terminals");
+        assert_eq!(registry.lookup(&AppIdentity::from_app_id(Some("com.apple.terminal"))).name, DEFAULT_NAME);
+    }
+
+    #[test]
+    fn app_ids_match_bundle_ids_and_wm_classes() {
+        let registry = registry("");
+        assert_eq!(registry.lookup(&AppIdentity::from_app_id(Some("com.tinyspeck.slackmacgap"))).name, "Chat apps");
+        assert_eq!(registry.lookup(&AppIdentity::from_app_id(Some("slack"))).name, "Chat apps");
+        assert_eq!(AppIdentity::from_app_id(Some("slack")).app_id(), Some("slack"));
+    }
+
+    #[test]
+    fn x11_targets_are_matched_by_wm_class_only() {
+        let target = FocusedTarget {
+            app_id: Some("kitty".to_string()),
+            wm_class: vec!["kitty".to_string(), "kitty".to_string()],
+            ..FocusedTarget::default()
+        };
+        let identity = AppIdentity::from_target(&target);
+        assert_eq!(identity.bundle_id, None);
+        assert_eq!(identity.app_id(), Some("kitty"));
+    }
+
+    #[test]
+    fn titles_are_searched_with_the_pattern() {
+        let registry = registry(
+            r#"
+            [[profile]]
+            name = "Web mail"
+            title = "(?i)inbox"
+            separator = "enter"
+            "#,
+        );
+        let identity = AppIdentity { title: Some("Inbox (3) - Mail".to_string()), ..AppIdentity::default() };
+        assert_eq!(registry.lookup(&identity).separator, Separator::Enter);
+    }
+
+    #[test]
+    fn host_profiles_are_searched_before_builtins() {
+        let registry = registry(
+            r#"
+            [[profile]]
+            name = "My kitty"
+            wm_classes = ["kitty"]
+            terminal = true
+            separator_count = 1
+            paste = "ctrl+v"
+
+            [[profile]]
+            name = "default"
+            separator_count = 1
+            "#,
+        );
+        let kitty = registry.lookup(&wm_class("kitty"));
+        assert_eq!(kitty.name, "My kitty");
+        assert_eq!(kitty.paste, Chord { key: Key::V, modifiers: Modifiers::CONTROL });
+        assert_eq!(registry.lookup(&wm_class("gedit")).separator_count, 1);
+        // Built-ins the host file doesn't mention still apply
+        assert_eq!(registry.lookup(&wm_class("foot")).name, "Linux terminals");
+    }
+
+    #[test]
+    fn timing_presets_are_looked_up_by_name() {
+        let app = profile("[[profile]]\nname = \"default\"\ntiming = \"electron\"").unwrap();
+        assert_eq!(app.timing, Some(TimingProfile::ELECTRON));
+        assert!(profile("[[profile]]\nname = \"default\"\ntiming = \"sluggish\"").is_err());
+    }
+
+    #[test]
+    fn partial_timing_tables_fall_back_to_the_default_delays() {
+        let app = profile("[[profile]]\nname = \"default\"\ntiming = { key_event_ms = 7, clipboard_read_ms = 90 }").unwrap();
+        let expected = TimingProfile { key_event_ms: 7, clipboard_read_ms: 90, ..TimingProfile::DEFAULT };
+        assert_eq!(app.timing, Some(expected));
+        assert!(profile("[[profile]]\nname = \"default\"\ntiming = { key_ms = 7 }").is_err());
+    }
+
+    #[test]
+    fn unsafe_or_unmatchable_profiles_are_rejected() {
+        let errors = [
+            // Nothing to match
+            "[[profile]]\nname = \"Editor\"",
+            // Enter would send the message
+            "[[profile]]\nname = \"Chat\"\nwm_classes = [\"chat\"]\nseparator = \"enter\"\nenter_submits = true",
+            // A line break at a shell prompt runs the command
+            "[[profile]]\nname = \"Shell\"\nwm_classes = [\"xterm\"]\nterminal = true\nseparator = \"shift-enter\"",
+            "[[profile]]\nname = \"default\"\npaste = \"hyper+v\"",
+            "[[profile]]\nname = \"default\"\ninsertion = \"telepathy\"",
+            "[[profile]]\nname = \"default\"\ncolour = \"blue\"",
+        ];
+        for source in errors {
+            assert!(matches!(profile(source), Err(SuperspeedError::InvalidProfile(_))), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// Ghost text session state machine
+// One session tracks a single suggestion from insertion until accept/reject,
+// so out-of-order FFI calls fail loudly instead of deleting the user's text.
+
+use crate::clock;
+use crate::error::SuperspeedError;
+use crate::focus::FocusedTarget;
+use crate::keyboard::clipboard::ClipboardMarkers;
+use crate::keyboard::paste::{ClipboardRestore, SavedClipboard};
+use crate::keyboard::strategy::InsertionMethod;
+use crate::profile::{AppProfile, Separator};
+use crate::text;
+use std::fmt;
+use std::time::Instant;
+
+/// Where a ghost session is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GhostState {
+    /// No ghost text has been inserted yet
+    Idle,
+    /// Layout keys and paste are in flight
+    Inserting,
+    /// Ghost text is on screen, waiting for accept or reject
+    Pending,
+    /// Ghost text was kept
+    Accepted,
+    /// Ghost text was deleted
+    Rejected,
+    /// Insertion or deletion did not complete
+    Failed,
+}
+
+impl fmt::Display for GhostState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GhostState::Idle => "idle",
+            GhostState::Inserting => "inserting",
+            GhostState::Pending => "pending",
+            GhostState::Accepted => "accepted",
+            GhostState::Rejected => "rejected",
+            GhostState::Failed => "failed",
+        };
+        f.write_str(name)
+    }
+}
+
+impl GhostState {
+    /// Whether the state machine allows moving from `self` to `next`
+    pub fn can_transition_to(self, next: GhostState) -> bool {
+        use GhostState::*;
+        matches!(
+            (self, next),
+            (Idle | Accepted | Rejected | Failed, Inserting)
+                | (Inserting, Pending | Failed)
+                | (Pending, Accepted | Rejected | Failed)
+        )
+    }
+}
+
+/// How a ghost suggestion is set apart from what the user typed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GhostLayout {
+    /// Key typed between the user's text and the suggestion
+    pub separator: Separator,
+    /// Characters typed between the user's text and the suggestion
+    pub separator_count: usize,
+    /// Terminal prompt: the suggestion sits on the same line, since any Enter runs the command
+    pub terminal: bool,
+}
+
+impl GhostLayout {
+    /// Two Shift+Enter line breaks below the user's text
+    pub const LINES: GhostLayout = GhostLayout { separator: Separator::ShiftEnter, separator_count: 2, terminal: false };
+
+    /// Layout the app's profile asks for
+    pub fn for_app(app: &AppProfile) -> Self {
+        GhostLayout { separator: app.separator, separator_count: app.separator_count, terminal: app.terminal }
+    }
+
+    /// What the separator leaves in the field
+    pub fn separator_text(&self) -> String {
+        let unit = match self.separator {
+            Separator::ShiftEnter | Separator::Enter => "\n",
+            Separator::Tab => "\t",
+        };
+        unit.repeat(self.separator_count)
+    }
+}
+
+/// A single ghost suggestion and everything needed to undo it
+#[derive(Debug)]
+pub struct GhostSession {
+    state: GhostState,
+    text: String,
+    layout: GhostLayout,
+    markers: ClipboardMarkers,
+    target: Option<FocusedTarget>,
+    saved_clipboard: Option<SavedClipboard>,
+    insertion_method: Option<InsertionMethod>,
+    clipboard_restore: ClipboardRestore,
+    started_at: Option<Instant>,
+    inserted_at: Option<Instant>,
+    finished_at: Option<Instant>,
+}
+
+impl GhostSession {
+    pub const fn new() -> Self {
+        GhostSession {
+            state: GhostState::Idle,
+            text: String::new(),
+            layout: GhostLayout::LINES,
+            markers: ClipboardMarkers::PRIVATE,
+            target: None,
+            saved_clipboard: None,
+            insertion_method: None,
+            clipboard_restore: ClipboardRestore::NotNeeded,
+            started_at: None,
+            inserted_at: None,
+            finished_at: None,
+        }
+    }
+
+    pub fn state(&self) -> GhostState {
+        self.state
+    }
+
+    /// Ghost text inserted by this session
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// How the ghost text is laid out relative to the user's text
+    pub fn layout(&self) -> GhostLayout {
+        self.layout
+    }
+
+    /// Number of separator characters typed before the ghost text
+    pub fn separator_count(&self) -> usize {
+        self.layout.separator_count
+    }
+
+    /// Clipboard markers the ghost text was inserted with
+    pub fn markers(&self) -> ClipboardMarkers {
+        self.markers
+    }
+
+    /// Application and window the ghost text was inserted into
+    pub fn target(&self) -> Option<&FocusedTarget> {
+        self.target.as_ref()
+    }
+
+    /// Clipboard contents saved before the ghost text was pasted
+    pub fn saved_clipboard(&self) -> Option<&SavedClipboard> {
+        self.saved_clipboard.as_ref()
+    }
+
+    /// Method that inserted the ghost text, once it has landed
+    pub fn insertion_method(&self) -> Option<InsertionMethod> {
+        self.insertion_method
+    }
+
+    /// What happened to the saved clipboard when the session ended
+    pub fn clipboard_restore(&self) -> ClipboardRestore {
+        self.clipboard_restore
+    }
+
+    /// Record whether the saved clipboard was put back or the user's newer copy kept
+    pub fn set_clipboard_restore(&mut self, outcome: ClipboardRestore) {
+        self.clipboard_restore = outcome;
+    }
+
+    /// Backspaces needed to remove the ghost text and its separators
+    /// One per user-perceived character of the text, plus one per separator.
+    pub fn delete_count(&self) -> usize {
+        text::grapheme_count(&self.text) + self.separator_count()
+    }
+
+    pub fn started_at(&self) -> Option<Instant> {
+        self.started_at
+    }
+
+    pub fn inserted_at(&self) -> Option<Instant> {
+        self.inserted_at
+    }
+
+    pub fn finished_at(&self) -> Option<Instant> {
+        self.finished_at
+    }
+
+    /// Start a new session for `text` in `target`; fails while another suggestion is still on screen
+    pub fn begin_insert(
+        &mut self,
+        text: &str,
+        layout: GhostLayout,
+        markers: ClipboardMarkers,
+        target: FocusedTarget,
+    ) -> Result<(), SuperspeedError> {
+        self.transition(GhostState::Inserting, "insert")?;
+        self.text = text.to_string();
+        self.layout = layout;
+        self.markers = markers;
+        self.target = Some(target);
+        self.saved_clipboard = None;
+        self.insertion_method = None;
+        self.clipboard_restore = ClipboardRestore::NotNeeded;
+        self.started_at = Some(clock::now());
+        self.inserted_at = None;
+        self.finished_at = None;
+        Ok(())
+    }
+
+    /// Ghost text landed; remember how, and the clipboard it displaced
+    pub fn finish_insert(&mut self, saved_clipboard: Option<SavedClipboard>, method: InsertionMethod) -> Result<(), SuperspeedError> {
+        self.transition(GhostState::Pending, "finish inserting")?;
+        self.saved_clipboard = saved_clipboard;
+        self.insertion_method = Some(method);
+        self.inserted_at = Some(clock::now());
+        Ok(())
+    }
+
+    /// Fail unless ghost text is currently pending
+    pub fn expect_pending(&self, action: &str) -> Result<(), SuperspeedError> {
+        if self.state == GhostState::Pending {
+            Ok(())
+        } else {
+            Err(SuperspeedError::InvalidState(format!(
+                "Cannot {} ghost text: no ghost text is pending (session is {})",
+                action, self.state
+            )))
+        }
+    }
+
+    /// Keep the ghost text; returns the clipboard to restore
+    pub fn accept(&mut self) -> Result<Option<SavedClipboard>, SuperspeedError> {
+        self.expect_pending("accept")?;
+        self.transition(GhostState::Accepted, "accept")?;
+        self.finished_at = Some(clock::now());
+        Ok(self.saved_clipboard.take())
+    }
+
+    /// Ghost text was deleted; returns the clipboard to restore
+    pub fn reject(&mut self) -> Result<Option<SavedClipboard>, SuperspeedError> {
+        self.expect_pending("reject")?;
+        self.transition(GhostState::Rejected, "reject")?;
+        self.finished_at = Some(clock::now());
+        Ok(self.saved_clipboard.take())
+    }
+
+    /// Insertion or deletion did not complete; returns the clipboard to restore
+    pub fn fail(&mut self) -> Option<SavedClipboard> {
+        if self.state.can_transition_to(GhostState::Failed) {
+            self.state = GhostState::Failed;
+            self.finished_at = Some(clock::now());
+        }
+        self.saved_clipboard.take()
+    }
+
+    fn transition(&mut self, next: GhostState, action: &str) -> Result<(), SuperspeedError> {
+        if !self.state.can_transition_to(next) {
+            return Err(SuperspeedError::InvalidState(format!(
+                "Cannot {} ghost text while session is {}",
+                action, self.state
+            )));
+        }
+        self.state = next;
+        Ok(())
+    }
+}
+
+impl Default for GhostSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
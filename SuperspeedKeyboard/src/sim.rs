@@ -0,0 +1,524 @@
+// Headless model of an editable text field
+// VirtualField consumes the logical key events from the synth layer and keeps a
+// buffer, caret, selection, undo history and its own clipboard, so the whole
+// insert -> accept/reject flow can run end-to-end without a window server.
+
+use crate::clock::{self, VirtualClock};
+use crate::error::SuperspeedError;
+use crate::keyboard::clipboard::{self, Clipboard, MemoryClipboard};
+use crate::keyboard::synth::{self, Key, KeySynth, Modifiers};
+use crate::text;
+use std::sync::{Arc, Mutex};
+
+/// How a field reacts to Enter, Tab and selection keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldBehavior {
+    /// Plain Enter submits the contents instead of inserting a newline
+    pub enter_submits: bool,
+    /// Shift+Enter submits too (terminals send the same carriage return for both)
+    pub shift_enter_submits: bool,
+    /// Character inserted by Shift+Enter when it does not submit
+    pub soft_break: char,
+    /// Shift+Arrow extends a selection (terminals just move the cursor)
+    pub supports_selection: bool,
+    /// Tab inserts a tab character instead of moving focus
+    pub tab_inserts: bool,
+    /// Paste shortcuts are ignored (banking sites, some remote desktops)
+    pub blocks_paste: bool,
+    /// Ctrl+U deletes back to the start of the line (readline line editing)
+    pub kills_line: bool,
+}
+
+impl FieldBehavior {
+    /// Multi-line plain text view: Enter and Shift+Enter both insert a newline
+    pub const PLAIN_TEXT: FieldBehavior = FieldBehavior {
+        enter_submits: false,
+        shift_enter_submits: false,
+        soft_break: '\n',
+        supports_selection: true,
+        tab_inserts: true,
+        blocks_paste: false,
+        kills_line: false,
+    };
+
+    /// Chat composer (Slack, iMessage): Enter sends, Shift+Enter makes a newline
+    pub const CHAT_BOX: FieldBehavior = FieldBehavior {
+        enter_submits: true,
+        shift_enter_submits: false,
+        soft_break: '\n',
+        supports_selection: true,
+        tab_inserts: false,
+        blocks_paste: false,
+        kills_line: false,
+    };
+
+    /// Shell line editor: any Enter runs the line, no selection
+    pub const TERMINAL_LINE: FieldBehavior = FieldBehavior {
+        enter_submits: true,
+        shift_enter_submits: true,
+        soft_break: '\n',
+        supports_selection: false,
+        tab_inserts: true,
+        blocks_paste: false,
+        kills_line: true,
+    };
+
+    /// Rich text editor (Notion, Docs): Shift+Enter is a soft line break
+    pub const RICH_EDITOR: FieldBehavior = FieldBehavior {
+        enter_submits: false,
+        shift_enter_submits: false,
+        soft_break: '\u{2028}',
+        supports_selection: true,
+        tab_inserts: true,
+        blocks_paste: false,
+        kills_line: false,
+    };
+}
+
+#[derive(Debug)]
+struct FieldState {
+    behavior: FieldBehavior,
+    text: Vec<char>,
+    caret: usize,
+    // Selection anchor; the selection spans anchor..caret in either direction
+    anchor: Option<usize>,
+    undo: Vec<(Vec<char>, usize)>,
+    submitted: Vec<String>,
+    held_shift: bool,
+    held_shortcut: bool,
+}
+
+impl FieldState {
+    fn selection(&self) -> Option<(usize, usize)> {
+        self.anchor
+            .filter(|&anchor| anchor != self.caret)
+            .map(|anchor| (anchor.min(self.caret), anchor.max(self.caret)))
+    }
+
+    fn snapshot(&mut self) {
+        self.undo.push((self.text.clone(), self.caret));
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        match self.selection() {
+            Some((start, end)) => {
+                self.text.drain(start..end);
+                self.caret = start;
+                self.anchor = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.snapshot();
+        self.delete_selection();
+        for c in text.chars() {
+            self.text.insert(self.caret, c);
+            self.caret += 1;
+        }
+    }
+
+    // Chars in the user-perceived character before / after the caret
+    fn grapheme_before(&self) -> usize {
+        text::last_grapheme_chars(&self.text[..self.caret].iter().collect::<String>())
+    }
+
+    fn grapheme_after(&self) -> usize {
+        text::first_grapheme_chars(&self.text[self.caret..].iter().collect::<String>())
+    }
+
+    fn backspace(&mut self) {
+        self.snapshot();
+        if !self.delete_selection() && self.caret > 0 {
+            // Like real editors, one Backspace removes a whole grapheme cluster
+            let len = self.grapheme_before();
+            self.text.drain(self.caret - len..self.caret);
+            self.caret -= len;
+        }
+    }
+
+    fn move_caret(&mut self, left: bool, extend: bool) {
+        if extend && self.behavior.supports_selection {
+            self.anchor.get_or_insert(self.caret);
+        } else if let Some((start, end)) = self.selection() {
+            // Arrow without Shift collapses the selection to its edge
+            self.caret = if left { start } else { end };
+            self.anchor = None;
+            return;
+        } else {
+            self.anchor = None;
+        }
+
+        if left {
+            self.caret -= self.grapheme_before();
+        } else {
+            self.caret += self.grapheme_after();
+        }
+    }
+
+    fn kill_line(&mut self) {
+        self.snapshot();
+        let start = self.text[..self.caret].iter().rposition(|&c| c == '\n').map_or(0, |newline| newline + 1);
+        self.text.drain(start..self.caret);
+        self.caret = start;
+        self.anchor = None;
+    }
+
+    fn submit(&mut self) {
+        self.submitted.push(self.text.iter().collect());
+        self.text.clear();
+        self.caret = 0;
+        self.anchor = None;
+        self.undo.clear();
+    }
+
+    fn press(&mut self, key: Key, modifiers: Modifiers, clipboard: &mut MemoryClipboard) -> Result<(), SuperspeedError> {
+        let shift = modifiers.shift || self.held_shift;
+        let shortcut = modifiers.command || modifiers.control || self.held_shortcut;
+
+        match key {
+            Key::Shift => self.held_shift = true,
+            Key::Command | Key::Control => self.held_shortcut = true,
+            Key::Return if shift => {
+                if self.behavior.shift_enter_submits {
+                    self.submit();
+                } else {
+                    let soft_break = self.behavior.soft_break.to_string();
+                    self.insert(&soft_break);
+                }
+            }
+            Key::Return => {
+                if self.behavior.enter_submits {
+                    self.submit();
+                } else {
+                    self.insert("\n");
+                }
+            }
+            Key::Tab => {
+                if self.behavior.tab_inserts {
+                    self.insert("\t");
+                }
+            }
+            Key::Backspace => self.backspace(),
+            Key::LeftArrow => self.move_caret(true, shift),
+            Key::RightArrow => self.move_caret(false, shift),
+            Key::V if shortcut => {
+                if self.behavior.blocks_paste {
+                    // The shortcut is swallowed; nothing changes
+                } else if let Some(pasted) = clipboard.read_string()? {
+                    self.insert(&text::normalize_newlines(&pasted));
+                }
+            }
+            Key::C if shortcut => {
+                // Copying with nothing selected leaves the clipboard alone
+                if let Some((start, end)) = self.selection() {
+                    let selected: String = self.text[start..end].iter().collect();
+                    clipboard.clear()?;
+                    clipboard.write_string(&selected)?;
+                }
+            }
+            Key::U if shortcut => {
+                if self.behavior.kills_line {
+                    self.kill_line();
+                }
+            }
+            Key::V => self.insert(if shift { "V" } else { "v" }),
+            Key::C => self.insert(if shift { "C" } else { "c" }),
+            Key::U => self.insert(if shift { "U" } else { "u" }),
+        }
+        Ok(())
+    }
+
+    fn release(&mut self, key: Key) {
+        match key {
+            Key::Shift => self.held_shift = false,
+            Key::Command | Key::Control => self.held_shortcut = false,
+            _ => {}
+        }
+    }
+}
+
+/// A simulated text field with its own clipboard
+/// Clones share the same field, so a test can keep one handle and install another
+#[derive(Debug, Clone)]
+pub struct VirtualField {
+    state: Arc<Mutex<FieldState>>,
+    clipboard: MemoryClipboard,
+}
+
+impl VirtualField {
+    pub fn new(behavior: FieldBehavior) -> Self {
+        VirtualField {
+            state: Arc::new(Mutex::new(FieldState {
+                behavior,
+                text: Vec::new(),
+                caret: 0,
+                anchor: None,
+                undo: Vec::new(),
+                submitted: Vec::new(),
+                held_shift: false,
+                held_shortcut: false,
+            })),
+            clipboard: MemoryClipboard::new(),
+        }
+    }
+
+    /// Field pre-filled with `text`, caret at the end
+    pub fn with_text(behavior: FieldBehavior, text: &str) -> Self {
+        let field = VirtualField::new(behavior);
+        {
+            let mut state = field.state.lock().unwrap();
+            state.text = text.chars().collect();
+            state.caret = state.text.len();
+        }
+        field
+    }
+
+    /// Make this field and its clipboard the active synth and clipboard backends
+    pub fn install(&self) {
+        synth::set_backend(Box::new(self.clone()));
+        clipboard::set_backend(Box::new(self.clipboard.clone()));
+    }
+
+    /// Clipboard the field pastes from and copies to
+    pub fn clipboard(&self) -> MemoryClipboard {
+        self.clipboard.clone()
+    }
+
+    /// Current contents
+    pub fn text(&self) -> String {
+        self.state.lock().unwrap().text.iter().collect()
+    }
+
+    /// Contents before the caret
+    pub fn text_before_caret(&self) -> String {
+        let state = self.state.lock().unwrap();
+        state.text[..state.caret].iter().collect()
+    }
+
+    /// Caret position in characters
+    pub fn caret(&self) -> usize {
+        self.state.lock().unwrap().caret
+    }
+
+    /// Selected text, if any
+    pub fn selection(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state.selection().map(|(start, end)| state.text[start..end].iter().collect())
+    }
+
+    /// Everything submitted with Enter (sent messages, executed commands)
+    pub fn submitted(&self) -> Vec<String> {
+        self.state.lock().unwrap().submitted.clone()
+    }
+
+    /// Undo the last edit; returns false when there is nothing to undo
+    pub fn undo(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.undo.pop() {
+            Some((text, caret)) => {
+                state.text = text;
+                state.caret = caret;
+                state.anchor = None;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl KeySynth for VirtualField {
+    fn name(&self) -> &'static str {
+        "virtual-field"
+    }
+
+    fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        let mut clipboard = self.clipboard.clone();
+        self.state.lock().unwrap().press(key, modifiers, &mut clipboard)
+    }
+
+    fn key_up(&mut self, key: Key, _modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        self.state.lock().unwrap().release(key);
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), SuperspeedError> {
+        self.state.lock().unwrap().insert(text);
+        Ok(())
+    }
+}
+
+/// Install a virtual clock so built-in delays return immediately
+/// Returns a handle for inspecting or advancing virtual time.
+pub fn install_virtual_clock() -> VirtualClock {
+    let virtual_clock = VirtualClock::new();
+    clock::set_clock(Some(Arc::new(virtual_clock.clone())));
+    virtual_clock
+}
+
+/// Serialize tests that install backends, clocks or focus overrides (all process-wide)
+#[cfg(test)]
+pub(crate) fn exclusive() -> std::sync::MutexGuard<'static, ()> {
+    static GLOBALS: Mutex<()> = Mutex::new(());
+    // A failed test must not fail every test after it
+    GLOBALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Key backend whose every event fails, like one that lost its connection mid-action
+#[cfg(test)]
+pub(crate) struct Unplugged;
+
+#[cfg(test)]
+impl KeySynth for Unplugged {
+    fn name(&self) -> &'static str {
+        "unplugged"
+    }
+
+    fn key_down(&mut self, _key: Key, _modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        Err(SuperspeedError::EventPost("unplugged".to_string()))
+    }
+
+    fn key_up(&mut self, _key: Key, _modifiers: Modifiers) -> Result<(), SuperspeedError> {
+        Err(SuperspeedError::EventPost("unplugged".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::focus::{self, FocusedTarget};
+    use crate::keyboard::clipboard::ClipboardMarkers;
+    use crate::keyboard::strategy::InsertionMethod;
+    use crate::keyboard::{text_reader, verify};
+    use crate::session::GhostSession;
+    use crate::timing::{self, TimingProfile};
+    use std::time::Duration;
+
+    // A field with `text` and "user copy" on its clipboard, focused in `app`, on virtual time
+    fn focus_field(behavior: FieldBehavior, text: &str, app: FocusedTarget) -> (VirtualField, VirtualClock) {
+        let virtual_clock = install_virtual_clock();
+        focus::set_override(Some(app));
+        verify::set_enabled(true);
+        *crate::SESSION.lock().unwrap() = GhostSession::new();
+
+        let field = VirtualField::with_text(behavior, text);
+        field.clipboard().write_string("user copy").unwrap();
+        field.install();
+        (field, virtual_clock)
+    }
+
+    fn editor() -> FocusedTarget {
+        FocusedTarget::default()
+    }
+
+    fn terminal() -> FocusedTarget {
+        FocusedTarget { wm_class: vec!["kitty".to_string()], ..FocusedTarget::default() }
+    }
+
+    fn insert(text: &str) {
+        crate::insert_ghost_text(text, InsertionMethod::Auto, ClipboardMarkers::PRIVATE).unwrap();
+    }
+
+    #[test]
+    fn chat_box_accept_keeps_the_suggestion_below() {
+        let _globals = exclusive();
+        let (field, virtual_clock) = focus_field(FieldBehavior::CHAT_BOX, "draft", editor());
+
+        insert("Sounds good");
+        assert_eq!(field.text(), "draft\n\nSounds good");
+        // Every delay ran, on virtual time
+        assert!(virtual_clock.elapsed() >= Duration::from_millis(TimingProfile::DEFAULT.layout_settle_ms as u64));
+
+        crate::accept_ghost_text().unwrap();
+        assert_eq!(field.text(), "draft\n\nSounds good");
+        assert_eq!(field.clipboard().contents().as_deref(), Some("user copy"));
+        assert!(field.submitted().is_empty());
+    }
+
+    #[test]
+    fn chat_box_reject_restores_the_draft() {
+        let _globals = exclusive();
+        let (field, _) = focus_field(FieldBehavior::CHAT_BOX, "draft", editor());
+
+        insert("Sounds good");
+        crate::reject_ghost_text().unwrap();
+        assert_eq!(field.text(), "draft");
+        assert_eq!(field.caret(), 5);
+        assert_eq!(field.clipboard().contents().as_deref(), Some("user copy"));
+        assert!(field.submitted().is_empty());
+    }
+
+    #[test]
+    fn terminal_accept_replaces_the_request_with_the_command() {
+        let _globals = exclusive();
+        let (field, _) = focus_field(FieldBehavior::TERMINAL_LINE, "list files", terminal());
+
+        insert("ls -la");
+        assert_eq!(field.text(), "list files\t\tls -la");
+
+        crate::accept_ghost_text().unwrap();
+        assert_eq!(field.text(), "ls -la");
+        // Nothing ran: the user presses Enter themselves
+        assert!(field.submitted().is_empty());
+        assert_eq!(field.clipboard().contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn terminal_reject_restores_the_request() {
+        let _globals = exclusive();
+        let (field, _) = focus_field(FieldBehavior::TERMINAL_LINE, "list files", terminal());
+
+        insert("ls -la");
+        crate::reject_ghost_text().unwrap();
+        assert_eq!(field.text(), "list files");
+        assert!(field.submitted().is_empty());
+    }
+
+    #[test]
+    fn rich_editor_accept_keeps_soft_breaks() {
+        let _globals = exclusive();
+        let (field, _) = focus_field(FieldBehavior::RICH_EDITOR, "Notes", editor());
+
+        insert("First point\nSecond point");
+        assert_eq!(field.text(), "Notes\u{2028}\u{2028}First point\nSecond point");
+        crate::accept_ghost_text().unwrap();
+        assert_eq!(field.text(), "Notes\u{2028}\u{2028}First point\nSecond point");
+        assert_eq!(field.clipboard().contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn rich_editor_reject_removes_soft_breaks_too() {
+        let _globals = exclusive();
+        let (field, _) = focus_field(FieldBehavior::RICH_EDITOR, "Notes", editor());
+
+        insert("First point\nSecond point");
+        crate::reject_ghost_text().unwrap();
+        assert_eq!(field.text(), "Notes");
+        assert_eq!(field.clipboard().contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn cursor_context_is_read_with_select_and_copy() {
+        let _globals = exclusive();
+        let (field, _) = focus_field(FieldBehavior::PLAIN_TEXT, "Hello, world", editor());
+
+        let context = text_reader::read_cursor_context(5, ClipboardMarkers::PRIVATE, &timing::current()).unwrap();
+        assert_eq!(context, "world");
+        // Caret back where it was, nothing left selected, clipboard untouched
+        assert_eq!(field.caret(), 12);
+        assert_eq!(field.selection(), None);
+        assert_eq!(field.clipboard().contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn cursor_context_shorter_than_asked_for() {
+        let _globals = exclusive();
+        let (field, _) = focus_field(FieldBehavior::CHAT_BOX, "Hi", editor());
+
+        let context = text_reader::read_cursor_context(10, ClipboardMarkers::PRIVATE, &timing::current()).unwrap();
+        assert_eq!(context, "Hi");
+        assert_eq!(field.text(), "Hi");
+    }
+}
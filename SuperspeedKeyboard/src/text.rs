@@ -0,0 +1,119 @@
+// Counting text the way the user sees it
+// Backspace removes one user-perceived character, not one UTF-8 byte or code
+// point: a Devanagari conjunct, a ZWJ emoji sequence or a letter with combining
+// accents each go in a single press. Counts here use extended grapheme clusters.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Convert CRLF and lone CR line endings to LF, as text fields do on paste
+pub fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Number of user-perceived characters (extended grapheme clusters) after newline normalization
+pub fn grapheme_count(text: &str) -> usize {
+    normalize_newlines(text).graphemes(true).count()
+}
+
+/// Length in chars of the last user-perceived character of `text` (0 if empty)
+pub fn last_grapheme_chars(text: &str) -> usize {
+    text.graphemes(true).next_back().map_or(0, |g| g.chars().count())
+}
+
+/// Length in chars of the first user-perceived character of `text` (0 if empty)
+pub fn first_grapheme_chars(text: &str) -> usize {
+    text.graphemes(true).next().map_or(0, |g| g.chars().count())
+}
+
+/// Split `text` at grapheme boundaries into pieces of at most `max` units as measured by `units`
+/// A single grapheme larger than `max` becomes a piece of its own rather than being broken up.
+pub fn chunks(text: &str, max: usize, units: impl Fn(&str) -> usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (offset, grapheme) in text.grapheme_indices(true) {
+        let len = units(grapheme);
+        if size > 0 && size + len > max {
+            pieces.push(&text[start..offset]);
+            start = offset;
+            size = 0;
+        }
+        size += len;
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_counts_bytes() {
+        assert_eq!(grapheme_count("hello world"), 11);
+        assert_eq!(grapheme_count(""), 0);
+    }
+
+    #[test]
+    fn devanagari_conjuncts_are_single_characters() {
+        // क्ष = क + ् + ष, त्र = त + ् + र
+        assert_eq!(grapheme_count("क्ष"), 1);
+        assert_eq!(grapheme_count("त्र"), 1);
+        // न + म + स् + ते: the trailing conjunct plus vowel sign is one cluster
+        assert_eq!(grapheme_count("नमस्ते"), 3);
+        assert_eq!(grapheme_count("हिंदी"), 2);
+        assert!("नमस्ते".len() > 3 * 3);
+    }
+
+    #[test]
+    fn hinglish_mixes_scripts() {
+        // "kal " + मि ल ते + " " + हैं
+        assert_eq!(grapheme_count("kal मिलते हैं"), 4 + 3 + 1 + 1);
+    }
+
+    #[test]
+    fn zwj_emoji_sequences_are_single_characters() {
+        // Family: man + ZWJ + woman + ZWJ + girl + ZWJ + boy
+        assert_eq!(grapheme_count("👨\u{200d}👩\u{200d}👧\u{200d}👦"), 1);
+        // Rainbow flag: white flag + VS16 + ZWJ + rainbow
+        assert_eq!(grapheme_count("🏳\u{fe0f}\u{200d}🌈"), 1);
+        // Skin tone modifier and regional indicator pair
+        assert_eq!(grapheme_count("👍🏽🇮🇳"), 2);
+    }
+
+    #[test]
+    fn combining_marks_stay_with_their_base() {
+        // e + combining acute, a + combining ring + combining acute
+        assert_eq!(grapheme_count("e\u{301}"), 1);
+        assert_eq!(grapheme_count("a\u{30a}\u{301}b"), 2);
+        // Precomposed and decomposed forms count the same
+        assert_eq!(grapheme_count("café"), grapheme_count("cafe\u{301}"));
+    }
+
+    #[test]
+    fn line_endings_are_normalized() {
+        assert_eq!(normalize_newlines("a\r\nb\rc\n"), "a\nb\nc\n");
+        assert_eq!(grapheme_count("a\r\nb"), 3);
+        assert_eq!(grapheme_count("a\rb"), 3);
+        assert_eq!(grapheme_count("\r\n\r\n"), 2);
+    }
+
+    #[test]
+    fn chunks_never_split_a_grapheme() {
+        let count = |g: &str| g.chars().count();
+        assert_eq!(chunks("abcde", 2, count), ["ab", "cd", "e"]);
+        // क्ष is three chars; it moves to the next chunk instead of being cut
+        assert_eq!(chunks("abक्ष", 4, count), ["ab", "क्ष"]);
+        assert_eq!(chunks("क्षa", 2, count), ["क्ष", "a"]);
+        assert!(chunks("", 4, count).is_empty());
+    }
+
+    #[test]
+    fn edge_graphemes() {
+        assert_eq!(last_grapheme_chars("ab"), 1);
+        assert_eq!(last_grapheme_chars("aक्ष"), 3);
+        assert_eq!(first_grapheme_chars("e\u{301}x"), 2);
+        assert_eq!(last_grapheme_chars(""), 0);
+    }
+}
@@ -0,0 +1,286 @@
+// Timing profiles for synthetic input
+// Every delay between posted events comes from a TimingProfile. Profiles are
+// keyed by application identifier (bundle ID on macOS) and can be tuned by
+// calibrating against a live text field.
+
+use crate::clock;
+use crate::focus;
+use crate::error::SuperspeedError;
+use crate::keyboard::clipboard::ClipboardMarkers;
+use crate::keyboard::synth::Chord;
+use crate::keyboard::{clipboard, paste, simulate, text_reader};
+use crate::profile::{self, AppIdentity, AppProfile};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Delays used while synthesizing input, in milliseconds
+/// Layout is shared with C.
+/// In profile files, fields left out of a timing table keep their DEFAULT values.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimingProfile {
+    /// After each key down/up (Shift+Enter, Backspace, Tab, Cmd+V)
+    pub key_event_ms: u32,
+    /// After the Shift+Enter layout, before pasting
+    pub layout_settle_ms: u32,
+    /// After each Shift+Left / Right arrow key down
+    pub arrow_down_ms: u32,
+    /// After each Shift+Left / Right arrow key up
+    pub arrow_up_ms: u32,
+    /// After the selection is complete, before copying
+    pub selection_settle_ms: u32,
+    /// Between Cmd+C key down and key up
+    pub copy_hold_ms: u32,
+    /// After Cmd+C, before reading the clipboard
+    pub clipboard_read_ms: u32,
+    /// Between clipboard verification polls
+    pub clipboard_poll_ms: u32,
+    /// Clipboard verification polls before giving up
+    pub clipboard_poll_attempts: u32,
+}
+
+/// cbindgen:ignore
+impl TimingProfile {
+    /// Delays the crate has always used; safe for most apps
+    pub const DEFAULT: TimingProfile = TimingProfile {
+        key_event_ms: 20,
+        layout_settle_ms: 100,
+        arrow_down_ms: 2,
+        arrow_up_ms: 1,
+        selection_settle_ms: 10,
+        copy_hold_ms: 10,
+        clipboard_read_ms: 50,
+        clipboard_poll_ms: 2,
+        clipboard_poll_attempts: 50,
+    };
+
+    /// Native AppKit fields that apply input almost immediately
+    pub const NATIVE: TimingProfile = TimingProfile {
+        key_event_ms: 5,
+        layout_settle_ms: 20,
+        arrow_down_ms: 1,
+        arrow_up_ms: 0,
+        selection_settle_ms: 5,
+        copy_hold_ms: 5,
+        clipboard_read_ms: 15,
+        clipboard_poll_ms: 2,
+        clipboard_poll_attempts: 50,
+    };
+
+    /// Electron/Chromium apps that need longer to process synthetic input
+    pub const ELECTRON: TimingProfile = TimingProfile {
+        key_event_ms: 35,
+        layout_settle_ms: 200,
+        arrow_down_ms: 4,
+        arrow_up_ms: 2,
+        selection_settle_ms: 30,
+        copy_hold_ms: 20,
+        clipboard_read_ms: 120,
+        clipboard_poll_ms: 4,
+        clipboard_poll_attempts: 75,
+    };
+
+    /// Built-in profile by name ("default", "native" or "electron")
+    pub fn preset(name: &str) -> Option<TimingProfile> {
+        match name {
+            "default" => Some(TimingProfile::DEFAULT),
+            "native" => Some(TimingProfile::NATIVE),
+            "electron" => Some(TimingProfile::ELECTRON),
+            _ => None,
+        }
+    }
+
+    /// Profile tuned for a field that shows synthetic input after `latency`
+    pub fn from_latency(latency: Duration) -> TimingProfile {
+        let ms = latency.as_millis().min(u32::MAX as u128) as u32;
+        TimingProfile {
+            key_event_ms: (ms / 4).clamp(2, 60),
+            layout_settle_ms: (ms * 2).clamp(20, 400),
+            arrow_down_ms: (ms / 25).clamp(1, 8),
+            arrow_up_ms: (ms / 50).clamp(0, 4),
+            selection_settle_ms: (ms / 2).clamp(5, 60),
+            copy_hold_ms: (ms / 4).clamp(5, 40),
+            clipboard_read_ms: (ms + ms / 2).clamp(10, 250),
+            clipboard_poll_ms: 2,
+            clipboard_poll_attempts: 50,
+        }
+    }
+}
+
+impl Default for TimingProfile {
+    fn default() -> Self {
+        TimingProfile::DEFAULT
+    }
+}
+
+/// Sleep for a profile delay on the active clock
+pub fn sleep_ms(ms: u32) {
+    if ms > 0 {
+        clock::sleep(Duration::from_millis(ms as u64));
+    }
+}
+
+// Fallback for apps without their own profile
+static DEFAULT_PROFILE: Mutex<TimingProfile> = Mutex::new(TimingProfile::DEFAULT);
+
+// Per-application overrides, keyed by application identifier
+static APP_PROFILES: Mutex<BTreeMap<String, TimingProfile>> = Mutex::new(BTreeMap::new());
+
+/// Set the profile for `app_id`, or the fallback profile with None
+pub fn set_profile(app_id: Option<&str>, profile: TimingProfile) {
+    match app_id {
+        Some(app_id) => {
+            APP_PROFILES.lock().unwrap().insert(app_id.to_string(), profile);
+        }
+        None => *DEFAULT_PROFILE.lock().unwrap() = profile,
+    }
+}
+
+/// Forget the profile for `app_id` so it uses the fallback again
+pub fn clear_profile(app_id: &str) {
+    APP_PROFILES.lock().unwrap().remove(app_id);
+}
+
+/// Profile for `app_id`, falling back to its app profile's timing, then the default profile
+pub fn profile_for(app_id: Option<&str>) -> TimingProfile {
+    resolve(app_id, &profile::lookup(&AppIdentity::from_app_id(app_id)))
+}
+
+/// Profile set for `app_id`, else the timing in `app`, else the default profile
+pub fn resolve(app_id: Option<&str>, app: &AppProfile) -> TimingProfile {
+    app_id
+        .and_then(|id| APP_PROFILES.lock().unwrap().get(id).copied())
+        .or(app.timing)
+        .unwrap_or_else(|| *DEFAULT_PROFILE.lock().unwrap())
+}
+
+/// Profile for whichever application currently has focus
+pub fn current() -> TimingProfile {
+    let identity = AppIdentity::current();
+    resolve(identity.app_id(), &profile::lookup(&identity))
+}
+
+// Calibration probe: one character that survives every keyboard layout and IME
+const PROBE: &str = "x";
+const CALIBRATION_ROUNDS: usize = 3;
+const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(2);
+// Graphemes read before the caret; enough that a field already ending in the probe still changes
+const CONTEXT_GRAPHEMES: usize = 8;
+
+/// Measure how quickly the focused field reflects synthetic input and store a tuned profile
+/// The caret must be in an editable field; the probe character is typed and removed again.
+pub fn calibrate(app_id: Option<&str>) -> Result<TimingProfile, SuperspeedError> {
+    let app_id = app_id.map(str::to_owned).or_else(|| focus::current().app_id);
+
+    // Poll aggressively so the measurement reflects the app, not our own delays
+    let probe_timing = TimingProfile::NATIVE;
+    // The probe goes into the focused field, so it pastes the way that app expects
+    let paste = profile::current().paste;
+    let mut worst = Duration::ZERO;
+
+    for round in 0..CALIBRATION_ROUNDS {
+        let latency = measure_round(paste, &probe_timing)?;
+        log_debug!("Calibration round {}: field updated after {:?}", round + 1, latency);
+        worst = worst.max(latency);
+    }
+
+    let profile = TimingProfile::from_latency(worst);
+    log_info!("Calibrated {} (latency {:?}): {:?}", app_id.as_deref().unwrap_or("default"), worst, profile);
+    set_profile(app_id.as_deref(), profile);
+    Ok(profile)
+}
+
+// One measurement; the user's clipboard is put back however it ends
+fn measure_round(paste: Chord, timing: &TimingProfile) -> Result<Duration, SuperspeedError> {
+    let saved = clipboard::with_backend(|pasteboard| pasteboard.snapshot())?;
+    let result = probe_round(paste, timing);
+    let restored = paste::restore_clipboard(&saved);
+    let latency = result?;
+    restored?;
+    Ok(latency)
+}
+
+// Paste the probe, then read back until the text before the caret changed to end with it
+// Each read-back runs our own select/copy delays; their cost, measured on the read taken
+// before pasting, is subtracted so only the app's latency remains.
+fn probe_round(paste: Chord, timing: &TimingProfile) -> Result<Duration, SuperspeedError> {
+    let read_started = clock::now();
+    let before = read_context(timing)?;
+    let read_overhead = clock::now() - read_started;
+
+    // The clipboard snapshot this returns is the one measure_round already holds
+    paste::insert_via_clipboard_and_save(PROBE, ClipboardMarkers::PRIVATE, paste, timing)?;
+    let started = clock::now();
+
+    loop {
+        if probe_landed(&before, &read_context(timing)?) {
+            simulate::backspace(timing)?;
+            return Ok((clock::now() - started).saturating_sub(read_overhead));
+        }
+        if clock::now() - started > CALIBRATION_TIMEOUT {
+            // A paste that shows up late must not stay in the user's text
+            sleep_ms(timing.layout_settle_ms);
+            if probe_landed(&before, &read_context(timing)?) {
+                simulate::backspace(timing)?;
+            }
+            return Err(SuperspeedError::Unsupported(
+                "Calibration timed out: the focused field never showed the probe text".to_string(),
+            ));
+        }
+        sleep_ms(timing.clipboard_poll_ms);
+    }
+}
+
+fn read_context(timing: &TimingProfile) -> Result<String, SuperspeedError> {
+    text_reader::read_cursor_context(CONTEXT_GRAPHEMES, ClipboardMarkers::PRIVATE, timing)
+}
+
+fn probe_landed(before: &str, after: &str) -> bool {
+    after != before && after.ends_with(PROBE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::clipboard::{Clipboard, MemoryClipboard};
+    use crate::sim::{self, FieldBehavior, VirtualField};
+
+    const APP: &str = "test.calibration";
+
+    fn field(behavior: FieldBehavior, text: &str) -> (VirtualField, MemoryClipboard) {
+        sim::install_virtual_clock();
+        let field = VirtualField::with_text(behavior, text);
+        field.install();
+        let mut clipboard = field.clipboard();
+        clipboard.write_string("user copy").unwrap();
+        (field, clipboard)
+    }
+
+    #[test]
+    fn calibration_removes_the_probe_and_restores_the_clipboard() {
+        let _globals = sim::exclusive();
+        // Already ends in the probe character, so only a change counts
+        let (field, clipboard) = field(FieldBehavior::CHAT_BOX, "check the box");
+
+        let profile = calibrate(Some(APP)).unwrap();
+        assert_eq!(profile_for(Some(APP)), profile);
+        clear_profile(APP);
+        assert_eq!(field.text(), "check the box");
+        assert_eq!(clipboard.contents().as_deref(), Some("user copy"));
+    }
+
+    #[test]
+    fn calibration_that_never_sees_the_probe_times_out_cleanly() {
+        let _globals = sim::exclusive();
+        let blocked = FieldBehavior { blocks_paste: true, ..FieldBehavior::CHAT_BOX };
+        let (field, clipboard) = field(blocked, "no paste here");
+
+        assert!(calibrate(Some(APP)).is_err());
+        assert_eq!(profile_for(Some(APP)), TimingProfile::DEFAULT);
+        assert_eq!(field.text(), "no paste here");
+        assert_eq!(clipboard.contents().as_deref(), Some("user copy"));
+    }
+}